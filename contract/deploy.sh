@@ -11,4 +11,17 @@ echo ">> Deploying contracts"
 
 # https://docs.near.org/tools/near-cli#near-dev-deploy
 near dev-deploy --wasmFile ./target/wasm32-unknown-unknown/release/xpnft.wasm
+near dev-deploy --wasmFile ./target/wasm32-unknown-unknown/release/xpmt.wasm
 near dev-deploy --wasmFile ./target/wasm32-unknown-unknown/release/xpbridge.wasm
+
+# The bridge deploys the wrapped collections with the xpnft code uploaded to it.
+# `upload_collection_code` only accepts the code once its hash was set with a
+# signed `validate_set_collection_code`, see Deploy.md.
+XPNFT_WASM=./target/wasm32-unknown-unknown/release/xpnft.wasm
+echo ">> xpnft code hash: $(sha256sum $XPNFT_WASM | cut -d' ' -f1)"
+
+if [ -n "$UPLOAD_COLLECTION_CODE" ]; then
+  echo ">> Uploading the code of the wrapped collections"
+  BRIDGE=$(cat ./neardev/dev-account)
+  near call "$BRIDGE" upload_collection_code "$(base64 -w0 $XPNFT_WASM)" --base64 --accountId "$BRIDGE" --deposit 5 --gas 300000000000000
+fi
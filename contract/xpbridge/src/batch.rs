@@ -0,0 +1,796 @@
+use crate::*;
+use near_sdk::PromiseResult;
+
+/// Maximum number of NFTs that can be frozen with `freeze_nft_batch`.
+pub const MAX_FREEZE_BATCH: usize = 15;
+pub(crate) const GAS_PER_FREEZE_BATCH_ITEM: Gas = Gas(15_000_000_000_000);
+/// Maximum number of NFTs in a `freeze_nft_batch` which registers
+/// collections, so that the metadata lookups and the freeze that
+/// follows them fit in the gas of a single transaction.
+pub const MAX_REGISTERING_FREEZE_BATCH: usize = 6;
+/// Maximum number of actions in `validate_transfer_nft_batch`
+/// and `validate_unfreeze_nft_batch`.
+pub const MAX_VALIDATE_BATCH: usize = 20;
+const GAS_PER_VALIDATE_BATCH_ITEM: Gas = Gas(12_000_000_000_000);
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeBatchItem {
+    pub token_contract: AccountId,
+    pub token_id: TokenId,
+    pub mint_with: String,
+}
+
+/// Freeze of the NFTs of a `freeze_nft_batch`, passed along its callbacks.
+/// `amt` is the fee of the whole batch.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeBatchRequest {
+    pub tokens: Vec<FreezeBatchItem>,
+    pub chain_nonce: u8,
+    pub to: String,
+    pub amt: U128,
+    pub sender: AccountId,
+}
+
+/// Fee signature for `freeze_nft_batch`, signed with the context
+/// "BatchTransferTx". Covers all the (token_contract, token_id) pairs
+/// of the batch with one fee.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchTransferTx {
+    value: u128,
+    from_chain: u8,
+    to_chain: u8,
+    tokens: Vec<(AccountId, TokenId)>,
+    to: String,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Freezes multiple NFTs, possibly from different contracts, on the
+    /// bridge contract with a single fee signature. The NFTs are
+    /// transferred to this bridge contract in parallel and then
+    /// `freeze_batch_callback` emits a TransferNftEvent for every frozen NFT.
+    /// The fee is split evenly between the NFTs and the share of the NFTs
+    /// that failed to transfer is returned to the sender.
+    /// In permissionless mode, the collections which are not whitelisted are
+    /// registered like in `freeze_nft`, for a registration fee each on top
+    /// of the fee. The registration fee of a collection none of whose NFTs
+    /// were frozen is returned to the sender. Such a batch holds at most
+    /// `MAX_REGISTERING_FREEZE_BATCH` NFTs.
+    /// FAILS: If contract is paused, any of the collections is blocked or
+    /// not whitelisted outside of permissionless mode, or the batch is too
+    /// large to register collections.
+    #[payable]
+    pub fn freeze_nft_batch(
+        &mut self,
+        tokens: Vec<FreezeBatchItem>,
+        chain_nonce: u8,
+        to: String,
+        sig_data: Vec<u8>,
+    ) -> Promise {
+        require!(!tokens.is_empty(), "Empty batch");
+        require!(tokens.len() <= MAX_FREEZE_BATCH, "Batch too large");
+        require!(
+            env::prepaid_gas()
+                >= GAS_FOR_FREEZE_NFT + Gas(GAS_PER_FREEZE_BATCH_ITEM.0 * tokens.len() as u64),
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+        let mut unknown: Vec<AccountId> = Vec::new();
+        for item in tokens.iter() {
+            require!(
+                !self.blocked_collections.contains(&item.token_contract),
+                "Collection blocked"
+            );
+            if !self.is_bridgeable(&item.token_contract) && !unknown.contains(&item.token_contract)
+            {
+                unknown.push(item.token_contract.clone());
+            }
+        }
+
+        if !unknown.is_empty() {
+            let amt = self.internal_take_registration_fees(unknown.len());
+            require!(
+                tokens.len() <= MAX_REGISTERING_FREEZE_BATCH,
+                "Batch too large to register collections"
+            );
+            return self.internal_register_collections(
+                unknown,
+                PendingFreeze::Batch {
+                    request: FreezeBatchRequest {
+                        tokens,
+                        chain_nonce,
+                        to,
+                        amt: U128(amt),
+                        sender: env::signer_account_id(),
+                    },
+                    sig_data,
+                },
+            );
+        }
+
+        self.internal_freeze_nft_batch(
+            FreezeBatchRequest {
+                tokens,
+                chain_nonce,
+                to,
+                amt: U128(env::attached_deposit()),
+                sender: env::signer_account_id(),
+            },
+            sig_data,
+            Vec::new(),
+        )
+    }
+
+    #[private]
+    pub fn check_enough_fees_callback_for_batch(
+        &mut self,
+        request: FreezeBatchRequest,
+        registrations: Vec<PendingRegistration>,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => {
+                // The payouts are joined after the transfers, so the result
+                // of the payout of the i-th NFT is at index len + i.
+                let transfers = request
+                    .tokens
+                    .iter()
+                    .map(|t| {
+                        common_nft::ext(t.token_contract.clone())
+                            .with_attached_deposit(1)
+                            .with_static_gas(Gas(TGAS * 10))
+                            .nft_transfer(env::current_account_id(), t.token_id.clone(), None, None)
+                    })
+                    .chain(
+                        request
+                            .tokens
+                            .iter()
+                            .map(|t| nft_payout(t.token_contract.clone(), t.token_id.clone())),
+                    )
+                    .reduce(|acc, p| acc.and(p))
+                    .unwrap();
+
+                let callback_gas =
+                    Gas(TGAS * 13 + TGAS * 2 * (request.tokens.len() + registrations.len()) as u64);
+                transfers.then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(callback_gas)
+                        .freeze_batch_callback(request, registrations),
+                );
+            }
+            Err(e) => {
+                let fees = self.internal_settle_registrations(registrations, |_| false);
+                self.internal_refund(request.sender, request.amt.0 + fees);
+                env::log_str(&format!(
+                    "freeze batch callback: failed to transfer nfts: failed to verify tx fee :actionid: {} : {:?}",
+                    self.action_cnt, e
+                ))
+            }
+        }
+    }
+
+    /// This is the callback function when the promises in the
+    /// check_enough_fees_callback_for_batch function are completed.
+    /// Emits a TransferNftEvent with the royalties on NEAR for every NFT
+    /// that was transferred, records the collections registered by the
+    /// batch and returns the fee share of the failed ones to the sender.
+    #[private]
+    pub fn freeze_batch_callback(
+        &mut self,
+        request: FreezeBatchRequest,
+        registrations: Vec<PendingRegistration>,
+    ) {
+        let FreezeBatchRequest {
+            tokens,
+            chain_nonce,
+            to,
+            amt,
+            sender,
+        } = request;
+        let amt = amt.0;
+        let count = tokens.len() as u128;
+        let share = amt / count;
+        let mut refund = 0;
+        let mut frozen: Vec<AccountId> = Vec::new();
+
+        for (i, item) in tokens.into_iter().enumerate() {
+            // The rounding remainder of the fee goes with the first NFT.
+            let item_amt = if i == 0 {
+                amt - share * (count - 1)
+            } else {
+                share
+            };
+
+            match env::promise_result(i as u64) {
+                PromiseResult::Successful(_) => {
+                    let payout = payout_from_result(env::promise_result(count as u64 + i as u64));
+                    let royalty = royalty_from_payout(payout);
+                    self.action_cnt += 1;
+                    self.tx_fees += item_amt;
+                    frozen.push(item.token_contract.clone());
+                    self.internal_record_freeze(
+                        self.action_cnt,
+                        FreezeRequest {
+                            token_contract: item.token_contract.clone(),
+                            token_id: item.token_id.clone(),
+                            chain_nonce,
+                            to: to.clone(),
+                            mint_with: item.mint_with.clone(),
+                            amt: U128(item_amt),
+                            sender: sender.clone(),
+                        },
+                        None,
+                    );
+
+                    TransferNftEvent {
+                        action_id: self.action_cnt,
+                        chain_nonce,
+                        to: to.clone(),
+                        amt: item_amt,
+                        contract: item.token_contract,
+                        token_id: item.token_id,
+                        mint_with: item.mint_with,
+                        fee_token: None,
+                        royalty,
+                        quantity: None,
+                    }
+                    .emit();
+                }
+                _ => {
+                    refund += item_amt;
+                    env::log_str(&format!(
+                        "freeze batch callback: failed to transfer nft: contract: {} : token id: {}",
+                        item.token_contract, item.token_id
+                    ))
+                }
+            }
+        }
+
+        refund += self.internal_settle_registrations(registrations, |c| frozen.contains(c));
+        if refund > 0 {
+            self.internal_refund(sender, refund);
+        }
+    }
+
+    /// Validates multiple transfers of NFTs from the bridge to the destination
+    /// chain. Every entry mints a new NEP-171 token like `validate_transfer_nft`,
+    /// with the attached deposit split evenly between the mints and what is
+    /// left of the split given to the last one. If no deposit is attached,
+    /// every mint is paid from the storage pool of the sponsor of its
+    /// collection. The deposits of the mints which fail or are cancelled
+    /// are returned to the caller. The whole batch is covered by a single signature.
+    /// The transfers above the rate limits of their chain or collection are
+    /// queued like in `validate_transfer_nft`.
+    /// FAILS: If contract is paused, any of the action ids was already consumed,
+    /// any `mint_with` doesn't match the collection registered for its origin
+    /// or any metadata hash is not a sha256 hash.
+    /// REQUIRED: Signature verification.
+    #[payable]
+    pub fn validate_transfer_nft_batch(
+        &mut self,
+        data: Vec<TransferNftData>,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
+        require!(!data.is_empty(), "Empty batch");
+        require!(data.len() <= MAX_VALIDATE_BATCH, "Batch too large");
+        require!(
+            env::prepaid_gas()
+                >= GAS_FOR_VALIDATE_TRANSFER
+                    + Gas(GAS_PER_VALIDATE_BATCH_ITEM.0 * data.len() as u64),
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+
+        self.require_transfer_sig(
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateTransferNftBatch",
+        );
+        for transfer in data.iter() {
+            self.consume_action(transfer.chain_nonce, transfer.action_id.0);
+            self.require_collection_origin(transfer);
+            require_valid_token_metadata(&transfer.token_metadata);
+        }
+
+        let sender = env::predecessor_account_id();
+        let share = env::attached_deposit() / data.len() as u128;
+        let remainder = env::attached_deposit() % data.len() as u128;
+        let last = data.len() - 1;
+        let mut deposits: Vec<(u128, Option<AccountId>)> = Vec::new();
+        let mut transfers: Vec<TransferNftData> = Vec::new();
+        for (i, transfer) in data.into_iter().enumerate() {
+            let attached = if i == last { share + remainder } else { share };
+            let (deposit, sponsor) = self.internal_mint_deposit(&transfer.mint_with, attached);
+            if self
+                .rate_limits
+                .try_acquire(transfer.chain_nonce, &transfer.mint_with)
+            {
+                transfers.push(transfer);
+                deposits.push((deposit, sponsor));
+            } else {
+                self.internal_queue_action(
+                    transfer.chain_nonce,
+                    transfer.action_id.0,
+                    transfer.mint_with.clone(),
+                    QueuedBridgeAction::TransferNft {
+                        data: Box::new(transfer),
+                        deposit: U128(deposit),
+                        sponsor,
+                        sender: sender.clone(),
+                    },
+                );
+            }
+        }
+        if transfers.is_empty() {
+            return PromiseOrValue::Value(());
+        }
+
+        let data = transfers;
+        let events: Vec<TransferValidatedEvent> =
+            data.iter().map(TransferNftData::validated_event).collect();
+        let callback_gas = Gas(TGAS * 13 + TGAS * 2 * data.len() as u64);
+
+        data.into_iter()
+            .zip(deposits.iter().cloned())
+            .map(|(t, (deposit, sponsor))| {
+                let origin = t.origin();
+                xpnft::ext(t.mint_with)
+                    .with_attached_deposit(deposit)
+                    .with_static_gas(Gas(TGAS * 10))
+                    .nft_mint(
+                        t.token_id,
+                        t.owner_id,
+                        t.token_metadata,
+                        t.royalty,
+                        Some(origin),
+                        sponsor,
+                    )
+            })
+            .reduce(|acc, p| acc.and(p))
+            .unwrap()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(callback_gas)
+                    .validate_transfer_batch_callback(events, deposits, sender),
+            )
+            .into()
+    }
+
+    /// This is the callback function when the promises in the
+    /// validate_transfer_nft_batch function are completed. Emits the
+    /// events of the successful mints and releases the action ids of
+    /// the mints that failed, returning their deposits to the sponsor
+    /// or the sender which paid them. The deposits of the sender are
+    /// returned with a single refund.
+    #[private]
+    pub fn validate_transfer_batch_callback(
+        &mut self,
+        events: Vec<TransferValidatedEvent>,
+        deposits: Vec<(u128, Option<AccountId>)>,
+        sender: AccountId,
+    ) {
+        let mut refund = 0;
+        for (i, (event, (deposit, sponsor))) in events.into_iter().zip(deposits).enumerate() {
+            match env::promise_result(i as u64) {
+                PromiseResult::Successful(_) => event.emit(),
+                _ => {
+                    self.consumed_actions
+                        .release(event.chain_nonce, event.action_id);
+                    match sponsor {
+                        Some(_) => {
+                            self.internal_refund_mint_deposit(sponsor, sender.clone(), deposit)
+                        }
+                        None => refund += deposit,
+                    }
+                    env::log_str(&format!(
+                        "validate transfer batch callback: failed to mint nft: actionid: {}",
+                        event.action_id
+                    ))
+                }
+            }
+        }
+        if refund > 0 {
+            self.internal_refund(sender, refund);
+        }
+    }
+
+    /// Unfreezes multiple NFTs on the bridge contract like
+    /// `validate_unfreeze_nft`. The whole batch is covered by a
+    /// single signature. The escrow records of the NFTs are removed once
+    /// they are transferred.
+    /// The unfreezes above the rate limits of their chain or collection are
+    /// queued like in `validate_unfreeze_nft`.
+    /// FAILS: If contract is paused, any of the contracts is not whitelisted
+    /// or any of the action ids was already consumed.
+    /// REQUIRED: Signature verification.
+    pub fn validate_unfreeze_nft_batch(
+        &mut self,
+        data: Vec<UnfreezeNftData>,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
+        require!(!data.is_empty(), "Empty batch");
+        require!(data.len() <= MAX_VALIDATE_BATCH, "Batch too large");
+        require!(
+            env::prepaid_gas()
+                >= GAS_FOR_VALIDATE_UNFREEZE
+                    + Gas(GAS_PER_VALIDATE_BATCH_ITEM.0 * data.len() as u64),
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+
+        for unfreeze in data.iter() {
+            self.require_bridgeable(&unfreeze.token_contract);
+        }
+
+        self.require_transfer_sig(
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateUnfreezeNftBatch",
+        );
+        for unfreeze in data.iter() {
+            self.consume_action(unfreeze.chain_nonce, unfreeze.action_id.0);
+        }
+
+        let mut unfreezes: Vec<UnfreezeNftData> = Vec::new();
+        for unfreeze in data {
+            if self
+                .rate_limits
+                .try_acquire(unfreeze.chain_nonce, &unfreeze.token_contract)
+            {
+                unfreezes.push(unfreeze);
+            } else {
+                self.internal_queue_action(
+                    unfreeze.chain_nonce,
+                    unfreeze.action_id.0,
+                    unfreeze.token_contract.clone(),
+                    QueuedBridgeAction::UnfreezeNft { data: unfreeze },
+                );
+            }
+        }
+        if unfreezes.is_empty() {
+            return PromiseOrValue::Value(());
+        }
+
+        let data = unfreezes;
+        let events: Vec<UnfreezeValidatedEvent> =
+            data.iter().map(UnfreezeNftData::validated_event).collect();
+        let callback_gas = Gas(TGAS * 5 + TGAS * 2 * data.len() as u64);
+
+        data.into_iter()
+            .map(|u| {
+                common_nft::ext(u.token_contract)
+                    .with_static_gas(Gas(TGAS * 10))
+                    .nft_transfer(u.receiver_id, u.token_id, None, None)
+            })
+            .reduce(|acc, p| acc.and(p))
+            .unwrap()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(callback_gas)
+                    .validate_unfreeze_batch_callback(events),
+            )
+            .into()
+    }
+
+    /// This is the callback function when the promises in the
+    /// validate_unfreeze_nft_batch function are completed. Emits the
+    /// events and removes the escrow records of the successful transfers
+    /// and releases the action ids of the transfers that failed.
+    #[private]
+    pub fn validate_unfreeze_batch_callback(&mut self, events: Vec<UnfreezeValidatedEvent>) {
+        for (i, event) in events.into_iter().enumerate() {
+            match env::promise_result(i as u64) {
+                PromiseResult::Successful(_) => {
+                    self.internal_close_freeze(&event.contract, &event.token_id);
+                    event.emit()
+                }
+                _ => {
+                    self.consumed_actions
+                        .release(event.chain_nonce, event.action_id);
+                    env::log_str(&format!(
+                        "validate unfreeze batch callback: failed to transfer nft: action id: {}",
+                        event.action_id
+                    ))
+                }
+            }
+        }
+    }
+
+    #[private]
+    pub fn verify_batch_paid_amount_by_sig(&self, data: BatchTransferTx, sig_data: Vec<u8>) {
+        self.require_fee_sig(data.try_to_vec().unwrap(), sig_data, b"BatchTransferTx");
+    }
+}
+
+impl XpBridge {
+    /// Verifies the fee of a batch and then transfers its NFTs to this
+    /// bridge contract with `check_enough_fees_callback_for_batch`.
+    pub(crate) fn internal_freeze_nft_batch(
+        &mut self,
+        request: FreezeBatchRequest,
+        sig_data: Vec<u8>,
+        registrations: Vec<PendingRegistration>,
+    ) -> Promise {
+        Self::ext(env::current_account_id())
+            .verify_batch_paid_amount_by_sig(
+                BatchTransferTx {
+                    value: request.amt.0,
+                    from_chain: CHAIN_NONCE,
+                    to_chain: request.chain_nonce,
+                    tokens: request
+                        .tokens
+                        .iter()
+                        .map(|t| (t.token_contract.clone(), t.token_id.clone()))
+                        .collect(),
+                    to: request.to.clone(),
+                },
+                sig_data,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 20
+                        + GAS_PER_FREEZE_BATCH_ITEM.0 * request.tokens.len() as u64
+                        + TGAS * 2 * registrations.len() as u64))
+                    .check_enough_fees_callback_for_batch(request, registrations),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn batch() -> Vec<FreezeBatchItem> {
+        ["1", "2", "3"]
+            .iter()
+            .map(|id| FreezeBatchItem {
+                token_contract: account("nft"),
+                token_id: id.to_string(),
+                mint_with: "0xwrapped".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failed_transfers_return_their_fee_share() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+
+        // The second NFT fails to transfer, none of the payouts resolve.
+        set_promise_results(vec![
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+            PromiseResult::Failed,
+            PromiseResult::Failed,
+        ]);
+        bridge.freeze_batch_callback(freeze_batch_request(batch()), Vec::new());
+
+        // The rounding remainder of the fee goes with the first NFT.
+        assert_eq!(bridge.get_action_cnt().0, 2);
+        assert_eq!(bridge.tx_fees, 7);
+        assert_eq!(transferred_to(&account("alice")), 3);
+    }
+
+    #[test]
+    fn failed_fee_check_returns_the_whole_fee() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+
+        set_context(&bridge_id(), 0);
+        bridge.check_enough_fees_callback_for_batch(
+            freeze_batch_request(batch()),
+            Vec::new(),
+            Err(PromiseError::Failed),
+        );
+
+        assert_eq!(bridge.tx_fees, 0);
+        assert_eq!(transferred_to(&account("alice")), 10);
+    }
+
+    #[test]
+    fn batch_fee_signature_covers_the_tokens() {
+        let bridge = setup();
+        let data = BatchTransferTx {
+            value: 10,
+            from_chain: 31,
+            to_chain: 4,
+            tokens: batch()
+                .into_iter()
+                .map(|t| (t.token_contract, t.token_id))
+                .collect(),
+            to: "0xreceiver".to_string(),
+        };
+        let sig = fee_sig(b"BatchTransferTx", &data);
+
+        set_context(&bridge_id(), 0);
+        bridge.verify_batch_paid_amount_by_sig(data, sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Collection not whitelisted")]
+    fn unknown_collections_need_permissionless_mode() {
+        let mut bridge = setup();
+
+        set_context(&account("alice"), 10);
+        bridge.freeze_nft_batch(batch(), 4, "0xreceiver".to_string(), vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch too large")]
+    fn batch_size_is_limited() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+        let tokens = (0..=MAX_FREEZE_BATCH)
+            .map(|i| FreezeBatchItem {
+                token_contract: account("nft"),
+                token_id: i.to_string(),
+                mint_with: "0xwrapped".to_string(),
+            })
+            .collect();
+
+        set_context(&account("alice"), 10);
+        bridge.freeze_nft_batch(tokens, 4, "0xreceiver".to_string(), vec![]);
+    }
+
+    fn permissionless_batch(len: usize, unknown: usize) -> (XpBridge, Vec<FreezeBatchItem>) {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+        bridge.permissionless_mode = true;
+        bridge.registration_fee = 100;
+        let tokens = (0..len)
+            .map(|i| FreezeBatchItem {
+                token_contract: if i < unknown {
+                    account(&format!("nft{}", i))
+                } else {
+                    account("nft")
+                },
+                token_id: i.to_string(),
+                mint_with: "0xwrapped".to_string(),
+            })
+            .collect();
+        (bridge, tokens)
+    }
+
+    #[test]
+    fn full_batches_of_known_collections_fit_the_gas() {
+        let (mut bridge, tokens) = permissionless_batch(MAX_FREEZE_BATCH, 0);
+
+        set_context(&account("alice"), 10);
+        bridge.freeze_nft_batch(tokens, 4, "0xreceiver".to_string(), vec![]);
+    }
+
+    #[test]
+    fn registering_batches_fit_the_gas() {
+        let (mut bridge, tokens) =
+            permissionless_batch(MAX_REGISTERING_FREEZE_BATCH, MAX_REGISTERING_FREEZE_BATCH);
+
+        set_context(
+            &account("alice"),
+            10 + 100 * MAX_REGISTERING_FREEZE_BATCH as u128,
+        );
+        bridge.freeze_nft_batch(tokens, 4, "0xreceiver".to_string(), vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch too large to register collections")]
+    fn full_batches_cannot_register_collections() {
+        let (mut bridge, tokens) = permissionless_batch(MAX_FREEZE_BATCH, 1);
+
+        set_context(&account("alice"), 110);
+        bridge.freeze_nft_batch(tokens, 4, "0xreceiver".to_string(), vec![]);
+    }
+
+    #[test]
+    fn failed_mints_release_their_action_ids() {
+        let mut bridge = setup();
+        let data = vec![transfer_data(1, "1"), transfer_data(2, "2")];
+        let sig = group_sig(b"ValidateTransferNftBatch", &data);
+
+        set_context(&account("relayer"), 21);
+        bridge.validate_transfer_nft_batch(data.clone(), sig);
+        assert!(bridge.consumed_actions.is_consumed(4, 1));
+        assert!(bridge.consumed_actions.is_consumed(4, 2));
+
+        set_promise_results(vec![
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+        ]);
+        // The last mint gets what is left of the split.
+        let events = data.iter().map(TransferNftData::validated_event).collect();
+        bridge.validate_transfer_batch_callback(
+            events,
+            vec![(10, None), (11, None)],
+            account("relayer"),
+        );
+
+        assert!(bridge.consumed_actions.is_consumed(4, 1));
+        assert!(!bridge.consumed_actions.is_consumed(4, 2));
+        assert_eq!(transferred_to(&account("relayer")), 11);
+    }
+
+    #[test]
+    fn transfers_above_the_rate_limit_are_queued() {
+        let mut bridge = setup();
+        bridge.rate_limits.set_chain_limit(4, Some(1));
+        let data = vec![transfer_data(1, "1"), transfer_data(2, "2")];
+        let sig = group_sig(b"ValidateTransferNftBatch", &data);
+
+        set_context(&account("relayer"), 21);
+        bridge.validate_transfer_nft_batch(data, sig);
+
+        // The last transfer gets what is left of the split.
+        let queued = bridge.get_queued_action(U64(1)).unwrap();
+        match queued.action {
+            QueuedBridgeAction::TransferNft { data, deposit, .. } => {
+                assert_eq!(data.action_id.0, 2);
+                assert_eq!(deposit.0, 11);
+            }
+            _ => panic!("Expected a queued transfer"),
+        }
+        assert!(bridge.get_queued_action(U64(2)).is_none());
+
+        let data = CancelQueuedData {
+            action_id: U128(1),
+            queue_id: U64(1),
+        };
+        let sig = group_sig(b"CancelQueuedAction", &data);
+        bridge.validate_cancel_queued_action(data, sig);
+        assert_eq!(transferred_to(&account("relayer")), 11);
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicated Action")]
+    fn batch_action_ids_cannot_be_replayed() {
+        let mut bridge = setup();
+        let data = vec![transfer_data(1, "1"), transfer_data(1, "2")];
+        let sig = group_sig(b"ValidateTransferNftBatch", &data);
+
+        set_context(&account("relayer"), 20);
+        bridge.validate_transfer_nft_batch(data, sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Collection not whitelisted")]
+    fn unfreeze_batch_needs_bridgeable_collections() {
+        let mut bridge = setup();
+        let data = vec![unfreeze_data(1, "1")];
+        let sig = group_sig(b"ValidateUnfreezeNftBatch", &data);
+
+        set_context(&account("relayer"), 0);
+        bridge.validate_unfreeze_nft_batch(data, sig);
+    }
+
+    #[test]
+    fn unfreeze_batch_closes_the_freeze_records() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+        set_promise_results(vec![
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+        ]);
+        bridge.freeze_batch_callback(freeze_batch_request(batch()[..1].to_vec()), Vec::new());
+        assert!(bridge.get_freeze(U128(1)).is_some());
+
+        let data = vec![unfreeze_data(1, "1")];
+        let sig = group_sig(b"ValidateUnfreezeNftBatch", &data);
+        set_context(&account("relayer"), 0);
+        bridge.validate_unfreeze_nft_batch(data, sig);
+        // The record is kept until the NFT is transferred.
+        assert!(bridge.get_freeze(U128(1)).is_some());
+
+        let event = || unfreeze_data(1, "1").validated_event();
+        set_promise_results(vec![PromiseResult::Failed]);
+        bridge.validate_unfreeze_batch_callback(vec![event()]);
+        assert!(bridge.get_freeze(U128(1)).is_some());
+
+        set_promise_results(vec![PromiseResult::Successful(vec![])]);
+        bridge.validate_unfreeze_batch_callback(vec![event()]);
+        assert!(bridge.get_freeze(U128(1)).is_none());
+    }
+}
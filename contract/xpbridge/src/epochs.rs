@@ -0,0 +1,251 @@
+use crate::*;
+
+/// Default number of blocks during which the previous group key
+/// is still accepted after a rotation (about a day).
+pub const DEFAULT_KEY_GRACE_PERIOD: u64 = 86_400;
+/// Max grace period in blocks (about a week), so a rotation away from
+/// a leaked key takes effect in a bounded time.
+pub const MAX_KEY_GRACE_PERIOD: u64 = 604_800;
+
+/// A group key with the block heights it is valid between.
+/// The current epoch has no expiry.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GroupKeyEpoch {
+    pub group_key: [u8; 32],
+    pub activated_at: U64,
+    pub expires_at: Option<U64>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct KeyGracePeriodData {
+    pub action_id: U128,
+    pub grace_period: U64,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Sets the number of blocks during which the previous
+    /// group key is still accepted after a rotation.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused or the grace period is above
+    /// `MAX_KEY_GRACE_PERIOD`.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_key_grace_period(&mut self, data: KeyGracePeriodData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+        require!(
+            data.grace_period.0 <= MAX_KEY_GRACE_PERIOD,
+            "Grace period too long"
+        );
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetKeyGracePeriod",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetKeyGracePeriod {
+                grace_period: data.grace_period,
+            },
+        );
+    }
+
+    /// Gets the group key epoch with the provided index.
+    pub fn get_group_key_epoch(&self, epoch: u32) -> Option<GroupKeyEpoch> {
+        self.group_key_epochs.get(epoch as u64)
+    }
+
+    /// Gets the index of the epoch of the current group key.
+    pub fn get_current_epoch(&self) -> u32 {
+        (self.group_key_epochs.len() - 1) as u32
+    }
+
+    /// Gets the number of blocks during which the previous
+    /// group key is still accepted after a rotation.
+    pub fn get_key_grace_period(&self) -> U64 {
+        U64(self.key_grace_period)
+    }
+}
+
+impl XpBridge {
+    /// Makes `group_key` the key of a new epoch. The previous key stays
+    /// valid for the grace period, any older key expires immediately.
+    pub(crate) fn internal_rotate_group_key(&mut self, group_key: [u8; 32]) {
+        let height = env::block_height();
+        let len = self.group_key_epochs.len();
+        if len > 1 {
+            let mut older = self.group_key_epochs.get(len - 2).unwrap();
+            if older.expires_at.map_or(true, |e| e.0 > height) {
+                older.expires_at = Some(U64(height));
+                self.group_key_epochs.replace(len - 2, &older);
+            }
+        }
+        let mut previous = self.group_key_epochs.get(len - 1).unwrap();
+        previous.expires_at = Some(U64(height + self.key_grace_period));
+        self.group_key_epochs.replace(len - 1, &previous);
+
+        self.group_key_epochs.push(&GroupKeyEpoch {
+            group_key,
+            activated_at: U64(height),
+            expires_at: None,
+        });
+        self.group_key = group_key;
+    }
+
+    /// Gets the group key a signature was made with. A 64 byte signature
+    /// uses the current key, a 68 byte one is prefixed with the little
+    /// endian u32 index of the epoch it was signed in. The previous epoch
+    /// is only accepted during its grace period if `grace` is set.
+    /// FAILS: If the epoch doesn't exist or is expired.
+    pub(crate) fn signing_group_key<'a>(
+        &self,
+        sig_data: &'a [u8],
+        grace: bool,
+    ) -> ([u8; 32], &'a [u8]) {
+        if sig_data.len() != 68 {
+            return (self.group_key, sig_data);
+        }
+
+        let (epoch, sig) = sig_data.split_at(4);
+        let epoch = u32::from_le_bytes(epoch.try_into().unwrap());
+        let key_epoch = self
+            .group_key_epochs
+            .get(epoch as u64)
+            .unwrap_or_else(|| env::panic_str("Unknown epoch"));
+        require!(
+            key_epoch
+                .expires_at
+                .map_or(true, |e| e.0 > env::block_height()),
+            "Epoch expired"
+        );
+        require!(
+            grace || key_epoch.expires_at.is_none(),
+            "Previous epoch not accepted"
+        );
+        (key_epoch.group_key, sig)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    /// Rotates to the next group key at block 10.
+    fn setup_rotated() -> XpBridge {
+        let mut bridge = setup();
+        let data = UpdateGroupkeyData {
+            action_id: U128(1),
+            group_key: next_group_keypair().public.to_bytes(),
+        };
+        let sig = group_sig(b"SetGroupKey", &data);
+        testing_env!(context(&bridge_id(), 0).block_index(10).build());
+        bridge.validate_update_group_key(data, sig);
+        bridge
+    }
+
+    /// Signs with the key of the first epoch, prefixed with its index.
+    fn previous_epoch_sig<T: BorshSerialize>(context: &[u8], data: &T) -> Vec<u8> {
+        [&0u32.to_le_bytes()[..], &group_sig(context, data)].concat()
+    }
+
+    fn at_height(height: u64) {
+        testing_env!(context(&account("relayer"), 10).block_index(height).build());
+    }
+
+    #[test]
+    fn rotation_starts_a_new_epoch() {
+        let bridge = setup_rotated();
+
+        assert_eq!(bridge.get_current_epoch(), 1);
+        let previous = bridge.get_group_key_epoch(0).unwrap();
+        assert_eq!(
+            previous.expires_at.unwrap().0,
+            10 + DEFAULT_KEY_GRACE_PERIOD
+        );
+        let current = bridge.get_group_key_epoch(1).unwrap();
+        assert!(current.group_key == next_group_keypair().public.to_bytes());
+        assert!(current.expires_at.is_none());
+    }
+
+    #[test]
+    fn rotations_use_the_grace_period_set() {
+        let mut bridge = setup();
+        let data = KeyGracePeriodData {
+            action_id: U128(1),
+            grace_period: U64(100),
+        };
+        let sig = group_sig(b"SetKeyGracePeriod", &data);
+        bridge.validate_set_key_grace_period(data, sig);
+
+        bridge.internal_rotate_group_key(next_group_keypair().public.to_bytes());
+        assert_eq!(
+            bridge.get_group_key_epoch(0).unwrap().expires_at.unwrap().0,
+            100
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Grace period too long")]
+    fn grace_period_is_bounded() {
+        let mut bridge = setup();
+        let data = KeyGracePeriodData {
+            action_id: U128(1),
+            grace_period: U64(MAX_KEY_GRACE_PERIOD + 1),
+        };
+        let sig = group_sig(b"SetKeyGracePeriod", &data);
+        bridge.validate_set_key_grace_period(data, sig);
+    }
+
+    #[test]
+    fn transfers_accept_the_previous_key_during_the_grace_period() {
+        let mut bridge = setup_rotated();
+        let data = transfer_data(1, "1");
+        let sig = previous_epoch_sig(b"ValidateTransferNft", &data);
+
+        at_height(10 + DEFAULT_KEY_GRACE_PERIOD - 1);
+        bridge.validate_transfer_nft(data, sig);
+        assert!(bridge.is_action_consumed(4, U128(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Epoch expired")]
+    fn previous_key_expires_after_the_grace_period() {
+        let mut bridge = setup_rotated();
+        let data = transfer_data(1, "1");
+        let sig = previous_epoch_sig(b"ValidateTransferNft", &data);
+
+        at_height(10 + DEFAULT_KEY_GRACE_PERIOD);
+        bridge.validate_transfer_nft(data, sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Previous epoch not accepted")]
+    fn admin_actions_need_the_current_key() {
+        let mut bridge = setup_rotated();
+        let data = KeyGracePeriodData {
+            action_id: U128(2),
+            grace_period: U64(0),
+        };
+        let sig = previous_epoch_sig(b"SetKeyGracePeriod", &data);
+
+        at_height(11);
+        bridge.validate_set_key_grace_period(data, sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized Action")]
+    fn unprefixed_signatures_use_the_current_key() {
+        let mut bridge = setup_rotated();
+        let data = transfer_data(1, "1");
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        at_height(11);
+        bridge.validate_transfer_nft(data, sig);
+    }
+}
@@ -0,0 +1,586 @@
+use crate::*;
+
+const GAS_FOR_CANCEL_FREEZE: Gas = Gas(40_000_000_000_000);
+const GAS_FOR_RECLAIM_NFT: Gas = Gas(30_000_000_000_000);
+
+/// An NFT held by the bridge for an outgoing transfer,
+/// keyed by the action id of its TransferNftEvent.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeRecord {
+    pub sender: AccountId,
+    pub token_contract: AccountId,
+    pub token_id: TokenId,
+    pub chain_nonce: u8,
+    pub to: String,
+    pub mint_with: String,
+    pub fee: U128,
+    pub fee_token: Option<AccountId>,
+    pub cancel_requested: bool,
+    pub frozen_at: U64,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CancelFreezeData {
+    pub action_id: U128,
+    pub freeze_id: U128,
+    pub refund_fee: bool,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DeliveryTimeoutData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub timeout: Option<U64>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DeliveryAckData {
+    pub action_id: U128,
+    pub freeze_ids: Vec<U128>,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Requests the cancellation of the freeze with the provided action id.
+    /// The NFT is returned once validators confirm with `validate_cancel_freeze`.
+    /// FAILS: If the caller is not the sender of the freeze or
+    /// the cancellation was already requested.
+    pub fn request_cancel(&mut self, action_id: U128) {
+        let mut record = self
+            .freezes
+            .get(&action_id.0)
+            .unwrap_or_else(|| env::panic_str("Unknown freeze"));
+        require!(
+            record.sender == env::predecessor_account_id(),
+            "Unauthorized"
+        );
+        require!(!record.cancel_requested, "Cancellation already requested");
+
+        record.cancel_requested = true;
+        self.freezes.insert(&action_id.0, &record);
+
+        CancelRequestedEvent {
+            action_id: action_id.0,
+            sender: record.sender,
+        }
+        .emit();
+    }
+
+    /// Cancels a freeze whose cancellation was requested by its sender,
+    /// returning the NFT and, if `refund_fee` is set, the fee paid for it.
+    /// FAILS: If contract is paused, the cancellation was not requested
+    /// or the fee to refund was already withdrawn.
+    /// REQUIRED: Signature verification.
+    pub fn validate_cancel_freeze(&mut self, data: CancelFreezeData, sig_data: Vec<u8>) -> Promise {
+        require!(
+            env::prepaid_gas() >= GAS_FOR_CANCEL_FREEZE,
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+
+        let record = self
+            .freezes
+            .get(&data.freeze_id.0)
+            .unwrap_or_else(|| env::panic_str("Unknown freeze"));
+        require!(record.cancel_requested, "Cancellation not requested");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"CancelFreeze",
+        );
+
+        self.internal_remove_freeze(data.freeze_id.0);
+        let fee = if data.refund_fee {
+            self.internal_reserve_fee(&record.fee_token, record.fee.0);
+            record.fee.0
+        } else {
+            0
+        };
+
+        common_nft::ext(record.token_contract.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_transfer(record.sender.clone(), record.token_id.clone(), None, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 20))
+                    .cancel_freeze_callback(data.action_id.0, data.freeze_id.0, record, fee),
+            )
+    }
+
+    /// This is the callback function when the promise in the
+    /// validate_cancel_freeze function is completed. Refunds the fee if
+    /// the NFT was returned, otherwise restores the freeze record.
+    #[private]
+    pub fn cancel_freeze_callback(
+        &mut self,
+        action_id: u128,
+        freeze_id: u128,
+        record: FreezeRecord,
+        fee: u128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => {
+                if fee > 0 {
+                    match &record.fee_token {
+                        Some(token) => {
+                            self.internal_refund_ft(record.sender.clone(), token.clone(), fee);
+                        }
+                        None => {
+                            self.internal_refund(record.sender.clone(), fee);
+                        }
+                    }
+                }
+
+                FreezeCancelledEvent {
+                    action_id: freeze_id,
+                    chain_nonce: record.chain_nonce,
+                    contract: record.token_contract,
+                    token_id: record.token_id,
+                    sender: record.sender,
+                    fee_refunded: fee,
+                    fee_token: record.fee_token,
+                }
+                .emit();
+            }
+            Err(e) => {
+                self.consumed_actions.release(CHAIN_NONCE, action_id);
+                self.internal_insert_freeze(freeze_id, &record);
+                if fee > 0 {
+                    self.internal_restore_fee(&record.fee_token, fee);
+                }
+                env::log_str(&format!(
+                    "cancel freeze callback: failed to return nft: actionid: {} : {:?}",
+                    freeze_id, e
+                ))
+            }
+        }
+    }
+
+    /// Sets the time in nanoseconds after which the sender of a freeze to
+    /// `chain_nonce` which was not acknowledged as delivered can reclaim the
+    /// NFT with `reclaim_nft`. A `timeout` of None disables reclaiming.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_delivery_timeout(&mut self, data: DeliveryTimeoutData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetDeliveryTimeout",
+        );
+
+        match data.timeout {
+            Some(timeout) => self.delivery_timeouts.insert(&data.chain_nonce, &timeout.0),
+            None => self.delivery_timeouts.remove(&data.chain_nonce),
+        };
+    }
+
+    /// Acknowledges that the NFTs of the freezes with the provided action ids
+    /// were minted on the destination chain. Their escrow records are removed,
+    /// so they can't be cancelled or reclaimed anymore.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_ack_delivery(&mut self, data: DeliveryAckData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"AckDelivery",
+        );
+
+        for freeze_id in data.freeze_ids {
+            self.internal_remove_freeze(freeze_id.0);
+        }
+    }
+
+    /// Returns the NFT of a freeze which was not acknowledged as delivered
+    /// within the delivery timeout of its destination chain to its sender.
+    /// The fee is not refunded.
+    /// FAILS: If contract is paused, the caller is not the sender of the
+    /// freeze or the delivery timeout didn't pass.
+    pub fn reclaim_nft(&mut self, action_id: U128) -> Promise {
+        require!(env::prepaid_gas() >= GAS_FOR_RECLAIM_NFT, "Not enough gas");
+        require!(!self.paused, "paused");
+
+        let record = self
+            .freezes
+            .get(&action_id.0)
+            .unwrap_or_else(|| env::panic_str("Unknown freeze"));
+        require!(
+            record.sender == env::predecessor_account_id(),
+            "Unauthorized"
+        );
+        let timeout = self
+            .delivery_timeouts
+            .get(&record.chain_nonce)
+            .unwrap_or_else(|| env::panic_str("No delivery timeout for chain"));
+        require!(
+            record.frozen_at.0.saturating_add(timeout) <= env::block_timestamp(),
+            "Delivery timeout not reached"
+        );
+
+        self.internal_remove_freeze(action_id.0);
+
+        common_nft::ext(record.token_contract.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_transfer(record.sender.clone(), record.token_id.clone(), None, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 8))
+                    .reclaim_nft_callback(action_id.0, record),
+            )
+    }
+
+    /// This is the callback function when the promise in the reclaim_nft
+    /// function is completed. Restores the freeze record if the NFT
+    /// couldn't be returned.
+    #[private]
+    pub fn reclaim_nft_callback(
+        &mut self,
+        freeze_id: u128,
+        record: FreezeRecord,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => FreezeReclaimedEvent {
+                action_id: freeze_id,
+                chain_nonce: record.chain_nonce,
+                contract: record.token_contract,
+                token_id: record.token_id,
+                sender: record.sender,
+            }
+            .emit(),
+            Err(e) => {
+                self.internal_insert_freeze(freeze_id, &record);
+                env::log_str(&format!(
+                    "reclaim nft callback: failed to return nft: actionid: {} : {:?}",
+                    freeze_id, e
+                ))
+            }
+        }
+    }
+
+    /// Gets the delivery timeout of the chain in nanoseconds.
+    pub fn get_delivery_timeout(&self, chain_nonce: u8) -> Option<U64> {
+        self.delivery_timeouts.get(&chain_nonce).map(U64)
+    }
+
+    /// Gets the escrow record of the freeze with the provided action id.
+    pub fn get_freeze(&self, action_id: U128) -> Option<FreezeRecord> {
+        self.freezes.get(&action_id.0)
+    }
+}
+
+impl XpBridge {
+    /// Records the NFT of a freeze under the action id of its TransferNftEvent.
+    /// An NFT can only be held once, so an open record of an earlier freeze
+    /// of the same NFT is removed.
+    pub(crate) fn internal_record_freeze(
+        &mut self,
+        action_id: u128,
+        request: FreezeRequest,
+        fee_token: Option<AccountId>,
+    ) {
+        self.internal_close_freeze(&request.token_contract, &request.token_id);
+        self.internal_insert_freeze(
+            action_id,
+            &FreezeRecord {
+                sender: request.sender,
+                token_contract: request.token_contract,
+                token_id: request.token_id,
+                chain_nonce: request.chain_nonce,
+                to: request.to,
+                mint_with: request.mint_with,
+                fee: request.amt,
+                fee_token,
+                cancel_requested: false,
+                frozen_at: U64(env::block_timestamp()),
+            },
+        );
+    }
+
+    /// Removes the open escrow record of the NFT, if any. Called when the
+    /// NFT is unfrozen, as it is no longer held for its earlier sender.
+    pub(crate) fn internal_close_freeze(&mut self, token_contract: &AccountId, token_id: &TokenId) {
+        if let Some(freeze_id) = self
+            .frozen_tokens
+            .remove(&(token_contract.clone(), token_id.clone()))
+        {
+            self.freezes.remove(&freeze_id);
+        }
+    }
+
+    fn internal_insert_freeze(&mut self, action_id: u128, record: &FreezeRecord) {
+        self.freezes.insert(&action_id, record);
+        self.frozen_tokens.insert(
+            &(record.token_contract.clone(), record.token_id.clone()),
+            &action_id,
+        );
+    }
+
+    fn internal_remove_freeze(&mut self, action_id: u128) {
+        if let Some(record) = self.freezes.remove(&action_id) {
+            self.frozen_tokens
+                .remove(&(record.token_contract, record.token_id));
+        }
+    }
+
+    /// Takes `amt` out of the collected fees so it can be refunded.
+    fn internal_reserve_fee(&mut self, fee_token: &Option<AccountId>, amt: u128) {
+        match fee_token {
+            Some(token) => {
+                let collected = self.ft_fees.get(token).unwrap_or(0);
+                require!(collected >= amt, "Fees already withdrawn");
+                self.ft_fees.insert(token, &(collected - amt));
+            }
+            None => {
+                require!(self.tx_fees >= amt, "Fees already withdrawn");
+                self.tx_fees -= amt;
+            }
+        }
+    }
+
+    fn internal_restore_fee(&mut self, fee_token: &Option<AccountId>, amt: u128) {
+        match fee_token {
+            Some(token) => {
+                let collected = self.ft_fees.get(token).unwrap_or(0);
+                self.ft_fees.insert(token, &(collected + amt));
+            }
+            None => self.tx_fees += amt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    /// Records the freeze 1 of NFT "1" by alice, who paid a fee of 10.
+    fn setup_freeze() -> XpBridge {
+        let mut bridge = setup();
+        bridge.tx_fees += 10;
+        bridge.internal_record_freeze(1, freeze_request("alice"), None);
+        bridge
+    }
+
+    fn setup_record() -> FreezeRecord {
+        FreezeRecord {
+            sender: account("alice"),
+            token_contract: account("nft"),
+            token_id: "1".to_string(),
+            chain_nonce: 4,
+            to: "0xreceiver".to_string(),
+            mint_with: "0xwrapped".to_string(),
+            fee: U128(10),
+            fee_token: None,
+            cancel_requested: false,
+            frozen_at: U64(0),
+        }
+    }
+
+    fn cancel_data(refund_fee: bool) -> (CancelFreezeData, Vec<u8>) {
+        let data = CancelFreezeData {
+            action_id: U128(1),
+            freeze_id: U128(1),
+            refund_fee,
+        };
+        let sig = group_sig(b"CancelFreeze", &data);
+        (data, sig)
+    }
+
+    fn set_timeout(bridge: &mut XpBridge, timeout: u64) {
+        let data = DeliveryTimeoutData {
+            action_id: U128(1),
+            chain_nonce: 4,
+            timeout: Some(U64(timeout)),
+        };
+        let sig = group_sig(b"SetDeliveryTimeout", &data);
+        bridge.validate_set_delivery_timeout(data, sig);
+    }
+
+    #[test]
+    fn cancelled_freeze_returns_the_fee() {
+        let mut bridge = setup_freeze();
+
+        set_context(&account("alice"), 0);
+        bridge.request_cancel(U128(1));
+        assert!(bridge.get_freeze(U128(1)).unwrap().cancel_requested);
+
+        let (data, sig) = cancel_data(true);
+        bridge.validate_cancel_freeze(data, sig);
+        assert!(bridge.get_freeze(U128(1)).is_none());
+        assert_eq!(bridge.tx_fees, 0);
+
+        let record = FreezeRecord {
+            cancel_requested: true,
+            ..setup_record()
+        };
+        set_context(&bridge_id(), 0);
+        bridge.cancel_freeze_callback(1, 1, record, 10, Ok(()));
+        assert_eq!(transferred_to(&account("alice")), 10);
+    }
+
+    #[test]
+    fn failed_cancel_restores_the_freeze() {
+        let mut bridge = setup_freeze();
+        set_context(&account("alice"), 0);
+        bridge.request_cancel(U128(1));
+        let (data, sig) = cancel_data(true);
+        bridge.validate_cancel_freeze(data, sig);
+
+        set_context(&bridge_id(), 0);
+        bridge.cancel_freeze_callback(1, 1, setup_record(), 10, Err(PromiseError::Failed));
+
+        assert!(bridge.get_freeze(U128(1)).is_some());
+        assert_eq!(bridge.tx_fees, 10);
+        assert!(!bridge.is_action_consumed(CHAIN_NONCE, U128(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_sender_can_request_a_cancel() {
+        let mut bridge = setup_freeze();
+
+        set_context(&account("mallory"), 0);
+        bridge.request_cancel(U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cancellation not requested")]
+    fn cancel_must_be_requested_by_the_sender() {
+        let mut bridge = setup_freeze();
+
+        let (data, sig) = cancel_data(false);
+        bridge.validate_cancel_freeze(data, sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fees already withdrawn")]
+    fn withdrawn_fees_cannot_be_refunded() {
+        let mut bridge = setup_freeze();
+        bridge.tx_fees = 0;
+
+        set_context(&account("alice"), 0);
+        bridge.request_cancel(U128(1));
+        let (data, sig) = cancel_data(true);
+        bridge.validate_cancel_freeze(data, sig);
+    }
+
+    #[test]
+    fn unfrozen_nfts_leave_no_stale_record() {
+        let mut bridge = setup_freeze();
+
+        // The NFT comes back to alice and is frozen again by bob.
+        bridge.internal_close_freeze(&account("nft"), &"1".to_string());
+        assert!(bridge.get_freeze(U128(1)).is_none());
+        bridge.internal_record_freeze(2, freeze_request("bob"), None);
+        // A freeze which was never unfrozen is replaced as well.
+        bridge.internal_record_freeze(3, freeze_request("carol"), None);
+
+        assert!(bridge.get_freeze(U128(2)).is_none());
+        assert_eq!(bridge.get_freeze(U128(3)).unwrap().sender, account("carol"));
+    }
+
+    #[test]
+    fn failed_unfreezes_keep_the_freeze_record() {
+        let mut bridge = setup_freeze();
+        let event = || unfreeze_data(2, "1").validated_event();
+
+        set_context(&bridge_id(), 0);
+        bridge.validate_unfreeze_callback(event(), Err(PromiseError::Failed));
+        assert!(bridge.get_freeze(U128(1)).is_some());
+
+        bridge.validate_unfreeze_callback(event(), Ok(()));
+        assert!(bridge.get_freeze(U128(1)).is_none());
+    }
+
+    #[test]
+    fn undelivered_nfts_can_be_reclaimed_after_the_timeout() {
+        let mut bridge = setup_freeze();
+        set_timeout(&mut bridge, 100);
+        assert_eq!(bridge.get_delivery_timeout(4).unwrap().0, 100);
+
+        testing_env!(context(&account("alice"), 0).block_timestamp(100).build());
+        bridge.reclaim_nft(U128(1));
+        assert!(bridge.get_freeze(U128(1)).is_none());
+        // The fee is kept.
+        assert_eq!(bridge.tx_fees, 10);
+
+        set_context(&bridge_id(), 0);
+        bridge.reclaim_nft_callback(1, setup_record(), Err(PromiseError::Failed));
+        assert!(bridge.get_freeze(U128(1)).is_some());
+    }
+
+    #[test]
+    fn cancelled_unfreezes_keep_the_nft_reclaimable() {
+        let mut bridge = setup_freeze();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+        bridge.rate_limits.set_chain_limit(4, Some(0));
+        set_timeout(&mut bridge, 0);
+
+        let data = unfreeze_data(2, "1");
+        let sig = group_sig(b"ValidateUnfreezeNft", &data);
+        bridge.validate_unfreeze_nft(data, sig);
+        assert!(bridge.get_queued_action(U64(1)).is_some());
+        assert!(bridge.get_freeze(U128(1)).is_some());
+
+        let data = CancelQueuedData {
+            action_id: U128(3),
+            queue_id: U64(1),
+        };
+        let sig = group_sig(b"CancelQueuedAction", &data);
+        bridge.validate_cancel_queued_action(data, sig);
+
+        set_context(&account("alice"), 0);
+        bridge.reclaim_nft(U128(1));
+        assert!(bridge.get_freeze(U128(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Delivery timeout not reached")]
+    fn nfts_cannot_be_reclaimed_before_the_timeout() {
+        let mut bridge = setup_freeze();
+        set_timeout(&mut bridge, 100);
+
+        testing_env!(context(&account("alice"), 0).block_timestamp(99).build());
+        bridge.reclaim_nft(U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_sender_can_reclaim() {
+        let mut bridge = setup_freeze();
+        set_timeout(&mut bridge, 0);
+
+        set_context(&account("mallory"), 0);
+        bridge.reclaim_nft(U128(1));
+    }
+
+    #[test]
+    fn delivered_nfts_cannot_be_reclaimed() {
+        let mut bridge = setup_freeze();
+        let data = DeliveryAckData {
+            action_id: U128(1),
+            freeze_ids: vec![U128(1)],
+        };
+        let sig = group_sig(b"AckDelivery", &data);
+        bridge.validate_ack_delivery(data, sig);
+
+        assert!(bridge.get_freeze(U128(1)).is_none());
+    }
+}
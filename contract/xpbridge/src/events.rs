@@ -1,3 +1,4 @@
+use crate::TimelockAction;
 use near_contract_standards::non_fungible_token::{Token, TokenId};
 use near_sdk::{
     env,
@@ -5,6 +6,7 @@ use near_sdk::{
     serde_json::{self},
     AccountId,
 };
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
@@ -13,9 +15,14 @@ pub struct TransferNftEvent {
     pub to: String,
     pub mint_with: String,
     pub action_id: u128,
+    /// Fee paid for the transfer.
     pub amt: u128,
     pub token_id: TokenId,
     pub contract: AccountId,
+    pub fee_token: Option<AccountId>,
+    pub royalty: Option<HashMap<AccountId, u32>>,
+    /// Number of copies of a NEP-245 token, None for a NEP-171 NFT.
+    pub quantity: Option<u128>,
 }
 
 impl TransferNftEvent {
@@ -45,9 +52,13 @@ pub struct UnfreezeNftEvent {
     pub chain_nonce: u8,
     pub to: String,
     pub action_id: u128,
+    /// Fee paid for the transfer.
     pub amt: u128,
     pub token: Option<Token>,
     pub contract: AccountId,
+    pub fee_token: Option<AccountId>,
+    /// Number of copies of a NEP-245 token, None for a NEP-171 NFT.
+    pub quantity: Option<u128>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -77,3 +88,304 @@ impl UnfreezeNftEvent {
         env::log_str(&self.to_json_event_string());
     }
 }
+
+/// Logs `event` as a bridge event of `event_type`.
+fn emit_event<T: Serialize>(event_type: &str, event: &T) {
+    let event = Event { event, event_type };
+    // Events cannot fail to serialize so fine to panic on error
+    let json = serde_json::to_string(&event)
+        .ok()
+        .unwrap_or_else(|| env::abort());
+    env::log_str(&format!("EVENT_JSON:{}", json));
+}
+
+/// Emitted when a transfer from a foreign chain was minted on NEAR.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferValidatedEvent {
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub action_id: u128,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    /// Number of copies of a NEP-245 token, None for a NEP-171 NFT.
+    pub quantity: Option<u128>,
+}
+
+impl TransferValidatedEvent {
+    pub fn emit(self) {
+        emit_event("ValidateTransferUnique", &self);
+    }
+}
+
+/// Emitted when the metadata of a wrapped NFT was updated
+/// from its home chain.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MetadataUpdatedEvent {
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub action_id: u128,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+}
+
+impl MetadataUpdatedEvent {
+    pub fn emit(self) {
+        emit_event("MetadataUpdated", &self);
+    }
+}
+
+/// Emitted when an NFT was unfrozen for a transfer from a foreign chain.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnfreezeValidatedEvent {
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub action_id: u128,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+    pub receiver_id: AccountId,
+    /// Number of copies of a NEP-245 token, None for a NEP-171 NFT.
+    pub quantity: Option<u128>,
+}
+
+impl UnfreezeValidatedEvent {
+    pub fn emit(self) {
+        emit_event("ValidateUnfreezeUnique", &self);
+    }
+}
+
+/// Emitted when a transfer from a foreign chain was stored as a
+/// voucher that the recipient can claim until `expires_at`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoucherIssuedEvent {
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub action_id: u128,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub expires_at: u64,
+}
+
+impl VoucherIssuedEvent {
+    pub fn emit(self) {
+        emit_event("VoucherIssued", &self);
+    }
+}
+
+/// Emitted when a voucher expired without being claimed.
+/// The NFT must be returned on the source chain.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoucherExpiredEvent {
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub action_id: u128,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+}
+
+impl VoucherExpiredEvent {
+    pub fn emit(self) {
+        emit_event("VoucherExpired", &self);
+    }
+}
+
+/// Emitted when a refund was transferred to `account_id`.
+/// `token` is the NEP-141 token of the refund, None for NEAR.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefundIssuedEvent {
+    pub account_id: AccountId,
+    pub amt: u128,
+    pub token: Option<AccountId>,
+}
+
+impl RefundIssuedEvent {
+    pub fn emit(self) {
+        emit_event("RefundIssued", &self);
+    }
+}
+
+/// Emitted when a refund to `account_id` failed and was recorded as a
+/// credit which can be claimed with `claim_refund`, or `claim_ft_refund`
+/// for a refund in the NEP-141 `token`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RefundPendingEvent {
+    pub account_id: AccountId,
+    pub amt: u128,
+    pub token: Option<AccountId>,
+}
+
+impl RefundPendingEvent {
+    pub fn emit(self) {
+        emit_event("RefundPending", &self);
+    }
+}
+
+/// Emitted when the sender of a freeze requests its cancellation.
+/// Validators should not mint the NFT on the destination chain while
+/// the request is pending.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CancelRequestedEvent {
+    pub action_id: u128,
+    pub sender: AccountId,
+}
+
+impl CancelRequestedEvent {
+    pub fn emit(self) {
+        emit_event("CancelRequested", &self);
+    }
+}
+
+/// Emitted when a freeze was cancelled and the NFT returned to its sender.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeCancelledEvent {
+    pub action_id: u128,
+    pub chain_nonce: u8,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+    pub sender: AccountId,
+    pub fee_refunded: u128,
+    pub fee_token: Option<AccountId>,
+}
+
+impl FreezeCancelledEvent {
+    pub fn emit(self) {
+        emit_event("FreezeCancelled", &self);
+    }
+}
+
+/// Emitted when the sender of a freeze reclaimed the NFT after the
+/// delivery timeout of the destination chain. Validators must not
+/// mint the NFT on the destination chain anymore.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeReclaimedEvent {
+    pub action_id: u128,
+    pub chain_nonce: u8,
+    pub contract: AccountId,
+    pub token_id: TokenId,
+    pub sender: AccountId,
+}
+
+impl FreezeReclaimedEvent {
+    pub fn emit(self) {
+        emit_event("FreezeReclaimed", &self);
+    }
+}
+
+/// Emitted when an incoming action was queued above the rate limits.
+/// It can be executed with `execute_queued_action` after `eta`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActionQueuedEvent {
+    pub queue_id: u64,
+    pub chain_nonce: u8,
+    pub action_id: u128,
+    pub contract: AccountId,
+    pub eta: u64,
+}
+
+impl ActionQueuedEvent {
+    pub fn emit(self) {
+        emit_event("ActionQueued", &self);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueuedActionCancelledEvent {
+    pub queue_id: u64,
+}
+
+impl QueuedActionCancelledEvent {
+    pub fn emit(self) {
+        emit_event("QueuedActionCancelled", &self);
+    }
+}
+
+/// Emitted when a sensitive admin action was scheduled by the timelock.
+/// It can be executed with `execute_timelock` after `eta`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockScheduledEvent {
+    pub timelock_id: u64,
+    pub action_id: u128,
+    pub action: TimelockAction,
+    pub eta: u64,
+}
+
+impl TimelockScheduledEvent {
+    pub fn emit(self) {
+        emit_event("TimelockScheduled", &self);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockExecutedEvent {
+    pub timelock_id: u64,
+}
+
+impl TimelockExecutedEvent {
+    pub fn emit(self) {
+        emit_event("TimelockExecuted", &self);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockCancelledEvent {
+    pub timelock_id: u64,
+}
+
+impl TimelockCancelledEvent {
+    pub fn emit(self) {
+        emit_event("TimelockCancelled", &self);
+    }
+}
+
+/// Emitted when a collection was registered by the first freeze of
+/// one of its NFTs in permissionless mode. The icon of the collection
+/// is left out to keep the log small.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionRegisteredEvent {
+    pub contract: AccountId,
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub registration_fee: u128,
+}
+
+impl CollectionRegisteredEvent {
+    pub fn emit(self) {
+        emit_event("CollectionRegistered", &self);
+    }
+}
+
+/// Emitted when a wrapped collection was deployed for a foreign collection.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionDeployedEvent {
+    pub chain_nonce: u8,
+    pub foreign_contract: String,
+    pub contract: AccountId,
+}
+
+impl CollectionDeployedEvent {
+    pub fn emit(self) {
+        emit_event("CollectionDeployed", &self);
+    }
+}
@@ -1,6 +1,11 @@
 
-use near_contract_standards::non_fungible_token::{metadata::TokenMetadata, Token, TokenId};
+use near_contract_standards::non_fungible_token::metadata::{NFTContractMetadata, TokenMetadata};
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_sdk::json_types::U128;
 use near_sdk::{ext_contract, AccountId, Promise};
+use std::collections::HashMap;
+
+use crate::{Payout, TokenOrigin};
 
 pub const TYOCTO: u128 = 1_000_000_000_000;
 pub const TGAS: u64 = 1_000_000_000_000;
@@ -12,8 +17,18 @@ pub trait XpNft {
         token_id: TokenId,
         token_owner_id: AccountId,
         token_metadata: TokenMetadata,
+        royalty: Option<HashMap<AccountId, u32>>,
+        origin: Option<TokenOrigin>,
+        refund_id: Option<AccountId>,
     ) -> Token;
 
+    fn nft_update_metadata(
+        &mut self,
+        token_id: TokenId,
+        token_metadata: TokenMetadata,
+        refund_id: Option<AccountId>,
+    );
+
     fn nft_burn(&mut self, token_id: TokenId, from: AccountId) -> Promise;
 
     fn nft_token(&self, token_id: TokenId) -> Option<Token>;
@@ -28,4 +43,38 @@ pub trait CommonNft {
         approval_id: Option<u64>,
         memo: Option<String>,
     );
-}
\ No newline at end of file
+
+    fn nft_metadata(&self) -> NFTContractMetadata;
+
+    fn nft_payout(&self, token_id: TokenId, balance: U128, max_len_payout: Option<u32>) -> Payout;
+}
+#[ext_contract(xpmt)]
+pub trait XpMt {
+    fn mt_mint(
+        &mut self,
+        token_id: TokenId,
+        token_owner_id: AccountId,
+        amount: U128,
+        token_metadata: Option<TokenMetadata>,
+        refund_id: Option<AccountId>,
+    );
+
+    fn mt_burn(&mut self, token_id: TokenId, from: AccountId, amount: U128);
+}
+
+#[ext_contract(common_mt)]
+pub trait MultiToken {
+    fn mt_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+    );
+}
+
+#[ext_contract(fungible_token)]
+pub trait FungibleToken {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
@@ -0,0 +1,501 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::metadata::{
+    NFTContractMetadata, NFT_METADATA_SPEC,
+};
+use near_sdk::serde_json::json;
+
+/// Storage key of the code of the wrapped collections.
+const COLLECTION_CODE_KEY: &[u8] = b"CODE";
+
+const GAS_FOR_DEPLOY_COLLECTION: Gas = Gas(60_000_000_000_000);
+const GAS_FOR_COLLECTION_INIT: Gas = Gas(20_000_000_000_000);
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionCodeData {
+    pub action_id: U128,
+    pub code_hash: [u8; 32],
+}
+
+/// The foreign collection a wrapped NEAR collection represents.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOrigin {
+    pub chain_nonce: u8,
+    pub foreign_contract: String,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOriginData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub foreign_contract: String,
+    pub contract: Option<AccountId>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DeployCollectionData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub foreign_contract: String,
+    pub name: String,
+    pub symbol: String,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Sets the sha256 hash of the xpnft wasm deployed for the wrapped
+    /// collections. The code itself is uploaded with `upload_collection_code`.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_collection_code(&mut self, data: CollectionCodeData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetCollectionCode",
+        );
+
+        self.collection_code_hash = Some(data.code_hash);
+    }
+
+    /// Stores the xpnft wasm passed as the raw input of the call.
+    /// Can be called by anyone, the caller pays for the storage of the
+    /// code with the attached deposit and gets back what is left of it.
+    /// FAILS: If the hash of the code doesn't match the approved hash or
+    /// the attached deposit doesn't cover the storage.
+    #[payable]
+    pub fn upload_collection_code(&mut self) {
+        let code = env::input().unwrap_or_default();
+        require!(
+            self.collection_code_hash == Some(env::sha256_array(&code)),
+            "Code hash not approved"
+        );
+
+        let initial_storage = env::storage_usage();
+        env::storage_write(COLLECTION_CODE_KEY, &code);
+        let cost =
+            env::storage_byte_cost() * env::storage_usage().saturating_sub(initial_storage) as u128;
+        let attached = env::attached_deposit();
+        require!(attached >= cost, "Not enough deposit for storage");
+
+        if attached > cost {
+            Promise::new(env::predecessor_account_id()).transfer(attached - cost);
+        }
+    }
+
+    /// Deploys the xpnft wasm as a sub-account of this contract for a
+    /// foreign collection, owned by this contract and initialized with the
+    /// name and symbol of the foreign collection. The attached deposit is
+    /// transferred to the new account to pay for its storage, and returned
+    /// to the caller if the deployment fails.
+    /// FAILS: If contract is paused, the collection was already deployed
+    /// or no code was uploaded.
+    /// REQUIRED: Signature verification.
+    #[payable]
+    pub fn validate_deploy_collection(
+        &mut self,
+        data: DeployCollectionData,
+        sig_data: Vec<u8>,
+    ) -> Promise {
+        require!(
+            env::prepaid_gas() >= GAS_FOR_DEPLOY_COLLECTION,
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+        require!(
+            !self
+                .collections
+                .contains_key(&(data.chain_nonce, data.foreign_contract.clone())),
+            "Collection already registered"
+        );
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"DeployCollection",
+        );
+
+        let code = env::storage_read(COLLECTION_CODE_KEY)
+            .unwrap_or_else(|| env::panic_str("No collection code"));
+        let contract = collection_account_id(data.chain_nonce, &data.foreign_contract);
+        let metadata = NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: data.name,
+            symbol: data.symbol,
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        };
+
+        Promise::new(contract.clone())
+            .create_account()
+            .transfer(env::attached_deposit())
+            .deploy_contract(code)
+            .function_call(
+                "initialize".to_string(),
+                json!({
+                    "owner_id": env::current_account_id(),
+                    "metadata": metadata,
+                })
+                .to_string()
+                .into_bytes(),
+                0,
+                GAS_FOR_COLLECTION_INIT,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .deploy_collection_callback(
+                        data.action_id.0,
+                        CollectionDeployedEvent {
+                            chain_nonce: data.chain_nonce,
+                            foreign_contract: data.foreign_contract,
+                            contract,
+                        },
+                        env::attached_deposit(),
+                        env::predecessor_account_id(),
+                    ),
+            )
+    }
+
+    /// This is the callback function when the promise in the
+    /// validate_deploy_collection function is completed. Records the
+    /// collection if it was deployed, otherwise the deposit is returned.
+    #[private]
+    pub fn deploy_collection_callback(
+        &mut self,
+        action_id: u128,
+        event: CollectionDeployedEvent,
+        amt: u128,
+        sender: AccountId,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => {
+                self.internal_set_collection_origin(
+                    event.chain_nonce,
+                    event.foreign_contract.clone(),
+                    Some(event.contract.clone()),
+                );
+                event.emit();
+            }
+            Err(e) => {
+                self.consumed_actions.release(CHAIN_NONCE, action_id);
+                self.internal_refund(sender, amt);
+                env::log_str(&format!(
+                    "deploy collection callback: failed to deploy collection: actionid: {} : {:?}",
+                    action_id, e
+                ))
+            }
+        }
+    }
+
+    /// Maps a foreign collection to a wrapped NEAR collection which was
+    /// deployed manually, replacing any previous mapping of both.
+    /// A `contract` of None removes the mapping of the foreign collection.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_collection_origin(
+        &mut self,
+        data: CollectionOriginData,
+        sig_data: Vec<u8>,
+    ) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetCollectionOrigin",
+        );
+
+        self.internal_set_collection_origin(data.chain_nonce, data.foreign_contract, data.contract);
+    }
+
+    /// Gets the foreign collection the wrapped NEAR collection represents.
+    pub fn get_collection_origin(&self, contract: AccountId) -> Option<CollectionOrigin> {
+        self.collection_origins.get(&contract)
+    }
+
+    /// Gets the wrapped NEAR collection of the foreign collection.
+    pub fn get_collection(&self, chain_nonce: u8, foreign_contract: String) -> Option<AccountId> {
+        self.collections.get(&(chain_nonce, foreign_contract))
+    }
+
+    /// Gets the sha256 hash of the xpnft wasm deployed for the wrapped collections.
+    pub fn get_collection_code_hash(&self) -> Option<[u8; 32]> {
+        self.collection_code_hash
+    }
+}
+
+impl XpBridge {
+    /// Maps the foreign collection to `contract` in both directions,
+    /// removing the previous mappings of both sides.
+    fn internal_set_collection_origin(
+        &mut self,
+        chain_nonce: u8,
+        foreign_contract: String,
+        contract: Option<AccountId>,
+    ) {
+        let key = (chain_nonce, foreign_contract);
+        if let Some(previous) = self.collections.remove(&key) {
+            self.collection_origins.remove(&previous);
+        }
+        let Some(contract) = contract else {
+            return;
+        };
+        if let Some(previous) = self.collection_origins.get(&contract) {
+            self.collections
+                .remove(&(previous.chain_nonce, previous.foreign_contract));
+        }
+
+        self.collections.insert(&key, &contract);
+        self.collection_origins.insert(
+            &contract,
+            &CollectionOrigin {
+                chain_nonce: key.0,
+                foreign_contract: key.1,
+            },
+        );
+    }
+
+    /// Checks that a transfer mints on the wrapped collection registered
+    /// for its origin. Transfers from or to collections which are not
+    /// registered are not checked.
+    /// FAILS: If the origin or `mint_with` is registered for another collection.
+    pub(crate) fn require_collection_origin(&self, data: &TransferNftData) {
+        self.require_origin(data.chain_nonce, &data.origin_contract, &data.mint_with);
+    }
+
+    /// Same as `require_collection_origin` for the parts of a transfer.
+    pub(crate) fn require_origin(
+        &self,
+        chain_nonce: u8,
+        origin_contract: &str,
+        mint_with: &AccountId,
+    ) {
+        if let Some(contract) = self
+            .collections
+            .get(&(chain_nonce, origin_contract.to_string()))
+        {
+            require!(contract == *mint_with, "Invalid mint_with for origin");
+        }
+        if let Some(origin) = self.collection_origins.get(mint_with) {
+            require!(
+                origin.chain_nonce == chain_nonce && origin.foreign_contract == origin_contract,
+                "Invalid origin for mint_with"
+            );
+        }
+    }
+}
+
+/// Account id of the wrapped collection of a foreign collection: the hex
+/// encoded first 16 bytes of sha256(borsh(chain_nonce, foreign_contract))
+/// as a sub-account of this contract.
+pub fn collection_account_id(chain_nonce: u8, foreign_contract: &str) -> AccountId {
+    let hash = env::sha256_array(&(chain_nonce, foreign_contract).try_to_vec().unwrap());
+    let prefix: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.{}", prefix, env::current_account_id())
+        .parse()
+        .unwrap_or_else(|_| env::panic_str("Invalid collection account id"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    const CODE: &[u8] = b"\0asm xpnft";
+
+    fn approve_code(bridge: &mut XpBridge) {
+        let data = CollectionCodeData {
+            action_id: U128(1),
+            code_hash: env::sha256_array(CODE),
+        };
+        let sig = group_sig(b"SetCollectionCode", &data);
+        bridge.validate_set_collection_code(data, sig);
+    }
+
+    fn upload(bridge: &mut XpBridge, code: &[u8]) {
+        let mut context = context(&account("uploader"), ONE_NEAR).build();
+        context.input = code.to_vec();
+        testing_env!(context);
+        bridge.upload_collection_code();
+    }
+
+    fn deploy_data() -> DeployCollectionData {
+        DeployCollectionData {
+            action_id: U128(2),
+            chain_nonce: 4,
+            foreign_contract: "0xorigin".to_string(),
+            name: "Collection".to_string(),
+            symbol: "COL".to_string(),
+        }
+    }
+
+    fn set_origin(bridge: &mut XpBridge, action_id: u128, origin: &str, contract: Option<&str>) {
+        let data = CollectionOriginData {
+            action_id: U128(action_id),
+            chain_nonce: 4,
+            foreign_contract: origin.to_string(),
+            contract: contract.map(account),
+        };
+        let sig = group_sig(b"SetCollectionOrigin", &data);
+        bridge.validate_set_collection_origin(data, sig);
+    }
+
+    #[test]
+    fn approved_code_is_stored() {
+        let mut bridge = setup();
+        approve_code(&mut bridge);
+
+        upload(&mut bridge, CODE);
+
+        assert_eq!(
+            env::storage_read(COLLECTION_CODE_KEY).unwrap(),
+            CODE.to_vec()
+        );
+        let refund = transferred_to(&account("uploader"));
+        assert!(refund > 0 && refund < ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "Code hash not approved")]
+    fn unapproved_code_is_rejected() {
+        let mut bridge = setup();
+        approve_code(&mut bridge);
+
+        upload(&mut bridge, b"\0asm other");
+    }
+
+    #[test]
+    fn collections_are_deployed_as_sub_accounts() {
+        let mut bridge = setup();
+        approve_code(&mut bridge);
+        upload(&mut bridge, CODE);
+        let data = deploy_data();
+        let sig = group_sig(b"DeployCollection", &data);
+
+        set_context(&account("relayer"), ONE_NEAR);
+        bridge.validate_deploy_collection(data, sig);
+
+        let contract = collection_account_id(4, "0xorigin");
+        assert!(contract.as_str().ends_with(".bridge.near"));
+        let receipt = get_created_receipts()
+            .into_iter()
+            .find(|r| r.receiver_id == contract)
+            .unwrap();
+        assert!(receipt.actions.contains(&VmAction::CreateAccount));
+        assert!(receipt.actions.contains(&VmAction::DeployContract {
+            code: CODE.to_vec()
+        }));
+        assert!(receipt
+            .actions
+            .contains(&VmAction::Transfer { deposit: ONE_NEAR }));
+
+        set_context(&bridge_id(), 0);
+        bridge.deploy_collection_callback(
+            2,
+            CollectionDeployedEvent {
+                chain_nonce: 4,
+                foreign_contract: "0xorigin".to_string(),
+                contract: contract.clone(),
+            },
+            ONE_NEAR,
+            account("relayer"),
+            Ok(()),
+        );
+        assert_eq!(
+            bridge.get_collection(4, "0xorigin".to_string()),
+            Some(contract)
+        );
+    }
+
+    #[test]
+    fn failed_deploy_refunds_the_caller() {
+        let mut bridge = setup();
+
+        set_context(&bridge_id(), 0);
+        bridge.consumed_actions.consume(CHAIN_NONCE, 2);
+        bridge.deploy_collection_callback(
+            2,
+            CollectionDeployedEvent {
+                chain_nonce: 4,
+                foreign_contract: "0xorigin".to_string(),
+                contract: collection_account_id(4, "0xorigin"),
+            },
+            ONE_NEAR,
+            account("relayer"),
+            Err(PromiseError::Failed),
+        );
+
+        assert_eq!(transferred_to(&account("relayer")), ONE_NEAR);
+        assert!(!bridge.is_action_consumed(CHAIN_NONCE, U128(2)));
+        assert!(bridge.get_collection(4, "0xorigin".to_string()).is_none());
+    }
+
+    #[test]
+    fn origins_are_mapped_both_ways() {
+        let mut bridge = setup();
+        set_origin(&mut bridge, 1, "0xorigin", Some("wrapped"));
+
+        assert_eq!(
+            bridge.get_collection(4, "0xorigin".to_string()),
+            Some(account("wrapped"))
+        );
+        let origin = bridge.get_collection_origin(account("wrapped")).unwrap();
+        assert_eq!(origin.chain_nonce, 4);
+        assert_eq!(origin.foreign_contract, "0xorigin");
+    }
+
+    #[test]
+    fn remapping_drops_the_previous_mappings() {
+        let mut bridge = setup();
+        set_origin(&mut bridge, 1, "0xorigin", Some("wrapped"));
+        set_origin(&mut bridge, 2, "0xother", Some("wrapped"));
+
+        assert!(bridge.get_collection(4, "0xorigin".to_string()).is_none());
+        assert_eq!(
+            bridge.get_collection(4, "0xother".to_string()),
+            Some(account("wrapped"))
+        );
+
+        set_origin(&mut bridge, 3, "0xother", None);
+        assert!(bridge.get_collection(4, "0xother".to_string()).is_none());
+        assert!(bridge.get_collection_origin(account("wrapped")).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid mint_with for origin")]
+    fn transfers_mint_on_the_mapped_collection() {
+        let mut bridge = setup();
+        set_origin(&mut bridge, 1, "0xorigin", Some("other"));
+        let data = transfer_data(1, "1");
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        set_context(&account("relayer"), 10);
+        bridge.validate_transfer_nft(data, sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid origin for mint_with")]
+    fn mapped_collections_only_mint_their_origin() {
+        let mut bridge = setup();
+        set_origin(&mut bridge, 1, "0xother", Some("wrapped"));
+        let data = transfer_data(1, "1");
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        set_context(&account("relayer"), 10);
+        bridge.validate_transfer_nft(data, sig);
+    }
+}
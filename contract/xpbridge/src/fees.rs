@@ -0,0 +1,240 @@
+use crate::*;
+
+/// Denominator for the basis point shares of a fee split.
+pub const FEE_BPS_DENOMINATOR: u16 = 10_000;
+
+#[derive(Clone, PartialEq, Debug, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeBeneficiary {
+    pub account_id: AccountId,
+    pub share_bps: u16,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSplitData {
+    pub action_id: U128,
+    pub beneficiaries: Vec<FeeBeneficiary>,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Sets the split applied to the collected fees by `validate_withdraw_fees`.
+    /// Shares are in basis points and must add up to 10000.
+    /// An empty list of beneficiaries removes the split, and the fees
+    /// are withdrawn to the account in {WithdrawFeeData} again.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_fee_split(&mut self, data: FeeSplitData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        if !data.beneficiaries.is_empty() {
+            let total: u32 = data.beneficiaries.iter().map(|b| b.share_bps as u32).sum();
+            require!(
+                total == FEE_BPS_DENOMINATOR as u32,
+                "Fee split shares must add up to 10000"
+            );
+        }
+        for (i, beneficiary) in data.beneficiaries.iter().enumerate() {
+            require!(
+                !data.beneficiaries[..i]
+                    .iter()
+                    .any(|b| b.account_id == beneficiary.account_id),
+                "Duplicated beneficiary"
+            );
+        }
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetFeeSplit",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetFeeSplit {
+                beneficiaries: data.beneficiaries,
+            },
+        );
+    }
+
+    /// Transfers the fees accrued by the caller through the fee split.
+    /// FAILS: If the caller has nothing to claim.
+    pub fn claim_fees(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amt = self.accrued_fees.remove(&account_id).unwrap_or(0);
+        require!(amt > 0, "Nothing to claim");
+        self.total_accrued_fees -= amt;
+
+        Promise::new(account_id.clone()).transfer(amt).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas(TGAS * 10))
+                .claim_fees_callback(account_id, amt),
+        )
+    }
+
+    /// This is the callback function when the promise in the claim_fees
+    /// function is completed. Credits the amount back to the beneficiary
+    /// if the transfer failed.
+    #[private]
+    pub fn claim_fees_callback(
+        &mut self,
+        account_id: AccountId,
+        amt: u128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        if let Err(e) = call_result {
+            self.internal_accrue_fees(&account_id, amt);
+            env::log_str(&format!(
+                "claim fees callback: failed to transfer fees: account: {} : {:?}",
+                account_id, e
+            ))
+        }
+    }
+
+    /// Gets the currently configured fee split.
+    pub fn get_fee_split(&self) -> Vec<FeeBeneficiary> {
+        self.fee_split.clone()
+    }
+
+    /// Gets the fees accrued by `account_id` that are not claimed yet.
+    pub fn get_accrued_fees(&self, account_id: AccountId) -> U128 {
+        U128(self.accrued_fees.get(&account_id).unwrap_or(0))
+    }
+}
+
+impl XpBridge {
+    /// Balance of the contract that can be withdrawn as fees. Keeps the
+    /// storage staking, a one NEAR reserve and the balances owed to
+    /// other accounts in the contract.
+    pub(crate) fn withdrawable_fees(&self) -> u128 {
+        let storage_cost = env::storage_usage() as u128 * env::storage_byte_cost();
+        env::account_balance()
+            .saturating_sub(storage_cost)
+            .saturating_sub(ONE_NEAR)
+            .saturating_sub(self.locked_balance())
+    }
+
+    /// Balance of the contract which belongs to other accounts.
+    pub(crate) fn locked_balance(&self) -> u128 {
+        self.total_accrued_fees
+            + self.total_storage_pool
+            + self.total_pending_refunds
+            + self.total_queued_deposits
+    }
+
+    /// Credits `amt` to the beneficiaries of the fee split. The rounding
+    /// remainder goes to the first beneficiary.
+    pub(crate) fn internal_split_fees(&mut self, amt: u128) {
+        let split = self.fee_split.clone();
+        let mut distributed = 0;
+        for beneficiary in split.iter().skip(1) {
+            let share = amt * beneficiary.share_bps as u128 / FEE_BPS_DENOMINATOR as u128;
+            self.internal_accrue_fees(&beneficiary.account_id, share);
+            distributed += share;
+        }
+        if let Some(first) = split.first() {
+            self.internal_accrue_fees(&first.account_id, amt - distributed);
+        }
+    }
+
+    pub(crate) fn internal_accrue_fees(&mut self, account_id: &AccountId, amt: u128) {
+        if amt == 0 {
+            return;
+        }
+        let balance = self.accrued_fees.get(account_id).unwrap_or(0);
+        self.accrued_fees.insert(account_id, &(balance + amt));
+        self.total_accrued_fees += amt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    fn split(beneficiaries: &[(&str, u16)]) -> Vec<FeeBeneficiary> {
+        beneficiaries
+            .iter()
+            .map(|(name, share_bps)| FeeBeneficiary {
+                account_id: account(name),
+                share_bps: *share_bps,
+            })
+            .collect()
+    }
+
+    fn set_fee_split(bridge: &mut XpBridge, action_id: u128, beneficiaries: &[(&str, u16)]) {
+        let data = FeeSplitData {
+            action_id: U128(action_id),
+            beneficiaries: split(beneficiaries),
+        };
+        let sig = group_sig(b"SetFeeSplit", &data);
+        bridge.validate_set_fee_split(data, sig);
+    }
+
+    #[test]
+    fn withdraw_fees_credits_the_beneficiaries() {
+        let mut bridge = setup();
+        set_fee_split(&mut bridge, 1, &[("alice", 7_000), ("bob", 3_000)]);
+        assert!(bridge.get_fee_split() == split(&[("alice", 7_000), ("bob", 3_000)]));
+
+        testing_env!(context(&account("relayer"), 0)
+            .account_balance(100 * ONE_NEAR)
+            .build());
+        assert!(bridge.withdrawable_fees() > 0);
+        let data = WithdrawFeeData {
+            action_id: U128(2),
+            account_id: account("treasury"),
+        };
+        let sig = group_sig(b"WithdrawFees", &data);
+        bridge.validate_withdraw_fees(data, sig);
+
+        // The consumed action id takes storage, so the split amount is read
+        // back from the beneficiaries.
+        let alice = bridge.get_accrued_fees(account("alice")).0;
+        let bob = bridge.get_accrued_fees(account("bob")).0;
+        assert!(bob > 0);
+        assert_eq!(bob, (alice + bob) * 3_000 / 10_000);
+        assert_eq!(bridge.get_accrued_fees(account("treasury")).0, 0);
+
+        set_context(&account("bob"), 0);
+        bridge.claim_fees();
+        assert_eq!(bridge.get_accrued_fees(account("bob")).0, 0);
+        assert_eq!(bridge.locked_balance(), alice);
+    }
+
+    #[test]
+    fn failed_claim_credits_the_fees_back() {
+        let mut bridge = setup();
+        bridge.internal_accrue_fees(&account("bob"), 100);
+
+        set_context(&account("bob"), 0);
+        bridge.claim_fees();
+        set_context(&bridge_id(), 0);
+        bridge.claim_fees_callback(account("bob"), 100, Err(PromiseError::Failed));
+
+        assert_eq!(bridge.get_accrued_fees(account("bob")).0, 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fee split shares must add up to 10000")]
+    fn fee_split_shares_must_add_up() {
+        let mut bridge = setup();
+        set_fee_split(&mut bridge, 1, &[("alice", 7_000), ("bob", 2_000)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized Action")]
+    fn fee_split_requires_the_group_signature() {
+        let mut bridge = setup();
+        let data = FeeSplitData {
+            action_id: U128(1),
+            beneficiaries: split(&[("alice", 10_000)]),
+        };
+        let sig = fee_sig(b"SetFeeSplit", &data);
+        bridge.validate_set_fee_split(data, sig);
+    }
+}
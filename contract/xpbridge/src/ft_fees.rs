@@ -0,0 +1,579 @@
+use crate::*;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::serde_json;
+
+/// Fee signature for bridge transfers paid with a NEP-141 token.
+/// Same as {TransferTx} with the token the fee is paid in.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtTransferTx {
+    value: u128,
+    fee_token: AccountId,
+    from_chain: u8,
+    to_chain: u8,
+    token_contract: AccountId,
+    token_id: TokenId,
+    to: String,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTokenData {
+    pub action_id: U128,
+    pub token: AccountId,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawFtFeeData {
+    pub action_id: U128,
+    pub token: AccountId,
+    pub account_id: AccountId,
+}
+
+/// Bridge request passed in the `msg` of `ft_transfer_call`
+/// when the fee is paid with a NEP-141 token.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FtBridgeRequest {
+    FreezeNft {
+        token_contract: AccountId,
+        token_id: TokenId,
+        chain_nonce: u8,
+        to: String,
+        mint_with: String,
+        sig_data: Vec<u8>,
+    },
+    WithdrawNft {
+        token_contract: AccountId,
+        token_id: TokenId,
+        chain_nonce: u8,
+        to: String,
+        sig_data: Vec<u8>,
+    },
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Transfers the fees collected in `token` to `account_id` and then
+    /// calls 'withdraw_ft_fee_callback'.
+    /// FAILS: If no fees were collected in the token.
+    pub(crate) fn internal_withdraw_ft_fees(
+        &mut self,
+        action_id: u128,
+        token: AccountId,
+        account_id: AccountId,
+    ) -> PromiseOrValue<()> {
+        let amt = self.ft_fees.remove(&token).unwrap_or(0);
+        require!(amt > 0, "No fees collected");
+
+        fungible_token::ext(token.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas(TGAS * 10))
+            .ft_transfer(account_id, U128(amt), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .withdraw_ft_fee_callback(action_id, token, amt),
+            )
+            .into()
+    }
+
+    /// Adds the provided NEP-141 token to the tokens accepted for bridge fees.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_whitelist_fee_token(&mut self, data: FeeTokenData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+        require!(!self.fee_tokens.contains(&data.token), "Already whitelist");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"WhitelistFeeToken",
+        );
+
+        self.fee_tokens.insert(&data.token);
+    }
+
+    /// Removes the provided NEP-141 token from the tokens accepted for bridge fees.
+    /// Fees already collected in the token can still be withdrawn.
+    /// FAILS: If contract is paused AND if the token is not present in whitelist.
+    /// REQUIRED: Signature verification.
+    pub fn validate_blacklist_fee_token(&mut self, data: FeeTokenData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+        require!(self.fee_tokens.contains(&data.token), "Not whitelist");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"BlacklistFeeToken",
+        );
+
+        self.fee_tokens.remove(&data.token);
+    }
+
+    /// Withdraws the fees collected in a NEP-141 token
+    /// to the account_id provied in the {WithdrawFtFeeData}.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_withdraw_ft_fees(
+        &mut self,
+        data: WithdrawFtFeeData,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
+        require!(!self.paused, "paused");
+        require!(
+            env::prepaid_gas() >= GAS_FOR_VALIDATE_WITHDRAW,
+            "Not enough gas"
+        );
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"WithdrawFtFees",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::WithdrawFtFees {
+                token: data.token,
+                account_id: data.account_id,
+            },
+        )
+    }
+
+    /// This is the callback function when the promise in the
+    /// validate_withdraw_ft_fees function is completed. Restores
+    /// the collected fees if the transfer failed.
+    #[private]
+    pub fn withdraw_ft_fee_callback(
+        &mut self,
+        action_id: u128,
+        token: AccountId,
+        amt: u128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        if let Err(e) = call_result {
+            self.consumed_actions.release(CHAIN_NONCE, action_id);
+            let collected = self.ft_fees.get(&token).unwrap_or(0);
+            self.ft_fees.insert(&token, &(collected + amt));
+            env::log_str(&format!(
+                "withdraw ft fee callback: failed to transfer tokens: actionid: {} : {:?}",
+                action_id, e
+            ))
+        }
+    }
+
+    /// This is the callback function when the nft transfer of a freeze
+    /// paid with a NEP-141 token is completed. Emits a TransferNftEvent
+    /// with the royalties of the NFT on NEAR and records the collection
+    /// registered by the freeze on success, otherwise the fee is returned
+    /// to the sender.
+    #[private]
+    pub fn ft_freeze_callback(
+        &mut self,
+        request: FreezeRequest,
+        fee_token: AccountId,
+        registration: Option<PendingRegistration>,
+        #[callback_result] call_result: Result<(), PromiseError>,
+        #[callback_result] payout: Result<Payout, PromiseError>,
+    ) -> U128 {
+        match call_result {
+            Ok(_) => {
+                // The registration is paid with the fee token.
+                self.internal_settle_registrations(registration, |_| true);
+                let royalty = royalty_from_payout(payout.ok());
+                self.action_cnt += 1;
+                self.internal_collect_ft_fees(&fee_token, request.amt.0);
+                self.internal_record_freeze(
+                    self.action_cnt,
+                    request.clone(),
+                    Some(fee_token.clone()),
+                );
+
+                TransferNftEvent {
+                    action_id: self.action_cnt,
+                    chain_nonce: request.chain_nonce,
+                    to: request.to,
+                    amt: request.amt.0,
+                    contract: request.token_contract,
+                    token_id: request.token_id,
+                    mint_with: request.mint_with,
+                    fee_token: Some(fee_token),
+                    royalty,
+                    quantity: None,
+                }
+                .emit();
+                U128(0)
+            }
+            Err(e) => {
+                env::log_str(&format!(
+                    "ft freeze callback: failed to transfer nft: actionid: {} : {:?}",
+                    self.action_cnt, e
+                ));
+                request.amt
+            }
+        }
+    }
+
+    /// This is the callback function when the token lookup of a withdraw
+    /// paid with a NEP-141 token is completed. Burns the nft and then calls
+    /// 'ft_withdraw_callback'.
+    #[private]
+    pub fn ft_token_callback(
+        &mut self,
+        request: WithdrawRequest,
+        fee_token: AccountId,
+        #[callback_result] call_result: Result<Option<Token>, PromiseError>,
+    ) -> PromiseOrValue<U128> {
+        match call_result {
+            Ok(token) => xpnft::ext(request.token_contract.clone())
+                .with_static_gas(Gas(TGAS * 10))
+                .nft_burn(request.token_id.clone(), request.sender.clone())
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(Gas(TGAS * 8))
+                        .ft_withdraw_callback(request, token, fee_token),
+                )
+                .into(),
+            Err(_) => PromiseOrValue::Value(request.amt),
+        }
+    }
+
+    /// This is the callback function when the burn of a withdraw paid with
+    /// a NEP-141 token is completed. Emits an UnfreezeNftEvent on success,
+    /// otherwise the fee is returned to the sender.
+    #[private]
+    pub fn ft_withdraw_callback(
+        &mut self,
+        request: WithdrawRequest,
+        token: Option<Token>,
+        fee_token: AccountId,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) -> U128 {
+        match call_result {
+            Ok(_) => {
+                self.action_cnt += 1;
+                self.internal_collect_ft_fees(&fee_token, request.amt.0);
+
+                UnfreezeNftEvent {
+                    action_id: self.action_cnt,
+                    chain_nonce: request.chain_nonce,
+                    to: request.to,
+                    amt: request.amt.0,
+                    contract: request.token_contract,
+                    token,
+                    fee_token: Some(fee_token),
+                    quantity: None,
+                }
+                .emit();
+                U128(0)
+            }
+            Err(e) => {
+                env::log_str(&format!(
+                    "ft withdraw callback: failed to burn nft:  actionid: {} : {:?}",
+                    self.action_cnt, e
+                ));
+                request.amt
+            }
+        }
+    }
+
+    /// Checks if the NEP-141 token provided in `token` is accepted for bridge fees.
+    pub fn is_fee_token(&self, token: AccountId) -> bool {
+        self.fee_tokens.contains(&token)
+    }
+
+    /// Gets the fees collected in the NEP-141 token provided in `token`.
+    pub fn get_ft_fees(&self, token: AccountId) -> U128 {
+        U128(self.ft_fees.get(&token).unwrap_or(0))
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for XpBridge {
+    /// Pays the fee of a bridge transfer with a whitelisted NEP-141 token.
+    /// The bridge request is passed as {FtBridgeRequest} in `msg` and the
+    /// fee signature is over {FtTransferTx} with the context "FtTransferTx".
+    /// The fee is returned to the sender if the transfer fails.
+    /// In permissionless mode, a freeze registers its collection like
+    /// `freeze_nft`, with the registration covered by the signed fee.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        require!(!self.paused, "paused");
+        let fee_token = env::predecessor_account_id();
+        require!(
+            self.fee_tokens.contains(&fee_token),
+            "Fee token not whitelisted"
+        );
+
+        let request: FtBridgeRequest =
+            serde_json::from_str(&msg).unwrap_or_else(|_| env::panic_str("Invalid bridge request"));
+
+        match request {
+            FtBridgeRequest::FreezeNft {
+                token_contract,
+                token_id,
+                chain_nonce,
+                to,
+                mint_with,
+                sig_data,
+            } => {
+                require!(env::prepaid_gas() >= GAS_FOR_FREEZE_NFT, "Not enough gas");
+                require!(
+                    !self.blocked_collections.contains(&token_contract),
+                    "Collection blocked"
+                );
+                self.require_fee_sig(
+                    FtTransferTx {
+                        value: amount.0,
+                        fee_token: fee_token.clone(),
+                        from_chain: CHAIN_NONCE,
+                        to_chain: chain_nonce,
+                        to: to.clone(),
+                        token_contract: token_contract.clone(),
+                        token_id: token_id.clone(),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                    sig_data,
+                    b"FtTransferTx",
+                );
+
+                if !self.is_bridgeable(&token_contract) {
+                    require!(self.permissionless_mode, "Collection not whitelisted");
+                    return self
+                        .internal_register_collections(
+                            vec![token_contract.clone()],
+                            PendingFreeze::Ft {
+                                request: FreezeRequest {
+                                    token_contract,
+                                    token_id,
+                                    chain_nonce,
+                                    to,
+                                    mint_with,
+                                    amt: amount,
+                                    sender: sender_id,
+                                },
+                                fee_token,
+                            },
+                        )
+                        .into();
+                }
+
+                self.internal_ft_freeze_nft(
+                    FreezeRequest {
+                        token_contract,
+                        token_id,
+                        chain_nonce,
+                        to,
+                        mint_with,
+                        amt: amount,
+                        sender: sender_id,
+                    },
+                    fee_token,
+                    None,
+                )
+                .into()
+            }
+            FtBridgeRequest::WithdrawNft {
+                token_contract,
+                token_id,
+                chain_nonce,
+                to,
+                sig_data,
+            } => {
+                require!(env::prepaid_gas() >= GAS_FOR_WITHDRAW_NFT, "Not enough gas");
+                self.require_fee_sig(
+                    FtTransferTx {
+                        value: amount.0,
+                        fee_token: fee_token.clone(),
+                        from_chain: CHAIN_NONCE,
+                        to_chain: chain_nonce,
+                        to: to.clone(),
+                        token_contract: token_contract.clone(),
+                        token_id: token_id.clone(),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                    sig_data,
+                    b"FtTransferTx",
+                );
+
+                xpnft::ext(token_contract.clone())
+                    .with_static_gas(Gas(5 * TGAS))
+                    .nft_token(token_id.clone())
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .with_static_gas(Gas(TGAS * 25))
+                            .ft_token_callback(
+                                WithdrawRequest {
+                                    token_contract,
+                                    token_id,
+                                    chain_nonce,
+                                    to,
+                                    amt: amount,
+                                    sender: sender_id,
+                                },
+                                fee_token,
+                            ),
+                    )
+                    .into()
+            }
+        }
+    }
+}
+
+impl XpBridge {
+    /// Transfers the NFT of a freeze paid with a NEP-141 token to this
+    /// bridge contract and then calls 'ft_freeze_callback'.
+    pub(crate) fn internal_ft_freeze_nft(
+        &mut self,
+        request: FreezeRequest,
+        fee_token: AccountId,
+        registration: Option<PendingRegistration>,
+    ) -> Promise {
+        common_nft::ext(request.token_contract.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_transfer(
+                env::current_account_id(),
+                request.token_id.clone(),
+                None,
+                None,
+            )
+            .and(nft_payout(
+                request.token_contract.clone(),
+                request.token_id.clone(),
+            ))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 9))
+                    .ft_freeze_callback(request, fee_token, registration),
+            )
+    }
+
+    fn internal_collect_ft_fees(&mut self, fee_token: &AccountId, amt: u128) {
+        let collected = self.ft_fees.get(fee_token).unwrap_or(0);
+        self.ft_fees.insert(fee_token, &(collected + amt));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn setup_fee_token() -> XpBridge {
+        let mut bridge = setup();
+        let data = FeeTokenData {
+            action_id: U128(1),
+            token: account("usdc"),
+        };
+        let sig = group_sig(b"WhitelistFeeToken", &data);
+        bridge.validate_whitelist_fee_token(data, sig);
+        bridge.internal_whitelist(&account("nft"), 2, None);
+        bridge
+    }
+
+    fn freeze_msg(amount: u128, context: &[u8]) -> String {
+        let tx = FtTransferTx {
+            value: amount,
+            fee_token: account("usdc"),
+            from_chain: 31,
+            to_chain: 4,
+            token_contract: account("nft"),
+            token_id: "1".to_string(),
+            to: "0xreceiver".to_string(),
+        };
+        serde_json::to_string(&FtBridgeRequest::FreezeNft {
+            token_contract: account("nft"),
+            token_id: "1".to_string(),
+            chain_nonce: 4,
+            to: "0xreceiver".to_string(),
+            mint_with: "0xwrapped".to_string(),
+            sig_data: fee_sig(context, &tx),
+        })
+        .unwrap()
+    }
+
+    fn freeze_callback(bridge: &mut XpBridge, result: Result<(), PromiseError>) -> U128 {
+        set_context(&bridge_id(), 0);
+        bridge.ft_freeze_callback(
+            FreezeRequest {
+                amt: U128(500),
+                ..freeze_request("alice")
+            },
+            account("usdc"),
+            None,
+            result,
+            Err(PromiseError::Failed),
+        )
+    }
+
+    #[test]
+    fn freeze_collects_the_fee_in_the_token() {
+        let mut bridge = setup_fee_token();
+        assert!(bridge.is_fee_token(account("usdc")));
+
+        set_context(&account("usdc"), 1);
+        bridge.ft_on_transfer(
+            account("alice"),
+            U128(500),
+            freeze_msg(500, b"FtTransferTx"),
+        );
+        assert_eq!(freeze_callback(&mut bridge, Ok(())).0, 0);
+
+        assert_eq!(bridge.get_ft_fees(account("usdc")).0, 500);
+        assert_eq!(bridge.get_action_cnt().0, 1);
+    }
+
+    #[test]
+    fn failed_freeze_returns_the_fee() {
+        let mut bridge = setup_fee_token();
+
+        set_context(&account("usdc"), 1);
+        bridge.ft_on_transfer(
+            account("alice"),
+            U128(500),
+            freeze_msg(500, b"FtTransferTx"),
+        );
+        assert_eq!(
+            freeze_callback(&mut bridge, Err(PromiseError::Failed)).0,
+            500
+        );
+
+        assert_eq!(bridge.get_ft_fees(account("usdc")).0, 0);
+        assert_eq!(bridge.get_action_cnt().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount Signature Verification Failed")]
+    fn fee_signature_needs_the_context_tag() {
+        let mut bridge = setup_fee_token();
+
+        set_context(&account("usdc"), 1);
+        bridge.ft_on_transfer(account("alice"), U128(500), freeze_msg(500, b""));
+    }
+
+    #[test]
+    #[should_panic(expected = "Fee token not whitelisted")]
+    fn fee_token_must_be_whitelisted() {
+        let mut bridge = setup_fee_token();
+
+        set_context(&account("dai"), 1);
+        bridge.ft_on_transfer(
+            account("alice"),
+            U128(500),
+            freeze_msg(500, b"FtTransferTx"),
+        );
+    }
+}
@@ -3,19 +3,61 @@ use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
 use near_contract_standards::non_fungible_token::Token;
 use near_contract_standards::non_fungible_token::TokenId;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedSet;
-use near_sdk::json_types::U128;
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedSet, Vector};
+use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::PanicOnDefault;
 use near_sdk::ONE_NEAR;
 use near_sdk::{env, near_bindgen, require, AccountId, Gas, Promise, PromiseError};
+use near_sdk::{PanicOnDefault, PromiseOrValue};
 use sha2::{Digest, Sha512};
+use std::collections::HashMap;
+pub mod batch;
+pub mod epochs;
+pub mod escrow;
 pub mod events;
 pub mod external;
+pub mod factory;
+pub mod fees;
+pub mod ft_fees;
+pub mod merkle;
+pub mod metadata;
+pub mod multi_token;
+pub mod rate_limits;
+pub mod refunds;
+pub mod registration;
+pub mod replay;
+pub mod royalties;
+pub mod storage_pool;
+#[cfg(test)]
+mod test_utils;
+pub mod timelock;
+pub mod vouchers;
+pub mod whitelist;
+pub use crate::batch::*;
+pub use crate::epochs::*;
+pub use crate::escrow::*;
 pub use crate::events::*;
 pub use crate::external::*;
-
-const GAS_FOR_FREEZE_NFT: Gas = Gas(45_000_000_000_000);
+pub use crate::factory::*;
+pub use crate::fees::*;
+pub use crate::ft_fees::*;
+pub use crate::merkle::*;
+pub use crate::metadata::*;
+pub use crate::multi_token::*;
+pub use crate::rate_limits::*;
+pub use crate::registration::*;
+pub use crate::replay::*;
+pub use crate::royalties::*;
+pub use crate::storage_pool::*;
+pub use crate::timelock::*;
+pub use crate::vouchers::*;
+pub use crate::whitelist::*;
+
+/// Nonce of the NEAR chain in the bridge. Also the namespace
+/// of the action ids signed for this contract itself.
+pub const CHAIN_NONCE: u8 = 31;
+
+const GAS_FOR_FREEZE_NFT: Gas = Gas(60_000_000_000_000);
 const GAS_FOR_WITHDRAW_NFT: Gas = Gas(65_000_000_000_000);
 const GAS_FOR_VALIDATE_TRANSFER: Gas = Gas(35_000_000_000_000);
 const GAS_FOR_VALIDATE_WITHDRAW: Gas = Gas(35_000_000_000_000);
@@ -44,7 +86,15 @@ pub struct UpdateGroupkeyData {
 #[serde(crate = "near_sdk::serde")]
 pub struct WhitelistData {
     action_id: U128,
-    token_contract: String,
+    token_contract: AccountId,
+    notes: Option<String>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BlacklistData {
+    action_id: U128,
+    token_contract: AccountId,
 }
 
 #[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
@@ -69,31 +119,165 @@ pub struct TransferTx {
 #[serde(crate = "near_sdk::serde")]
 pub struct TransferNftData {
     action_id: U128,
+    chain_nonce: u8,
+    tx_hash: String,
+    origin_contract: String,
+    origin_token_id: String,
     mint_with: AccountId,
     token_id: TokenId,
     owner_id: AccountId,
     token_metadata: TokenMetadata,
+    royalty: Option<HashMap<AccountId, u32>>,
+}
+
+impl TransferNftData {
+    /// Provenance of the token minted by this transfer.
+    fn origin(&self) -> TokenOrigin {
+        TokenOrigin {
+            chain_nonce: self.chain_nonce,
+            contract: self.origin_contract.clone(),
+            token_id: self.origin_token_id.clone(),
+            action_id: self.action_id,
+        }
+    }
+
+    /// Event emitted once the NFT of this transfer is minted.
+    fn validated_event(&self) -> TransferValidatedEvent {
+        TransferValidatedEvent {
+            chain_nonce: self.chain_nonce,
+            tx_hash: self.tx_hash.clone(),
+            action_id: self.action_id.0,
+            contract: self.mint_with.clone(),
+            token_id: self.token_id.clone(),
+            owner_id: self.owner_id.clone(),
+            quantity: None,
+        }
+    }
+}
+
+/// Provenance of a wrapped token, stored by xpnft at mint.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenOrigin {
+    pub chain_nonce: u8,
+    pub contract: String,
+    pub token_id: String,
+    pub action_id: U128,
 }
 
 #[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct UnfreezeNftData {
     action_id: U128,
+    chain_nonce: u8,
+    tx_hash: String,
     token_contract: AccountId,
     token_id: TokenId,
     receiver_id: AccountId,
 }
 
+impl UnfreezeNftData {
+    /// Event emitted once the NFT of this unfreeze is transferred.
+    fn validated_event(&self) -> UnfreezeValidatedEvent {
+        UnfreezeValidatedEvent {
+            chain_nonce: self.chain_nonce,
+            tx_hash: self.tx_hash.clone(),
+            action_id: self.action_id.0,
+            contract: self.token_contract.clone(),
+            token_id: self.token_id.clone(),
+            receiver_id: self.receiver_id.clone(),
+            quantity: None,
+        }
+    }
+}
+
+/// Freeze of an NFT, passed along the callbacks of `freeze_nft`.
+/// `amt` is the fee of the freeze.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeRequest {
+    pub token_contract: AccountId,
+    pub token_id: TokenId,
+    pub chain_nonce: u8,
+    pub to: String,
+    pub mint_with: String,
+    pub amt: U128,
+    pub sender: AccountId,
+}
+
+/// Withdraw of a wrapped NFT, passed along the callbacks of `withdraw_nft`.
+/// `amt` is the fee of the withdraw.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawRequest {
+    pub token_contract: AccountId,
+    pub token_id: TokenId,
+    pub chain_nonce: u8,
+    pub to: String,
+    pub amt: U128,
+    pub sender: AccountId,
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct XpBridge {
+    consumed_actions: ReplayGuard,
+    paused: bool,
+    tx_fees: u128,
+    group_key: [u8; 32],
+    fee_pk: [u8; 32],
+    action_cnt: u128,
+    whitelist: UnorderedSet<AccountId>,
+    fee_split: Vec<FeeBeneficiary>,
+    accrued_fees: LookupMap<AccountId, u128>,
+    total_accrued_fees: u128,
+    fee_tokens: UnorderedSet<AccountId>,
+    ft_fees: LookupMap<AccountId, u128>,
+    transfer_roots: LookupSet<[u8; 32]>,
+    vouchers: LookupMap<(u8, u128), VoucherRecord>,
+    storage_pool: LookupMap<AccountId, u128>,
+    total_storage_pool: u128,
+    storage_sponsors: LookupMap<AccountId, AccountId>,
+    mint_storage_cost: u128,
+    pending_refunds: LookupMap<AccountId, u128>,
+    total_pending_refunds: u128,
+    pending_ft_refunds: LookupMap<(AccountId, AccountId), u128>,
+    freezes: LookupMap<u128, FreezeRecord>,
+    frozen_tokens: LookupMap<(AccountId, TokenId), u128>,
+    delivery_timeouts: LookupMap<u8, u64>,
+    rate_limits: RateLimiter,
+    queued_actions: LookupMap<u64, QueuedAction>,
+    queued_cnt: u64,
+    total_queued_deposits: u128,
+    timelocks: LookupMap<u64, TimelockEntry>,
+    timelock_cnt: u64,
+    timelock_delay: u64,
+    guardians: LookupSet<AccountId>,
+    group_key_epochs: Vector<GroupKeyEpoch>,
+    key_grace_period: u64,
+    whitelist_entries: LookupMap<AccountId, WhitelistEntry>,
+    permissionless_collections: LookupSet<AccountId>,
+    permissionless_mode: bool,
+    registration_fee: u128,
+    registered_collections: LookupSet<AccountId>,
+    blocked_collections: LookupSet<AccountId>,
+    collection_code_hash: Option<[u8; 32]>,
+    collections: LookupMap<(u8, String), AccountId>,
+    collection_origins: LookupMap<AccountId, CollectionOrigin>,
+}
+
+/// State of the bridge before the consumed action ids were namespaced
+/// by chain, read by `migrate`. The strings of the whitelist are read
+/// as the account ids they were checked against.
+#[derive(BorshDeserialize)]
+struct OldXpBridge {
     consumed_actions: UnorderedSet<u128>,
     paused: bool,
     tx_fees: u128,
     group_key: [u8; 32],
     fee_pk: [u8; 32],
     action_cnt: u128,
-    whitelist: UnorderedSet<String>,
+    whitelist: UnorderedSet<AccountId>,
 }
 
 #[near_bindgen]
@@ -108,33 +292,84 @@ impl XpBridge {
             "Unauthorized"
         );
 
-        Self {
-            consumed_actions: UnorderedSet::new(b"c"),
-            paused: false,
-            fee_pk,
-            tx_fees: 0,
-            group_key,
-            action_cnt: 0,
-            whitelist: UnorderedSet::new(b"w"),
-        }
+        Self::new(group_key, fee_pk)
+    }
+
+    /// Migrates the state of a bridge deployed before the consumed action
+    /// ids were namespaced by chain. The consumed action ids, the fees, the
+    /// action count and the whitelist are kept, everything else starts with
+    /// the defaults of `initialize`.
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        assert!(
+            env::current_account_id() == env::predecessor_account_id(),
+            "Unauthorized"
+        );
+
+        let old: OldXpBridge =
+            env::state_read().unwrap_or_else(|| env::panic_str("No state to migrate"));
+        let mut bridge = Self::new(old.group_key, old.fee_pk);
+        bridge.consumed_actions = ReplayGuard::from_legacy(b"c", old.consumed_actions);
+        bridge.paused = old.paused;
+        bridge.tx_fees = old.tx_fees;
+        bridge.action_cnt = old.action_cnt;
+        bridge.whitelist = old.whitelist;
+        bridge
     }
 
     /// Ed25519 Signature verification logic.
     /// Signature check for bridge actions.
-    /// Consumes the passed action_id.
+    /// Consumes the passed action_id in the namespace of this chain.
+    /// Only the current group key is accepted.
     fn require_sig(&mut self, action_id: u128, data: Vec<u8>, sig_data: Vec<u8>, context: &[u8]) {
-        let f = self.consumed_actions.contains(&action_id);
-        require!(!f, "Duplicated Action");
+        self.consume_action(CHAIN_NONCE, action_id);
+        self.require_group_sig(data, sig_data, context);
+    }
+
+    /// Ed25519 Signature verification logic.
+    /// Signature check for transfers and unfreezes coming from a foreign chain.
+    /// Consumes the passed action_id in the namespace of `chain_nonce`.
+    fn require_chain_sig(
+        &mut self,
+        chain_nonce: u8,
+        action_id: u128,
+        data: Vec<u8>,
+        sig_data: Vec<u8>,
+        context: &[u8],
+    ) {
+        self.consume_action(chain_nonce, action_id);
+        self.require_transfer_sig(data, sig_data, context);
+    }
+
+    /// Marks the passed action_id of the chain as consumed.
+    /// FAILS: If the action_id was already consumed.
+    fn consume_action(&mut self, chain_nonce: u8, action_id: u128) {
+        self.consumed_actions.consume(chain_nonce, action_id);
+    }
 
-        self.consumed_actions.insert(&action_id);
+    /// Ed25519 Signature verification logic.
+    /// Checks that the data was signed by the current group key.
+    fn require_group_sig(&self, data: Vec<u8>, sig_data: Vec<u8>, context: &[u8]) {
+        self.verify_group_sig(data, sig_data, context, false);
+    }
 
+    /// Ed25519 Signature verification logic.
+    /// Checks that the data was signed by the group key, or by the key
+    /// of the epoch the signature names if it is still in its grace period.
+    /// Only incoming transfers and unfreezes may use the previous key.
+    fn require_transfer_sig(&self, data: Vec<u8>, sig_data: Vec<u8>, context: &[u8]) {
+        self.verify_group_sig(data, sig_data, context, true);
+    }
+
+    fn verify_group_sig(&self, data: Vec<u8>, sig_data: Vec<u8>, context: &[u8], grace: bool) {
         let mut hasher = Sha512::new();
         hasher.update(context);
         hasher.update(data);
         let hash = hasher.finalize();
 
-        let sig = Signature::new(sig_data.as_slice().try_into().unwrap());
-        let key = PublicKey::new(self.group_key);
+        let (group_key, sig_data) = self.signing_group_key(&sig_data, grace);
+        let sig = Signature::new(sig_data.try_into().unwrap());
+        let key = PublicKey::new(group_key);
         let res = key.verify(hash, &sig);
         require!(res.is_ok(), "Unauthorized Action");
     }
@@ -173,9 +408,16 @@ impl XpBridge {
 
     /// Withdraws the fees collected by the contract on NFT transfers.
     /// to the account_id provied in the {WithdrawFeeData}.
+    /// If a fee split is configured, the fees are instead credited to the
+    /// beneficiaries of the split, who can claim them with `claim_fees`.
+    /// Scheduled with the timelock if a delay is set.
     /// FAILS: If contract is paused.
     /// REQUIRED: Signature verification.
-    pub fn validate_withdraw_fees(&mut self, data: WithdrawFeeData, sig_data: Vec<u8>) -> Promise {
+    pub fn validate_withdraw_fees(
+        &mut self,
+        data: WithdrawFeeData,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
         require!(!self.paused, "paused");
         require!(
             env::prepaid_gas() >= GAS_FOR_VALIDATE_WITHDRAW,
@@ -189,13 +431,11 @@ impl XpBridge {
             b"WithdrawFees",
         );
 
-        let storage_used = env::storage_usage();
-        let amt =
-            (env::account_balance() - storage_used as u128 * env::storage_byte_cost()) - ONE_NEAR;
-        Promise::new(data.account_id).transfer(amt).then(
-            Self::ext(env::current_account_id())
-                .with_static_gas(Gas(TGAS * 15))
-                .withdraw_fee_callback(data.action_id.0),
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::WithdrawFees {
+                account_id: data.account_id,
+            },
         )
     }
 
@@ -210,7 +450,7 @@ impl XpBridge {
     ) {
         match call_result {
             Err(e) => {
-                self.consumed_actions.remove(&action_id);
+                self.consumed_actions.release(CHAIN_NONCE, action_id);
                 env::log_str(&format!(
                     "validate transfer callback: failed to transfer tokens: actionid: {} : {:?}",
                     action_id, e
@@ -223,6 +463,7 @@ impl XpBridge {
         }
     }
     /// Updates the Group Key for the contract.
+    /// Scheduled with the timelock if a delay is set.
     /// FAILS: If contract is paused.
     /// REQUIRED: Signature verification.
     pub fn validate_update_group_key(&mut self, data: UpdateGroupkeyData, sig_data: Vec<u8>) {
@@ -235,9 +476,18 @@ impl XpBridge {
             b"SetGroupKey",
         );
 
-        self.group_key = data.group_key;
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::UpdateGroupKey {
+                group_key: data.group_key,
+            },
+        );
     }
 
+    /// Updates the public key of the fee signatures.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
     pub fn validate_update_fee_public_key(&mut self, data: UpdateGroupkeyData, sig_data: Vec<u8>) {
         require!(!self.paused, "paused");
 
@@ -248,19 +498,25 @@ impl XpBridge {
             b"SetFeePublicKey",
         );
 
-        self.fee_pk = data.group_key;
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::UpdateFeePublicKey {
+                fee_pk: data.group_key,
+            },
+        );
     }
     /// Updates the whitelist for the contract.
     /// Adds the provided account_id to the whitelist
     /// so that they can be freezed for transfers to work
-    /// in the bridge
+    /// in the bridge. The action id and notes are recorded
+    /// as metadata of the whitelist entry.
     /// FAILS: If contract is paused.
     /// REQUIRED: Signature verification.
     pub fn validate_whitelist(&mut self, data: WhitelistData, sig_data: Vec<u8>) {
         require!(!self.paused, "paused");
 
         require!(
-            !self.whitelist.contains(&data.token_contract.to_string()),
+            !self.whitelist.contains(&data.token_contract),
             "Already whitelist"
         );
 
@@ -271,19 +527,20 @@ impl XpBridge {
             b"WhitelistNft",
         );
 
-        self.whitelist.insert(&data.token_contract);
+        self.internal_whitelist(&data.token_contract, data.action_id.0, data.notes);
     }
     /// Updates the whitelist for the contract.
     /// Removes the provided account_id from the whitelist
     /// so that they cannot be freezed for transfers to work
     /// in the bridge
+    /// Scheduled with the timelock if a delay is set.
     /// FAILS: If contract is paused AND if the contract is not present in whitelist.
     /// REQUIRED: Signature verification.
-    pub fn validate_blacklist(&mut self, data: WhitelistData, sig_data: Vec<u8>) {
+    pub fn validate_blacklist(&mut self, data: BlacklistData, sig_data: Vec<u8>) {
         require!(!self.paused, "paused");
 
         require!(
-            self.whitelist.contains(&data.token_contract.to_string()),
+            self.whitelist.contains(&data.token_contract),
             "Not whitelist"
         );
 
@@ -294,57 +551,93 @@ impl XpBridge {
             b"ValidateBlacklistNft",
         );
 
-        self.whitelist.remove(&data.token_contract);
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::Blacklist {
+                token_contract: data.token_contract,
+            },
+        );
     }
 
     /// Validates the transfer of NFT from the bridge to the destination chain.
     /// It mints a new NEP-171 token on chain to the destination account_id.
-    /// FAILS: If contract is paused.
+    /// If no deposit is attached, the storage of the token is paid from
+    /// the storage pool of the sponsor of the collection.
+    /// Above the rate limits of the chain or the collection, the transfer
+    /// is queued and can be executed with `execute_queued_action` later.
+    /// FAILS: If contract is paused, `mint_with` doesn't match the wrapped
+    /// collection registered for `origin_contract` or the `media_hash` or
+    /// `reference_hash` of the metadata is not a sha256 hash.
     /// REQUIRED: Signature verification.
     #[payable]
-    pub fn validate_transfer_nft(&mut self, data: TransferNftData, sig_data: Vec<u8>) -> Promise {
+    pub fn validate_transfer_nft(
+        &mut self,
+        data: TransferNftData,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
         require!(
             env::prepaid_gas() >= GAS_FOR_VALIDATE_TRANSFER,
             "Not enough gas"
         );
         require!(!self.paused, "paused");
 
-        self.require_sig(
+        self.require_chain_sig(
+            data.chain_nonce,
             data.action_id.into(),
             data.try_to_vec().unwrap(),
-            sig_data.into(),
+            sig_data,
             b"ValidateTransferNft",
         );
+        self.require_collection_origin(&data);
+        require_valid_token_metadata(&data.token_metadata);
+
+        let (deposit, sponsor) =
+            self.internal_mint_deposit(&data.mint_with, env::attached_deposit());
+
+        if !self
+            .rate_limits
+            .try_acquire(data.chain_nonce, &data.mint_with)
+        {
+            self.internal_queue_action(
+                data.chain_nonce,
+                data.action_id.0,
+                data.mint_with.clone(),
+                QueuedBridgeAction::TransferNft {
+                    data: Box::new(data),
+                    deposit: U128(deposit),
+                    sponsor,
+                    sender: env::predecessor_account_id(),
+                },
+            );
+            return PromiseOrValue::Value(());
+        }
 
-        xpnft::ext(data.mint_with)
-            .with_attached_deposit(env::attached_deposit())
-            .with_static_gas(Gas(TGAS * 10))
-            .nft_mint(data.token_id, data.owner_id, data.token_metadata)
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas(TGAS * 10))
-                    .validate_transfer_callback(data.action_id.0),
-            )
+        self.internal_transfer_nft(data, deposit, sponsor, env::predecessor_account_id())
+            .into()
     }
 
     // This is the callback function when the promise in the validate_unfreeze_nft
     /// function is completed. It will check if the promise result was
-    /// successful or not.
+    /// successful or not. The deposit of a failed mint is returned to the
+    /// sponsor or the sender which paid it.
     #[private]
     pub fn validate_transfer_callback(
         &mut self,
-        action_id: u128,
+        event: TransferValidatedEvent,
+        sponsor: Option<AccountId>,
+        sender: AccountId,
+        deposit: u128,
         #[callback_result] call_result: Result<Token, PromiseError>,
     ) {
-        let _res = match call_result {
-            Ok(_) => {
-                // Do Nothing
-            }
+        match call_result {
+            Ok(_) => event.emit(),
             Err(e) => {
-                self.consumed_actions.remove(&action_id);
+                self.consumed_actions
+                    .release(event.chain_nonce, event.action_id);
+                self.internal_refund_mint_deposit(sponsor, sender, deposit);
                 env::log_str(&format!(
                     "validate transfer callback: failed to mint nft: actionid: {} : {:?}",
-                    action_id, e
+                    event.action_id, e
                 ))
             }
         };
@@ -367,11 +660,11 @@ impl XpBridge {
         require!(env::prepaid_gas() >= GAS_FOR_WITHDRAW_NFT, "Not enough gas");
         require!(!self.paused, "paused");
 
-        return Self::ext(env::current_account_id())
+        Self::ext(env::current_account_id())
             .verify_paid_amount_by_sig(
                 TransferTx {
-                    value: env::attached_deposit().into(),
-                    from_chain: 31,
+                    value: env::attached_deposit(),
+                    from_chain: CHAIN_NONCE,
                     to_chain: chain_nonce,
                     to: to.clone(),
                     token_contract: token_contract.clone(),
@@ -382,48 +675,36 @@ impl XpBridge {
             .then(
                 Self::ext(env::current_account_id())
                     .with_static_gas(Gas(TGAS * 45))
-                    .check_enough_fees_callback_for_withdraw(
+                    .check_enough_fees_callback_for_withdraw(WithdrawRequest {
                         token_contract,
                         token_id,
                         chain_nonce,
                         to,
-                        env::attached_deposit(),
-                        env::signer_account_id(),
-                    ),
-            );
+                        amt: U128(env::attached_deposit()),
+                        sender: env::signer_account_id(),
+                    }),
+            )
     }
 
     #[private]
     pub fn check_enough_fees_callback_for_withdraw(
-        &self,
-        token_contract: AccountId,
-        token_id: TokenId,
-        chain_nonce: u8,
-        to: String,
-        amt: u128,
-        sender: AccountId,
+        &mut self,
+        request: WithdrawRequest,
         #[callback_result] call_result: Result<(), PromiseError>,
     ) {
         match call_result {
             Ok(_) => {
-                xpnft::ext(token_contract.clone())
+                xpnft::ext(request.token_contract.clone())
                     .with_static_gas(Gas(5 * TGAS))
-                    .nft_token(token_id.clone())
+                    .nft_token(request.token_id.clone())
                     .then(
                         Self::ext(env::current_account_id())
-                            .with_static_gas(Gas(TGAS * 25))
-                            .token_callback(
-                                token_contract,
-                                token_id,
-                                sender,
-                                chain_nonce,
-                                to,
-                                env::attached_deposit(),
-                            ),
+                            .with_static_gas(Gas(TGAS * 31))
+                            .token_callback(request),
                     );
             }
             Err(e) => {
-                Promise::new(sender).transfer(amt);
+                self.internal_refund(request.sender, request.amt.0);
                 env::log_str(&format!(
                     "withdraw callback: failed to transfer nft: failed to get tx fee :actionid: {} : {:?}",
                     self.action_cnt, e
@@ -439,33 +720,21 @@ impl XpBridge {
     #[private]
     pub fn token_callback(
         &mut self,
-        token_contract: AccountId,
-        token_id: TokenId,
-        owner_id: AccountId,
-        chain_nonce: u8,
-        to: String,
-        amt: u128,
+        request: WithdrawRequest,
         #[callback_result] call_result: Result<Option<Token>, PromiseError>,
     ) -> Promise {
         match call_result {
-            Ok(_) => xpnft::ext(token_contract.clone())
+            Ok(token) => xpnft::ext(request.token_contract.clone())
                 .with_static_gas(Gas(TGAS * 10))
-                .nft_burn(token_id.clone(), owner_id)
+                .nft_burn(request.token_id.clone(), request.sender.clone())
                 .then(
                     Self::ext(env::current_account_id())
-                        .with_static_gas(Gas(TGAS * 8))
-                        .withdraw_callback(
-                            token_contract,
-                            call_result.unwrap(),
-                            chain_nonce,
-                            to,
-                            amt.into(),
-                            env::predecessor_account_id(),
-                        ),
+                        .with_static_gas(Gas(TGAS * 14))
+                        .withdraw_callback(request, token),
                 ),
             Err(_) => {
                 // Return funds
-                Promise::new(env::signer_account_id()).transfer(amt)
+                self.internal_refund(request.sender, request.amt.0)
             }
         }
     }
@@ -476,31 +745,29 @@ impl XpBridge {
     #[private]
     pub fn withdraw_callback(
         &mut self,
-        token_contract: AccountId,
+        request: WithdrawRequest,
         token: Option<Token>,
-        chain_nonce: u8,
-        to: String,
-        amt: u128,
-        sender: AccountId,
         #[callback_result] call_result: Result<(), PromiseError>,
     ) {
         match call_result {
             Ok(_) => {
                 self.action_cnt += 1;
-                self.tx_fees += amt;
+                self.tx_fees += request.amt.0;
 
                 UnfreezeNftEvent {
                     action_id: self.action_cnt,
-                    chain_nonce,
-                    to,
-                    amt,
-                    contract: token_contract,
+                    chain_nonce: request.chain_nonce,
+                    to: request.to,
+                    amt: request.amt.0,
+                    contract: request.token_contract,
                     token,
+                    fee_token: None,
+                    quantity: None,
                 }
                 .emit();
             }
             Err(e) => {
-                Promise::new(sender).transfer(amt);
+                self.internal_refund(request.sender, request.amt.0);
                 env::log_str(&format!(
                     "validate withdraw callback: failed to burn nft:  actionid: {} : {:?}",
                     self.action_cnt, e
@@ -512,6 +779,11 @@ impl XpBridge {
     /// Freezes the NFT on the bridge contract. NFT is transferred to this
     /// bridge contract with a promise and then on completion of the promise
     /// the callback function `freeze_callback` is called.
+    /// In permissionless mode, the first freeze of an NFT of a collection
+    /// which is not whitelisted registers the collection. The registration
+    /// fee is returned to the sender if the NFT is not frozen.
+    /// FAILS: If contract is paused, the collection is blocked or it is
+    /// not whitelisted outside of permissionless mode.
     #[payable]
     pub fn freeze_nft(
         &mut self,
@@ -524,68 +796,76 @@ impl XpBridge {
     ) -> Promise {
         require!(env::prepaid_gas() >= GAS_FOR_FREEZE_NFT, "Not enough gas");
         require!(!self.paused, "paused");
+        require!(
+            !self.blocked_collections.contains(&token_contract),
+            "Collection blocked"
+        );
 
-        return Self::ext(env::current_account_id())
-            .verify_paid_amount_by_sig(
-                TransferTx {
-                    value: env::attached_deposit().into(),
-                    from_chain: 31,
-                    to_chain: chain_nonce,
-                    to: to.clone(),
-                    token_contract: token_contract.clone(),
-                    token_id: token_id.clone(),
-                },
-                sig_data,
-            )
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas(TGAS * 30))
-                    .check_enough_fees_callback_for_transfer(
+        if !self.is_bridgeable(&token_contract) {
+            let amt = self.internal_take_registration_fees(1);
+            return self.internal_register_collections(
+                vec![token_contract.clone()],
+                PendingFreeze::Nft {
+                    request: FreezeRequest {
                         token_contract,
                         token_id,
                         chain_nonce,
                         to,
                         mint_with,
-                        env::attached_deposit(),
-                        env::signer_account_id(),
-                    ),
+                        amt: U128(amt),
+                        sender: env::signer_account_id(),
+                    },
+                    sig_data,
+                },
             );
+        }
+
+        self.internal_freeze_nft(
+            FreezeRequest {
+                token_contract,
+                token_id,
+                chain_nonce,
+                to,
+                mint_with,
+                amt: U128(env::attached_deposit()),
+                sender: env::signer_account_id(),
+            },
+            sig_data,
+            None,
+        )
     }
 
     #[private]
     pub fn check_enough_fees_callback_for_transfer(
         &mut self,
-        token_contract: AccountId,
-        token_id: TokenId,
-        chain_nonce: u8,
-        to: String,
-        mint_with: String,
-        amt: u128,
-        sender: AccountId,
+        request: FreezeRequest,
+        registration: Option<PendingRegistration>,
         #[callback_result] call_result: Result<(), PromiseError>,
     ) {
         match call_result {
             Ok(_) => {
-                common_nft::ext(token_contract.clone())
+                common_nft::ext(request.token_contract.clone())
                     .with_attached_deposit(1)
                     .with_static_gas(Gas(TGAS * 10))
-                    .nft_transfer(env::current_account_id(), token_id.clone(), None, None)
+                    .nft_transfer(
+                        env::current_account_id(),
+                        request.token_id.clone(),
+                        None,
+                        None,
+                    )
+                    .and(nft_payout(
+                        request.token_contract.clone(),
+                        request.token_id.clone(),
+                    ))
                     .then(
                         Self::ext(env::current_account_id())
-                            .with_static_gas(Gas(TGAS * 8))
-                            .freeze_callback(
-                                token_contract,
-                                token_id,
-                                chain_nonce,
-                                to,
-                                mint_with,
-                                amt,
-                                sender,
-                            ),
+                            .with_static_gas(Gas(TGAS * 15))
+                            .freeze_callback(request, registration),
                     );
             }
             Err(e) => {
-                Promise::new(sender).transfer(amt);
+                let fees = self.internal_settle_registrations(registration, |_| false);
+                self.internal_refund(request.sender, request.amt.0 + fees);
                 env::log_str(&format!(
                     "freeze callback: failed to transfer nft: failed to verify tx fee :actionid: {} : {:?}",
                     self.action_cnt, e
@@ -597,37 +877,44 @@ impl XpBridge {
     /// This is the callback function when the promise in the freeze_nft
     /// function is completed. It will check if the promise result was
     /// successful or not. If it was successful, it will emit a TransferNftEvent
-    /// event.
+    /// event with the royalties of the NFT on NEAR and record the collection
+    /// registered by the freeze, otherwise the fees are returned to the sender.
     #[private]
     pub fn freeze_callback(
         &mut self,
-        token_contract: AccountId,
-        token_id: TokenId,
-        chain_nonce: u8,
-        to: String,
-        mint_with: String,
-        amt: u128,
-        sender: AccountId,
+        request: FreezeRequest,
+        registration: Option<PendingRegistration>,
         #[callback_result] call_result: Result<(), PromiseError>,
+        #[callback_result] payout: Result<Payout, PromiseError>,
     ) {
         match call_result {
             Ok(_) => {
+                let fees = self.internal_settle_registrations(registration, |_| true);
+                if fees > 0 {
+                    self.internal_refund(request.sender.clone(), fees);
+                }
+                let royalty = royalty_from_payout(payout.ok());
                 self.action_cnt += 1;
-                self.tx_fees += amt;
+                self.tx_fees += request.amt.0;
+                self.internal_record_freeze(self.action_cnt, request.clone(), None);
 
                 TransferNftEvent {
                     action_id: self.action_cnt,
-                    chain_nonce,
-                    to,
-                    amt,
-                    contract: token_contract,
-                    token_id,
-                    mint_with,
+                    chain_nonce: request.chain_nonce,
+                    to: request.to,
+                    amt: request.amt.0,
+                    contract: request.token_contract,
+                    token_id: request.token_id,
+                    mint_with: request.mint_with,
+                    fee_token: None,
+                    royalty,
+                    quantity: None,
                 }
                 .emit();
             }
             Err(e) => {
-                Promise::new(sender).transfer(amt);
+                let fees = self.internal_settle_registrations(registration, |_| false);
+                self.internal_refund(request.sender, request.amt.0 + fees);
                 env::log_str(&format!(
                     "freeze callback: failed to transfer nft: actionid: {} : {:?}",
                     self.action_cnt, e
@@ -639,93 +926,77 @@ impl XpBridge {
     /// This function unfreezes the NFT on the bridge contract.
     /// It will transfer the NFT from this contract to the receiver
     /// contract.
-    pub fn validate_unfreeze_nft(&mut self, data: UnfreezeNftData, sig_data: Vec<u8>) -> Promise {
+    /// The escrow record of the freeze of the NFT is removed once the NFT
+    /// is transferred, so it can still be reclaimed while the unfreeze is
+    /// queued or if the transfer fails.
+    /// Above the rate limits of the chain or the collection, the unfreeze
+    /// is queued and can be executed with `execute_queued_action` later.
+    pub fn validate_unfreeze_nft(
+        &mut self,
+        data: UnfreezeNftData,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
         require!(
             env::prepaid_gas() >= GAS_FOR_VALIDATE_UNFREEZE,
             "Not enough gas"
         );
         require!(!self.paused, "paused");
 
-        require!(
-            self.whitelist
-                .contains(&data.token_contract.clone().to_string()),
-            "Not whitelist"
-        );
+        self.require_bridgeable(&data.token_contract);
 
-        self.require_sig(
+        self.require_chain_sig(
+            data.chain_nonce,
             data.action_id.into(),
             data.try_to_vec().unwrap(),
             sig_data,
             b"ValidateUnfreezeNft",
         );
 
-        common_nft::ext(data.token_contract)
-            .with_static_gas(Gas(TGAS * 10))
-            .nft_transfer(data.receiver_id, data.token_id, None, None)
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(Gas(TGAS * 10))
-                    .validate_unfreeze_callback(data.action_id.0),
-            )
+        if !self
+            .rate_limits
+            .try_acquire(data.chain_nonce, &data.token_contract)
+        {
+            self.internal_queue_action(
+                data.chain_nonce,
+                data.action_id.0,
+                data.token_contract.clone(),
+                QueuedBridgeAction::UnfreezeNft { data },
+            );
+            return PromiseOrValue::Value(());
+        }
+
+        self.internal_unfreeze_nft(data).into()
     }
 
     /// This is the callback function when the promise in the validate_unfreeze_nft
     /// function is completed. It will check if the promise result was
-    /// successful or not.
+    /// successful or not. The escrow record of the NFT is removed if it was.
     #[private]
     pub fn validate_unfreeze_callback(
         &mut self,
-        action_id: u128,
+        event: UnfreezeValidatedEvent,
         #[callback_result] call_result: Result<(), PromiseError>,
     ) {
-        let _res = match call_result {
+        match call_result {
             Ok(_) => {
-                // Do Nothing
+                self.internal_close_freeze(&event.contract, &event.token_id);
+                event.emit()
             }
             Err(e) => {
-                self.consumed_actions.remove(&action_id);
+                self.consumed_actions
+                    .release(event.chain_nonce, event.action_id);
                 env::log_str(&format!(
                     "validate unfreeze callback: failed to transfer nft: action id: {}: {:?}",
-                    action_id, e
+                    event.action_id, e
                 ))
             }
         };
     }
 
-    /// This function takes all the parameters of the TransferNftData
-    /// and then encodes into Bytes (Vec<u8>) which is consumed by the
-    /// validator for signing the transaction.
-    pub fn encode_transfer_action(
-        &self,
-        action_id: U128,
-        mint_with: AccountId,
-        owner_id: AccountId,
-        token_id: String,
-        title: String,
-        description: String,
-        media: String,
-        extra: String,
-    ) -> Vec<u8> {
-        let data = TransferNftData {
-            action_id,
-            mint_with,
-            owner_id,
-            token_id,
-            token_metadata: TokenMetadata {
-                title: Some(title),
-                description: Some(description),
-                media: Some(media),
-                media_hash: None,
-                copies: None,
-                issued_at: None,
-                expires_at: None,
-                starts_at: None,
-                updated_at: None,
-                extra: Some(extra),
-                reference: None,
-                reference_hash: None,
-            },
-        };
+    /// This function takes the TransferNftData and then encodes it into
+    /// Bytes (Vec<u8>) which is consumed by the validator for signing
+    /// the transaction.
+    pub fn encode_transfer_action(&self, data: TransferNftData) -> Vec<u8> {
         data.try_to_vec().unwrap()
     }
 
@@ -735,12 +1006,16 @@ impl XpBridge {
     pub fn encode_unfreeze_action(
         &self,
         action_id: U128,
+        chain_nonce: u8,
+        tx_hash: String,
         token_id: String,
         receiver_id: AccountId,
         token_contract: AccountId,
     ) -> Vec<u8> {
         let event = UnfreezeNftData {
             action_id,
+            chain_nonce,
+            tx_hash,
             token_id,
             receiver_id,
             token_contract,
@@ -756,7 +1031,7 @@ impl XpBridge {
     /// Checks if the contract provided in `contract_id` is whitelisted
     /// or not.
     /// Returns boolean
-    pub fn is_whitelist(&self, contract_id: String) -> bool {
+    pub fn is_whitelist(&self, contract_id: AccountId) -> bool {
         self.whitelist.contains(&contract_id)
     }
 
@@ -772,13 +1047,450 @@ impl XpBridge {
 
     #[private]
     pub fn verify_paid_amount_by_sig(&self, data: TransferTx, sig_data: Vec<u8>) {
+        self.require_fee_sig(data.try_to_vec().unwrap(), sig_data, b"");
+    }
+}
+
+impl XpBridge {
+    fn new(group_key: [u8; 32], fee_pk: [u8; 32]) -> Self {
+        let mut bridge = Self {
+            consumed_actions: ReplayGuard::new(b"c"),
+            paused: false,
+            fee_pk,
+            tx_fees: 0,
+            group_key,
+            action_cnt: 0,
+            whitelist: UnorderedSet::new(b"w"),
+            fee_split: Vec::new(),
+            accrued_fees: LookupMap::new(b"a"),
+            total_accrued_fees: 0,
+            fee_tokens: UnorderedSet::new(b"t"),
+            ft_fees: LookupMap::new(b"x"),
+            transfer_roots: LookupSet::new(b"r"),
+            vouchers: LookupMap::new(b"v"),
+            storage_pool: LookupMap::new(b"s"),
+            total_storage_pool: 0,
+            storage_sponsors: LookupMap::new(b"p"),
+            mint_storage_cost: DEFAULT_MINT_STORAGE_COST,
+            pending_refunds: LookupMap::new(b"f"),
+            total_pending_refunds: 0,
+            pending_ft_refunds: LookupMap::new(b"z"),
+            freezes: LookupMap::new(b"e"),
+            frozen_tokens: LookupMap::new(b"n"),
+            delivery_timeouts: LookupMap::new(b"d"),
+            rate_limits: RateLimiter::new(b"l"),
+            queued_actions: LookupMap::new(b"q"),
+            queued_cnt: 0,
+            total_queued_deposits: 0,
+            timelocks: LookupMap::new(b"k"),
+            timelock_cnt: 0,
+            timelock_delay: DEFAULT_TIMELOCK_DELAY,
+            guardians: LookupSet::new(b"g"),
+            group_key_epochs: Vector::new(b"h"),
+            key_grace_period: DEFAULT_KEY_GRACE_PERIOD,
+            whitelist_entries: LookupMap::new(b"m"),
+            permissionless_collections: LookupSet::new(b"u"),
+            permissionless_mode: false,
+            registration_fee: 0,
+            registered_collections: LookupSet::new(b"j"),
+            blocked_collections: LookupSet::new(b"b"),
+            collection_code_hash: None,
+            collections: LookupMap::new(b"y"),
+            collection_origins: LookupMap::new(b"i"),
+        };
+        bridge.group_key_epochs.push(&GroupKeyEpoch {
+            group_key,
+            activated_at: U64(env::block_height()),
+            expires_at: None,
+        });
+        bridge
+    }
+
+    /// Ed25519 Signature verification logic.
+    /// Signature check for the fee paid on bridge transfers.
+    /// {TransferTx} is signed with an empty context, every other
+    /// fee signature is prefixed with the name of its struct.
+    fn require_fee_sig(&self, data: Vec<u8>, sig_data: Vec<u8>, context: &[u8]) {
         let mut hasher = Sha512::new();
-        hasher.update(data.try_to_vec().unwrap());
+        hasher.update(context);
+        hasher.update(data);
         let hash = hasher.finalize();
         let sig = Signature::new(sig_data.as_slice().try_into().unwrap());
         let key = PublicKey::new(self.fee_pk);
-        let _ = key
-            .verify(hash, &sig)
+        key.verify(hash, &sig)
             .expect("Amount Signature Verification Failed");
     }
+
+    /// Withdraws the fees collected by the contract to `account_id`, or
+    /// credits them to the beneficiaries of the fee split if one is set.
+    pub(crate) fn internal_withdraw_fees(
+        &mut self,
+        action_id: u128,
+        account_id: AccountId,
+    ) -> PromiseOrValue<()> {
+        let amt = self.withdrawable_fees();
+        if !self.fee_split.is_empty() {
+            self.internal_split_fees(amt);
+            self.tx_fees = 0;
+            return PromiseOrValue::Value(());
+        }
+
+        Promise::new(account_id)
+            .transfer(amt)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 15))
+                    .withdraw_fee_callback(action_id),
+            )
+            .into()
+    }
+
+    /// Verifies the fee of a freeze and then transfers the NFT to this
+    /// bridge contract with `check_enough_fees_callback_for_transfer`.
+    fn internal_freeze_nft(
+        &mut self,
+        request: FreezeRequest,
+        sig_data: Vec<u8>,
+        registration: Option<PendingRegistration>,
+    ) -> Promise {
+        Self::ext(env::current_account_id())
+            .verify_paid_amount_by_sig(
+                TransferTx {
+                    value: request.amt.0,
+                    from_chain: CHAIN_NONCE,
+                    to_chain: request.chain_nonce,
+                    to: request.to.clone(),
+                    token_contract: request.token_contract.clone(),
+                    token_id: request.token_id.clone(),
+                },
+                sig_data,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 40))
+                    .check_enough_fees_callback_for_transfer(request, registration),
+            )
+    }
+
+    /// Mints the NFT of a validated transfer and then calls
+    /// the callback function 'validate_transfer_callback'.
+    fn internal_transfer_nft(
+        &mut self,
+        data: TransferNftData,
+        deposit: u128,
+        sponsor: Option<AccountId>,
+        sender: AccountId,
+    ) -> Promise {
+        let origin = data.origin();
+        let event = data.validated_event();
+        xpnft::ext(data.mint_with)
+            .with_attached_deposit(deposit)
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_mint(
+                data.token_id,
+                data.owner_id,
+                data.token_metadata,
+                data.royalty,
+                Some(origin),
+                sponsor.clone(),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .validate_transfer_callback(event, sponsor, sender, deposit),
+            )
+    }
+
+    /// Transfers the NFT of a validated unfreeze to the receiver and
+    /// then calls the callback function 'validate_unfreeze_callback'.
+    fn internal_unfreeze_nft(&mut self, data: UnfreezeNftData) -> Promise {
+        common_nft::ext(data.token_contract.clone())
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_transfer(data.receiver_id.clone(), data.token_id.clone(), None, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .validate_unfreeze_callback(data.validated_event()),
+            )
+    }
+}
+
+/// Checks that the hashes of the metadata of a transferred NFT are sha256
+/// hashes. Malformed base64 is already rejected when deserializing.
+/// FAILS: If `media_hash` or `reference_hash` is present and not 32 bytes.
+pub(crate) fn require_valid_token_metadata(metadata: &TokenMetadata) {
+    require!(
+        metadata
+            .media_hash
+            .as_ref()
+            .map_or(true, |h| h.0.len() == 32),
+        "Invalid media_hash"
+    );
+    require!(
+        metadata
+            .reference_hash
+            .as_ref()
+            .map_or(true, |h| h.0.len() == 32),
+        "Invalid reference_hash"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::json_types::Base64VecU8;
+    use near_sdk::serde_json;
+
+    #[derive(BorshSerialize)]
+    struct BaselineXpBridge {
+        consumed_actions: UnorderedSet<u128>,
+        paused: bool,
+        tx_fees: u128,
+        group_key: [u8; 32],
+        fee_pk: [u8; 32],
+        action_cnt: u128,
+        whitelist: UnorderedSet<String>,
+    }
+
+    #[test]
+    fn migrate_keeps_the_baseline_state() {
+        set_context(&bridge_id(), 0);
+        let mut old = BaselineXpBridge {
+            consumed_actions: UnorderedSet::new(b"c"),
+            paused: false,
+            tx_fees: 7,
+            group_key: group_keypair().public.to_bytes(),
+            fee_pk: fee_keypair().public.to_bytes(),
+            action_cnt: 3,
+            whitelist: UnorderedSet::new(b"w"),
+        };
+        old.consumed_actions.insert(&5);
+        old.whitelist.insert(&"nft.near".to_string());
+        env::state_write(&old);
+
+        let bridge = XpBridge::migrate();
+
+        assert_eq!(bridge.get_action_cnt().0, 3);
+        assert_eq!(bridge.tx_fees, 7);
+        assert!(bridge.is_whitelist(account("nft")));
+        assert!(bridge.get_whitelist(None, None)[0].entry.is_none());
+        assert!(bridge.is_action_consumed(CHAIN_NONCE, U128(5)));
+        assert!(bridge.is_action_consumed(7, U128(5)));
+        assert!(!bridge.is_action_consumed(7, U128(6)));
+        assert_eq!(bridge.get_current_epoch(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicated Action")]
+    fn migrated_action_ids_cannot_be_replayed() {
+        set_context(&bridge_id(), 0);
+        let mut old = BaselineXpBridge {
+            consumed_actions: UnorderedSet::new(b"c"),
+            paused: false,
+            tx_fees: 0,
+            group_key: group_keypair().public.to_bytes(),
+            fee_pk: fee_keypair().public.to_bytes(),
+            action_cnt: 0,
+            whitelist: UnorderedSet::new(b"w"),
+        };
+        old.consumed_actions.insert(&1);
+        env::state_write(&old);
+
+        let mut bridge = XpBridge::migrate();
+        let data = PauseData { action_id: U128(1) };
+        let sig = group_sig(b"SetPause", &data);
+        bridge.validate_pause(data, sig);
+    }
+
+    #[test]
+    fn action_ids_are_namespaced_by_source_chain() {
+        let mut bridge = setup();
+        let from_bsc = transfer_data(1, "1");
+        let mut from_eth = transfer_data(1, "2");
+        from_eth.chain_nonce = 5;
+
+        set_context(&account("relayer"), 10);
+        let sig = group_sig(b"ValidateTransferNft", &from_bsc);
+        bridge.validate_transfer_nft(from_bsc, sig);
+        let sig = group_sig(b"ValidateTransferNft", &from_eth);
+        bridge.validate_transfer_nft(from_eth, sig);
+
+        assert!(bridge.is_action_consumed(4, U128(1)));
+        assert!(bridge.is_action_consumed(5, U128(1)));
+        assert!(!bridge.is_action_consumed(CHAIN_NONCE, U128(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicated Action")]
+    fn action_ids_cannot_be_replayed_on_the_same_chain() {
+        let mut bridge = setup();
+        let data = transfer_data(1, "1");
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        set_context(&account("relayer"), 10);
+        bridge.validate_transfer_nft(data.clone(), sig.clone());
+        bridge.validate_transfer_nft(data, sig);
+    }
+
+    #[test]
+    fn signed_bridge_actions_use_the_near_namespace() {
+        let mut bridge = setup();
+        let data = PauseData { action_id: U128(1) };
+        let sig = group_sig(b"SetPause", &data);
+        bridge.validate_pause(data, sig);
+
+        assert!(bridge.is_action_consumed(CHAIN_NONCE, U128(1)));
+        assert!(!bridge.is_action_consumed(4, U128(1)));
+    }
+
+    #[test]
+    fn validated_unfreezes_carry_the_source_tx_hash() {
+        let mut bridge = setup();
+
+        set_context(&bridge_id(), 0);
+        let data = UnfreezeNftData {
+            tx_hash: "0xsourcetx".to_string(),
+            ..unfreeze_data(1, "1")
+        };
+        bridge.validate_unfreeze_callback(data.validated_event(), Ok(()));
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs[0].contains("0xsourcetx"));
+    }
+
+    fn freeze(bridge: &mut XpBridge) -> Promise {
+        set_context(&account("alice"), 10);
+        bridge.freeze_nft(
+            account("nft"),
+            "1".to_string(),
+            4,
+            "0xreceiver".to_string(),
+            "0xwrapped".to_string(),
+            vec![],
+        )
+    }
+
+    #[test]
+    #[should_panic(expected = "Collection not whitelisted")]
+    fn freeze_requires_a_whitelisted_collection() {
+        let mut bridge = setup();
+        freeze(&mut bridge);
+    }
+
+    #[test]
+    fn freeze_accepts_whitelisted_collections() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+        freeze(&mut bridge);
+
+        // The fee signature is verified first.
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, bridge_id());
+        assert!(matches!(
+            &receipts[0].actions[0],
+            near_sdk::mock::VmAction::FunctionCall { function_name, .. }
+                if function_name == "verify_paid_amount_by_sig"
+        ));
+    }
+
+    #[test]
+    fn freeze_accepts_permissionless_collections() {
+        let mut bridge = setup();
+        bridge.permissionless_collections.insert(&account("nft"));
+        freeze(&mut bridge);
+    }
+
+    fn full_metadata() -> TokenMetadata {
+        TokenMetadata {
+            title: Some("Wrapped #1".to_string()),
+            description: Some("A wrapped NFT".to_string()),
+            media: Some("ipfs://media".to_string()),
+            media_hash: Some(Base64VecU8(vec![1; 32])),
+            copies: Some(1),
+            issued_at: Some("1700000000".to_string()),
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: Some("{\"trait\":\"rare\"}".to_string()),
+            reference: Some("ipfs://reference".to_string()),
+            reference_hash: Some(Base64VecU8(vec![2; 32])),
+        }
+    }
+
+    fn validate_with_metadata(bridge: &mut XpBridge, metadata: TokenMetadata) {
+        let mut data = transfer_data(1, "1");
+        data.token_metadata = metadata;
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        set_context(&account("relayer"), 10);
+        bridge.validate_transfer_nft(data, sig);
+    }
+
+    /// Gets the arguments of the `nft_mint` call made by the last call.
+    fn minted_args() -> serde_json::Value {
+        let args = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .flat_map(|r| r.actions)
+            .find_map(|a| match a {
+                near_sdk::mock::VmAction::FunctionCall {
+                    function_name,
+                    args,
+                    ..
+                } if function_name == "nft_mint" => Some(args),
+                _ => None,
+            })
+            .unwrap();
+        serde_json::from_slice(&args).unwrap()
+    }
+
+    #[test]
+    fn transfers_mint_the_full_metadata() {
+        let mut bridge = setup();
+        validate_with_metadata(&mut bridge, full_metadata());
+
+        let args = minted_args();
+        assert_eq!(
+            args["token_metadata"],
+            serde_json::to_value(full_metadata()).unwrap()
+        );
+    }
+
+    #[test]
+    fn transfers_mint_with_the_origin_of_the_token() {
+        let mut bridge = setup();
+        validate_with_metadata(&mut bridge, token_metadata());
+
+        let args = minted_args();
+        assert_eq!(args["origin"]["chain_nonce"], 4);
+        assert_eq!(args["origin"]["contract"], "0xorigin");
+        assert_eq!(args["origin"]["token_id"], "1");
+        assert_eq!(args["origin"]["action_id"], "1");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid media_hash")]
+    fn media_hash_must_be_a_sha256_hash() {
+        let mut bridge = setup();
+        validate_with_metadata(
+            &mut bridge,
+            TokenMetadata {
+                media_hash: Some(Base64VecU8(vec![1; 31])),
+                ..full_metadata()
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid reference_hash")]
+    fn reference_hash_must_be_a_sha256_hash() {
+        let mut bridge = setup();
+        validate_with_metadata(
+            &mut bridge,
+            TokenMetadata {
+                reference_hash: Some(Base64VecU8(vec![2; 33])),
+                ..full_metadata()
+            },
+        );
+    }
 }
@@ -0,0 +1,263 @@
+use crate::*;
+
+pub(crate) const GAS_FOR_CLAIM_TRANSFER: Gas = Gas(35_000_000_000_000);
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferRootData {
+    pub action_id: U128,
+    pub root: [u8; 32],
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Registers the Merkle root of a set of {TransferNftData} leaves.
+    /// Each leaf can then be claimed once with `claim_transfer`.
+    /// FAILS: If contract is paused or the root is already registered.
+    /// REQUIRED: Signature verification.
+    pub fn validate_transfer_root(&mut self, data: TransferRootData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+        require!(
+            !self.transfer_roots.contains(&data.root),
+            "Root already registered"
+        );
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateTransferRoot",
+        );
+
+        self.transfer_roots.insert(&data.root);
+    }
+
+    /// Mints the wrapped NFT of a transfer included in a registered
+    /// Merkle root. The caller pays for the storage of the token with the
+    /// attached deposit and gets back what is left of it.
+    /// The action_id of the leaf is consumed like in `validate_transfer_nft`,
+    /// so every transfer is minted only once.
+    /// Above the rate limits of the chain or the collection, the claim
+    /// is queued and can be executed with `execute_queued_action` later.
+    /// FAILS: If contract is paused, the proof doesn't lead to a registered root,
+    /// `mint_with` doesn't match the collection registered for its origin
+    /// or a metadata hash is not a sha256 hash.
+    #[payable]
+    pub fn claim_transfer(
+        &mut self,
+        leaf: TransferNftData,
+        proof: Vec<[u8; 32]>,
+    ) -> PromiseOrValue<()> {
+        require!(
+            env::prepaid_gas() >= GAS_FOR_CLAIM_TRANSFER,
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+        require!(env::attached_deposit() > 0, "Storage deposit required");
+
+        let root = merkle_root(&leaf.try_to_vec().unwrap(), &proof);
+        require!(self.transfer_roots.contains(&root), "Invalid proof");
+
+        self.consume_action(leaf.chain_nonce, leaf.action_id.0);
+        self.require_collection_origin(&leaf);
+        require_valid_token_metadata(&leaf.token_metadata);
+
+        let claimant = env::predecessor_account_id();
+        if !self
+            .rate_limits
+            .try_acquire(leaf.chain_nonce, &leaf.mint_with)
+        {
+            self.internal_queue_action(
+                leaf.chain_nonce,
+                leaf.action_id.0,
+                leaf.mint_with.clone(),
+                QueuedBridgeAction::ClaimTransfer {
+                    leaf: Box::new(leaf),
+                    claimant,
+                    deposit: U128(env::attached_deposit()),
+                },
+            );
+            return PromiseOrValue::Value(());
+        }
+
+        self.internal_claim_transfer(leaf, claimant, env::attached_deposit())
+            .into()
+    }
+
+    /// This is the callback function when the promise in the claim_transfer
+    /// function is completed. Releases the leaf and returns the deposit to
+    /// the claimant if the mint failed.
+    #[private]
+    pub fn claim_transfer_callback(
+        &mut self,
+        event: TransferValidatedEvent,
+        claimant: AccountId,
+        amt: u128,
+        #[callback_result] call_result: Result<Token, PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => event.emit(),
+            Err(e) => {
+                self.consumed_actions
+                    .release(event.chain_nonce, event.action_id);
+                self.internal_refund(claimant, amt);
+                env::log_str(&format!(
+                    "claim transfer callback: failed to mint nft: actionid: {} : {:?}",
+                    event.action_id, e
+                ))
+            }
+        }
+    }
+
+    /// Checks if the provided Merkle root of transfers is registered.
+    pub fn is_transfer_root(&self, root: [u8; 32]) -> bool {
+        self.transfer_roots.contains(&root)
+    }
+}
+
+impl XpBridge {
+    /// Mints the wrapped NFT of a claimed leaf and then calls
+    /// 'claim_transfer_callback'.
+    pub(crate) fn internal_claim_transfer(
+        &mut self,
+        leaf: TransferNftData,
+        claimant: AccountId,
+        deposit: u128,
+    ) -> Promise {
+        let origin = leaf.origin();
+        let event = leaf.validated_event();
+        xpnft::ext(leaf.mint_with)
+            .with_attached_deposit(deposit)
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_mint(
+                leaf.token_id,
+                leaf.owner_id,
+                leaf.token_metadata,
+                leaf.royalty,
+                Some(origin),
+                Some(claimant.clone()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 15))
+                    .claim_transfer_callback(event, claimant, deposit),
+            )
+    }
+}
+
+/// Computes the Merkle root of the borsh encoded `leaf` with `proof`.
+/// Leaves are hashed as sha256(0x00 || leaf) and nodes as
+/// sha256(0x01 || a || b) with the pair sorted, so the proof
+/// doesn't need to carry the position of the siblings.
+pub fn merkle_root(leaf: &[u8], proof: &[[u8; 32]]) -> [u8; 32] {
+    let mut node = sha256_array(&[&[0u8], leaf].concat());
+    for sibling in proof {
+        let (a, b) = if node <= *sibling {
+            (node, *sibling)
+        } else {
+            (*sibling, node)
+        };
+        node = sha256_array(&[&[1u8], &a[..], &b[..]].concat());
+    }
+    node
+}
+
+fn sha256_array(data: &[u8]) -> [u8; 32] {
+    env::sha256(data).try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn leaf_hash(leaf: &TransferNftData) -> [u8; 32] {
+        sha256_array(&[&[0u8], &leaf.try_to_vec().unwrap()[..]].concat())
+    }
+
+    /// Registers the root of a tree with the leaves of the transfers 1 and 2.
+    fn setup_root() -> XpBridge {
+        let mut bridge = setup();
+        let root = merkle_root(
+            &transfer_data(1, "1").try_to_vec().unwrap(),
+            &[leaf_hash(&transfer_data(2, "2"))],
+        );
+        let data = TransferRootData {
+            action_id: U128(10),
+            root,
+        };
+        let sig = group_sig(b"ValidateTransferRoot", &data);
+        bridge.validate_transfer_root(data, sig);
+        assert!(bridge.is_transfer_root(root));
+        bridge
+    }
+
+    #[test]
+    fn proof_order_does_not_matter() {
+        set_context(&bridge_id(), 0);
+        let first = transfer_data(1, "1");
+        let second = transfer_data(2, "2");
+        assert_eq!(
+            merkle_root(&first.try_to_vec().unwrap(), &[leaf_hash(&second)]),
+            merkle_root(&second.try_to_vec().unwrap(), &[leaf_hash(&first)])
+        );
+    }
+
+    #[test]
+    fn every_leaf_can_be_claimed_once() {
+        let mut bridge = setup_root();
+
+        set_context(&account("bob"), 10);
+        bridge.claim_transfer(
+            transfer_data(2, "2"),
+            vec![leaf_hash(&transfer_data(1, "1"))],
+        );
+
+        assert!(bridge.consumed_actions.is_consumed(4, 2));
+        assert!(!bridge.consumed_actions.is_consumed(4, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicated Action")]
+    fn claimed_leaves_cannot_be_replayed() {
+        let mut bridge = setup_root();
+        let proof = vec![leaf_hash(&transfer_data(2, "2"))];
+
+        set_context(&account("bob"), 10);
+        bridge.claim_transfer(transfer_data(1, "1"), proof.clone());
+        bridge.claim_transfer(transfer_data(1, "1"), proof);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid proof")]
+    fn leaves_outside_the_root_are_rejected() {
+        let mut bridge = setup_root();
+
+        set_context(&account("bob"), 10);
+        bridge.claim_transfer(
+            transfer_data(3, "3"),
+            vec![leaf_hash(&transfer_data(2, "2"))],
+        );
+    }
+
+    #[test]
+    fn failed_mint_releases_the_leaf() {
+        let mut bridge = setup_root();
+        set_context(&account("bob"), 10);
+        bridge.claim_transfer(
+            transfer_data(1, "1"),
+            vec![leaf_hash(&transfer_data(2, "2"))],
+        );
+
+        set_context(&bridge_id(), 0);
+        bridge.claim_transfer_callback(
+            transfer_data(1, "1").validated_event(),
+            account("bob"),
+            10,
+            Err(PromiseError::Failed),
+        );
+
+        assert!(!bridge.consumed_actions.is_consumed(4, 1));
+        assert_eq!(transferred_to(&account("bob")), 10);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::*;
+
+const GAS_FOR_VALIDATE_UPDATE_METADATA: Gas = Gas(30_000_000_000_000);
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UpdateMetadataData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub mint_with: AccountId,
+    pub token_id: TokenId,
+    pub token_metadata: TokenMetadata,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Validates a change of the metadata of an NFT on its home chain and
+    /// replaces the metadata of the wrapped NFT with `nft_update_metadata`.
+    /// The attached deposit pays for additional storage, the rest of it is
+    /// returned to the caller.
+    /// FAILS: If contract is paused, `mint_with` is registered for another
+    /// chain or the `media_hash` or `reference_hash` of the metadata is not
+    /// a sha256 hash.
+    /// REQUIRED: Signature verification.
+    #[payable]
+    pub fn validate_update_metadata(
+        &mut self,
+        data: UpdateMetadataData,
+        sig_data: Vec<u8>,
+    ) -> Promise {
+        require!(
+            env::prepaid_gas() >= GAS_FOR_VALIDATE_UPDATE_METADATA,
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+
+        self.consume_action(data.chain_nonce, data.action_id.into());
+        self.require_group_sig(
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateUpdateMetadata",
+        );
+        if let Some(origin) = self.get_collection_origin(data.mint_with.clone()) {
+            require!(
+                origin.chain_nonce == data.chain_nonce,
+                "Invalid origin for mint_with"
+            );
+        }
+        require_valid_token_metadata(&data.token_metadata);
+
+        let event = MetadataUpdatedEvent {
+            chain_nonce: data.chain_nonce,
+            tx_hash: data.tx_hash,
+            action_id: data.action_id.0,
+            contract: data.mint_with.clone(),
+            token_id: data.token_id.clone(),
+        };
+        xpnft::ext(data.mint_with)
+            .with_attached_deposit(env::attached_deposit())
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_update_metadata(
+                data.token_id,
+                data.token_metadata,
+                Some(env::predecessor_account_id()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .update_metadata_callback(
+                        event,
+                        env::attached_deposit(),
+                        env::predecessor_account_id(),
+                    ),
+            )
+    }
+
+    /// This is the callback function when the promise in the
+    /// validate_update_metadata function is completed. Releases the
+    /// action and returns the deposit if the metadata wasn't updated.
+    #[private]
+    pub fn update_metadata_callback(
+        &mut self,
+        event: MetadataUpdatedEvent,
+        amt: u128,
+        sender: AccountId,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => event.emit(),
+            Err(e) => {
+                self.consumed_actions
+                    .release(event.chain_nonce, event.action_id);
+                if amt > 0 {
+                    self.internal_refund(sender, amt);
+                }
+                env::log_str(&format!(
+                    "update metadata callback: failed to update metadata: actionid: {} : {:?}",
+                    event.action_id, e
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::get_created_receipts;
+
+    fn update_data() -> UpdateMetadataData {
+        UpdateMetadataData {
+            action_id: U128(5),
+            chain_nonce: 4,
+            tx_hash: "0xupdate".to_string(),
+            mint_with: account("wrapped"),
+            token_id: "1".to_string(),
+            token_metadata: TokenMetadata {
+                title: Some("Renamed".to_string()),
+                ..token_metadata()
+            },
+        }
+    }
+
+    fn update(bridge: &mut XpBridge, data: UpdateMetadataData) {
+        let sig = group_sig(b"ValidateUpdateMetadata", &data);
+
+        set_context(&account("relayer"), 10);
+        bridge.validate_update_metadata(data, sig);
+    }
+
+    #[test]
+    fn update_replaces_the_metadata_of_the_wrapped_nft() {
+        let mut bridge = setup();
+        update(&mut bridge, update_data());
+
+        assert!(bridge.is_action_consumed(4, U128(5)));
+        let receipt = get_created_receipts()
+            .into_iter()
+            .find(|r| r.receiver_id == account("wrapped"))
+            .unwrap();
+        assert!(matches!(
+            &receipt.actions[0],
+            VmAction::FunctionCall { function_name, deposit: 10, .. }
+                if function_name == "nft_update_metadata"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid origin for mint_with")]
+    fn update_must_come_from_the_home_chain() {
+        let mut bridge = setup();
+        bridge.collection_origins.insert(
+            &account("wrapped"),
+            &CollectionOrigin {
+                chain_nonce: 5,
+                foreign_contract: "0xorigin".to_string(),
+            },
+        );
+
+        update(&mut bridge, update_data());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid reference_hash")]
+    fn updated_metadata_hashes_are_checked() {
+        let mut bridge = setup();
+        let mut data = update_data();
+        data.token_metadata.reference_hash = Some(vec![0; 16].into());
+
+        update(&mut bridge, data);
+    }
+
+    #[test]
+    fn failed_update_releases_the_action() {
+        let mut bridge = setup();
+        update(&mut bridge, update_data());
+
+        set_context(&bridge_id(), 0);
+        bridge.update_metadata_callback(
+            MetadataUpdatedEvent {
+                chain_nonce: 4,
+                tx_hash: "0xupdate".to_string(),
+                action_id: 5,
+                contract: account("wrapped"),
+                token_id: "1".to_string(),
+            },
+            10,
+            account("relayer"),
+            Err(PromiseError::Failed),
+        );
+
+        assert!(!bridge.is_action_consumed(4, U128(5)));
+        assert_eq!(transferred_to(&account("relayer")), 10);
+    }
+}
@@ -0,0 +1,707 @@
+use crate::*;
+
+const GAS_FOR_FREEZE_MT: Gas = Gas(40_000_000_000_000);
+const GAS_FOR_WITHDRAW_MT: Gas = Gas(40_000_000_000_000);
+pub(crate) const GAS_FOR_VALIDATE_MT: Gas = Gas(35_000_000_000_000);
+
+/// Fee signature for bridge transfers of NEP-245 tokens, signed with the
+/// context "MtTransferTx". Same as {TransferTx} with the number of copies
+/// transferred.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtTransferTx {
+    value: u128,
+    from_chain: u8,
+    to_chain: u8,
+    token_contract: AccountId,
+    token_id: TokenId,
+    amount: u128,
+    to: String,
+}
+
+/// Copies of a NEP-245 token frozen with `freeze_mt`, approved for the
+/// bridge with `approval_id`.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FreezeMtItem {
+    pub token_contract: AccountId,
+    pub token_id: TokenId,
+    pub amount: U128,
+    pub approval_id: u64,
+}
+
+/// Transfer of copies of a multi-token (ERC-1155) from a foreign chain,
+/// minted on the wrapped NEP-245 contract `mint_with`.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferMtData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub origin_contract: String,
+    pub mint_with: AccountId,
+    pub token_id: TokenId,
+    pub owner_id: AccountId,
+    pub amount: U128,
+    pub token_metadata: TokenMetadata,
+}
+
+/// Return of copies of a NEP-245 token frozen on the bridge.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnfreezeMtData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub tx_hash: String,
+    pub token_contract: AccountId,
+    pub token_id: TokenId,
+    pub amount: U128,
+    pub receiver_id: AccountId,
+}
+
+impl TransferMtData {
+    /// Event emitted once the copies of this transfer are minted.
+    fn validated_event(&self) -> TransferValidatedEvent {
+        TransferValidatedEvent {
+            chain_nonce: self.chain_nonce,
+            tx_hash: self.tx_hash.clone(),
+            action_id: self.action_id.0,
+            contract: self.mint_with.clone(),
+            token_id: self.token_id.clone(),
+            owner_id: self.owner_id.clone(),
+            quantity: Some(self.amount.0),
+        }
+    }
+}
+
+impl UnfreezeMtData {
+    /// Event emitted once the copies of this unfreeze are transferred.
+    fn validated_event(&self) -> UnfreezeValidatedEvent {
+        UnfreezeValidatedEvent {
+            chain_nonce: self.chain_nonce,
+            tx_hash: self.tx_hash.clone(),
+            action_id: self.action_id.0,
+            contract: self.token_contract.clone(),
+            token_id: self.token_id.clone(),
+            receiver_id: self.receiver_id.clone(),
+            quantity: Some(self.amount.0),
+        }
+    }
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Freezes `token.amount` copies of a NEP-245 token on the bridge
+    /// contract. The bridge must be approved for the copies with
+    /// `token.approval_id`.
+    /// The attached deposit is the fee, which is returned to the sender
+    /// if the transfer fails. Then calls 'freeze_mt_callback'.
+    /// Unlike `freeze_nft`, no FreezeRecord is kept for the copies, so the
+    /// freeze can't be cancelled with `request_cancel` or reclaimed with
+    /// `reclaim_nft`. The copies only leave the bridge with
+    /// `validate_unfreeze_mt`.
+    /// FAILS: If contract is paused, the collection is blocked or not
+    /// whitelisted, the amount is zero or the fee signature is invalid.
+    /// Collections are not registered by this in permissionless mode.
+    #[payable]
+    pub fn freeze_mt(
+        &mut self,
+        token: FreezeMtItem,
+        chain_nonce: u8,
+        to: String,
+        mint_with: String,
+        sig_data: Vec<u8>,
+    ) -> Promise {
+        require!(env::prepaid_gas() >= GAS_FOR_FREEZE_MT, "Not enough gas");
+        require!(!self.paused, "paused");
+        require!(token.amount.0 > 0, "Amount must be positive");
+        self.require_freezable(&token.token_contract);
+        self.require_fee_sig(
+            MtTransferTx {
+                value: env::attached_deposit(),
+                from_chain: CHAIN_NONCE,
+                to_chain: chain_nonce,
+                token_contract: token.token_contract.clone(),
+                token_id: token.token_id.clone(),
+                amount: token.amount.0,
+                to: to.clone(),
+            }
+            .try_to_vec()
+            .unwrap(),
+            sig_data,
+            b"MtTransferTx",
+        );
+
+        let sender = env::predecessor_account_id();
+        common_mt::ext(token.token_contract.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas(TGAS * 10))
+            .mt_transfer(
+                env::current_account_id(),
+                token.token_id.clone(),
+                token.amount,
+                Some((sender.clone(), token.approval_id)),
+                None,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .freeze_mt_callback(
+                        FreezeRequest {
+                            token_contract: token.token_contract,
+                            token_id: token.token_id,
+                            chain_nonce,
+                            to,
+                            mint_with,
+                            amt: U128(env::attached_deposit()),
+                            sender,
+                        },
+                        token.amount,
+                    ),
+            )
+    }
+
+    /// This is the callback function when the promise in the freeze_mt
+    /// function is completed. Emits a TransferNftEvent with the quantity
+    /// on success, otherwise the fee is returned to the sender.
+    #[private]
+    pub fn freeze_mt_callback(
+        &mut self,
+        request: FreezeRequest,
+        amount: U128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => {
+                self.action_cnt += 1;
+                self.tx_fees += request.amt.0;
+
+                TransferNftEvent {
+                    action_id: self.action_cnt,
+                    chain_nonce: request.chain_nonce,
+                    to: request.to,
+                    amt: request.amt.0,
+                    contract: request.token_contract,
+                    token_id: request.token_id,
+                    mint_with: request.mint_with,
+                    fee_token: None,
+                    royalty: None,
+                    quantity: Some(amount.0),
+                }
+                .emit();
+            }
+            Err(e) => {
+                self.internal_refund(request.sender, request.amt.0);
+                env::log_str(&format!(
+                    "freeze mt callback: failed to transfer tokens: actionid: {} : {:?}",
+                    self.action_cnt, e
+                ))
+            }
+        }
+    }
+
+    /// Validates the transfer of copies of a multi-token from a foreign
+    /// chain and mints them on the wrapped NEP-245 contract. The attached
+    /// deposit pays for the storage, the rest of it is returned to the caller.
+    /// Above the rate limits of the chain or the collection, the transfer
+    /// is queued and can be executed with `execute_queued_action` later.
+    /// FAILS: If contract is paused, `mint_with` doesn't match the wrapped
+    /// collection registered for `origin_contract` or a metadata hash is not
+    /// a sha256 hash.
+    /// REQUIRED: Signature verification.
+    #[payable]
+    pub fn validate_transfer_mt(
+        &mut self,
+        data: TransferMtData,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
+        require!(env::prepaid_gas() >= GAS_FOR_VALIDATE_MT, "Not enough gas");
+        require!(!self.paused, "paused");
+
+        self.require_chain_sig(
+            data.chain_nonce,
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateTransferMt",
+        );
+        self.require_origin(data.chain_nonce, &data.origin_contract, &data.mint_with);
+        require_valid_token_metadata(&data.token_metadata);
+
+        let sender = env::predecessor_account_id();
+        if !self
+            .rate_limits
+            .try_acquire(data.chain_nonce, &data.mint_with)
+        {
+            self.internal_queue_action(
+                data.chain_nonce,
+                data.action_id.0,
+                data.mint_with.clone(),
+                QueuedBridgeAction::TransferMt {
+                    data: Box::new(data),
+                    deposit: U128(env::attached_deposit()),
+                    sender,
+                },
+            );
+            return PromiseOrValue::Value(());
+        }
+
+        self.internal_transfer_mt(data, env::attached_deposit(), sender)
+            .into()
+    }
+
+    /// This is the callback function when the promise in the
+    /// validate_transfer_mt function is completed. Releases the action
+    /// and returns the deposit if the tokens weren't minted.
+    #[private]
+    pub fn validate_transfer_mt_callback(
+        &mut self,
+        event: TransferValidatedEvent,
+        deposit: u128,
+        sender: AccountId,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => event.emit(),
+            Err(e) => {
+                self.consumed_actions
+                    .release(event.chain_nonce, event.action_id);
+                if deposit > 0 {
+                    self.internal_refund(sender, deposit);
+                }
+                env::log_str(&format!(
+                    "validate transfer mt callback: failed to mint tokens: actionid: {} : {:?}",
+                    event.action_id, e
+                ))
+            }
+        }
+    }
+
+    /// Burns copies of a wrapped multi-token of the caller to send them
+    /// back to their home chain. The attached deposit is the fee, which is
+    /// returned to the caller if the burn fails.
+    /// FAILS: If contract is paused, the amount is zero or the fee
+    /// signature is invalid.
+    #[payable]
+    pub fn withdraw_mt(
+        &mut self,
+        token_contract: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        chain_nonce: u8,
+        to: String,
+        sig_data: Vec<u8>,
+    ) -> Promise {
+        require!(env::prepaid_gas() >= GAS_FOR_WITHDRAW_MT, "Not enough gas");
+        require!(!self.paused, "paused");
+        require!(amount.0 > 0, "Amount must be positive");
+        self.require_fee_sig(
+            MtTransferTx {
+                value: env::attached_deposit(),
+                from_chain: CHAIN_NONCE,
+                to_chain: chain_nonce,
+                token_contract: token_contract.clone(),
+                token_id: token_id.clone(),
+                amount: amount.0,
+                to: to.clone(),
+            }
+            .try_to_vec()
+            .unwrap(),
+            sig_data,
+            b"MtTransferTx",
+        );
+
+        let sender = env::predecessor_account_id();
+        xpmt::ext(token_contract.clone())
+            .with_static_gas(Gas(TGAS * 10))
+            .mt_burn(token_id.clone(), sender.clone(), amount)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .withdraw_mt_callback(
+                        WithdrawRequest {
+                            token_contract,
+                            token_id,
+                            chain_nonce,
+                            to,
+                            amt: U128(env::attached_deposit()),
+                            sender,
+                        },
+                        amount,
+                    ),
+            )
+    }
+
+    /// This is the callback function when the promise in the withdraw_mt
+    /// function is completed. Emits an UnfreezeNftEvent with the quantity
+    /// on success, otherwise the fee is returned to the sender.
+    #[private]
+    pub fn withdraw_mt_callback(
+        &mut self,
+        request: WithdrawRequest,
+        amount: U128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => {
+                self.action_cnt += 1;
+                self.tx_fees += request.amt.0;
+
+                UnfreezeNftEvent {
+                    action_id: self.action_cnt,
+                    chain_nonce: request.chain_nonce,
+                    to: request.to,
+                    amt: request.amt.0,
+                    contract: request.token_contract,
+                    token: Some(Token {
+                        token_id: request.token_id,
+                        owner_id: request.sender,
+                        metadata: None,
+                        approved_account_ids: None,
+                    }),
+                    fee_token: None,
+                    quantity: Some(amount.0),
+                }
+                .emit();
+            }
+            Err(e) => {
+                self.internal_refund(request.sender, request.amt.0);
+                env::log_str(&format!(
+                    "withdraw mt callback: failed to burn tokens: actionid: {} : {:?}",
+                    self.action_cnt, e
+                ))
+            }
+        }
+    }
+
+    /// Unfreezes copies of a NEP-245 token frozen on the bridge contract
+    /// and transfers them to the receiver.
+    /// Above the rate limits of the chain or the collection, the unfreeze
+    /// is queued and can be executed with `execute_queued_action` later.
+    /// FAILS: If contract is paused or the collection is not whitelisted.
+    /// REQUIRED: Signature verification.
+    pub fn validate_unfreeze_mt(
+        &mut self,
+        data: UnfreezeMtData,
+        sig_data: Vec<u8>,
+    ) -> PromiseOrValue<()> {
+        require!(env::prepaid_gas() >= GAS_FOR_VALIDATE_MT, "Not enough gas");
+        require!(!self.paused, "paused");
+        self.require_bridgeable(&data.token_contract);
+
+        self.require_chain_sig(
+            data.chain_nonce,
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateUnfreezeMt",
+        );
+
+        if !self
+            .rate_limits
+            .try_acquire(data.chain_nonce, &data.token_contract)
+        {
+            self.internal_queue_action(
+                data.chain_nonce,
+                data.action_id.0,
+                data.token_contract.clone(),
+                QueuedBridgeAction::UnfreezeMt { data },
+            );
+            return PromiseOrValue::Value(());
+        }
+
+        self.internal_unfreeze_mt(data).into()
+    }
+
+    /// This is the callback function when the promise in the
+    /// validate_unfreeze_mt function is completed. Releases the
+    /// action if the tokens weren't transferred.
+    #[private]
+    pub fn validate_unfreeze_mt_callback(
+        &mut self,
+        event: UnfreezeValidatedEvent,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => event.emit(),
+            Err(e) => {
+                self.consumed_actions
+                    .release(event.chain_nonce, event.action_id);
+                env::log_str(&format!(
+                    "validate unfreeze mt callback: failed to transfer tokens: actionid: {} : {:?}",
+                    event.action_id, e
+                ))
+            }
+        }
+    }
+}
+
+impl XpBridge {
+    /// Mints the copies of a validated multi-token transfer and then calls
+    /// 'validate_transfer_mt_callback'.
+    pub(crate) fn internal_transfer_mt(
+        &mut self,
+        data: TransferMtData,
+        deposit: u128,
+        sender: AccountId,
+    ) -> Promise {
+        let event = data.validated_event();
+        xpmt::ext(data.mint_with)
+            .with_attached_deposit(deposit)
+            .with_static_gas(Gas(TGAS * 10))
+            .mt_mint(
+                data.token_id,
+                data.owner_id,
+                data.amount,
+                Some(data.token_metadata),
+                Some(sender.clone()),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .validate_transfer_mt_callback(event, deposit, sender),
+            )
+    }
+
+    /// Transfers the copies of a validated multi-token unfreeze to the
+    /// receiver and then calls 'validate_unfreeze_mt_callback'.
+    pub(crate) fn internal_unfreeze_mt(&mut self, data: UnfreezeMtData) -> Promise {
+        common_mt::ext(data.token_contract.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas(TGAS * 10))
+            .mt_transfer(
+                data.receiver_id.clone(),
+                data.token_id.clone(),
+                data.amount,
+                None,
+                None,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 10))
+                    .validate_unfreeze_mt_callback(data.validated_event()),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::get_created_receipts;
+
+    fn transfer_mt_data(action_id: u128) -> TransferMtData {
+        TransferMtData {
+            action_id: U128(action_id),
+            chain_nonce: 4,
+            tx_hash: format!("0xtx{}", action_id),
+            origin_contract: "0xorigin".to_string(),
+            mint_with: account("wrapped"),
+            token_id: "1".to_string(),
+            owner_id: account("bob"),
+            amount: U128(5),
+            token_metadata: token_metadata(),
+        }
+    }
+
+    fn unfreeze_mt_data(action_id: u128) -> UnfreezeMtData {
+        UnfreezeMtData {
+            action_id: U128(action_id),
+            chain_nonce: 4,
+            tx_hash: format!("0xtx{}", action_id),
+            token_contract: account("mt"),
+            token_id: "1".to_string(),
+            amount: U128(5),
+            receiver_id: account("bob"),
+        }
+    }
+
+    fn transfer_mt(bridge: &mut XpBridge, data: TransferMtData) -> PromiseOrValue<()> {
+        let sig = group_sig(b"ValidateTransferMt", &data);
+        set_context(&account("relayer"), 10);
+        bridge.validate_transfer_mt(data, sig)
+    }
+
+    fn mt_transfer_tx(amount: u128) -> MtTransferTx {
+        MtTransferTx {
+            value: 10,
+            from_chain: CHAIN_NONCE,
+            to_chain: 4,
+            token_contract: account("mt"),
+            token_id: "1".to_string(),
+            amount,
+            to: "0xreceiver".to_string(),
+        }
+    }
+
+    fn freeze_mt(bridge: &mut XpBridge, sig_data: Vec<u8>) -> Promise {
+        set_context(&account("alice"), 10);
+        bridge.freeze_mt(
+            FreezeMtItem {
+                token_contract: account("mt"),
+                token_id: "1".to_string(),
+                amount: U128(5),
+                approval_id: 0,
+            },
+            4,
+            "0xreceiver".to_string(),
+            "0xwrapped".to_string(),
+            sig_data,
+        )
+    }
+
+    /// Gets the name of the function called by the receipt to `receiver`.
+    fn called_function(receiver: &AccountId) -> String {
+        get_created_receipts()
+            .into_iter()
+            .filter(|r| &r.receiver_id == receiver)
+            .flat_map(|r| r.actions)
+            .find_map(|a| match a {
+                VmAction::FunctionCall { function_name, .. } => Some(function_name),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn freeze_transfers_the_copies_to_the_bridge() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("mt"), 1, None);
+
+        freeze_mt(&mut bridge, fee_sig(b"MtTransferTx", &mt_transfer_tx(5)));
+
+        assert_eq!(called_function(&account("mt")), "mt_transfer");
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount Signature Verification Failed")]
+    fn freeze_fee_signature_covers_the_amount() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("mt"), 1, None);
+
+        freeze_mt(&mut bridge, fee_sig(b"MtTransferTx", &mt_transfer_tx(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Collection not whitelisted")]
+    fn freeze_requires_a_whitelisted_collection() {
+        let mut bridge = setup();
+        freeze_mt(&mut bridge, fee_sig(b"MtTransferTx", &mt_transfer_tx(5)));
+    }
+
+    #[test]
+    fn failed_freeze_refunds_the_fee() {
+        let mut bridge = setup();
+
+        set_context(&bridge_id(), 0);
+        bridge.freeze_mt_callback(
+            FreezeRequest {
+                token_contract: account("mt"),
+                ..freeze_request("alice")
+            },
+            U128(5),
+            Err(PromiseError::Failed),
+        );
+
+        assert_eq!(bridge.action_cnt, 0);
+        assert_eq!(transferred_to(&account("alice")), 10);
+    }
+
+    #[test]
+    fn transfer_mints_the_copies() {
+        let mut bridge = setup();
+        transfer_mt(&mut bridge, transfer_mt_data(1));
+
+        assert!(bridge.is_action_consumed(4, U128(1)));
+        assert_eq!(called_function(&account("wrapped")), "mt_mint");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid mint_with for origin")]
+    fn transfer_mints_on_the_registered_collection() {
+        let mut bridge = setup();
+        bridge
+            .collections
+            .insert(&(4, "0xorigin".to_string()), &account("other"));
+
+        transfer_mt(&mut bridge, transfer_mt_data(1));
+    }
+
+    #[test]
+    fn failed_transfer_releases_the_action() {
+        let mut bridge = setup();
+        transfer_mt(&mut bridge, transfer_mt_data(1));
+
+        set_context(&bridge_id(), 0);
+        bridge.validate_transfer_mt_callback(
+            transfer_mt_data(1).validated_event(),
+            10,
+            account("relayer"),
+            Err(PromiseError::Failed),
+        );
+
+        assert!(!bridge.is_action_consumed(4, U128(1)));
+        assert_eq!(transferred_to(&account("relayer")), 10);
+    }
+
+    #[test]
+    fn transfers_above_the_limit_are_queued() {
+        let mut bridge = setup();
+        bridge.rate_limits.set_chain_limit(4, Some(1));
+        transfer_mt(&mut bridge, transfer_mt_data(1));
+
+        assert!(matches!(
+            transfer_mt(&mut bridge, transfer_mt_data(2)),
+            PromiseOrValue::Value(())
+        ));
+        assert!(matches!(
+            bridge.get_queued_action(U64(1)).unwrap().action,
+            QueuedBridgeAction::TransferMt { .. }
+        ));
+        assert_eq!(bridge.total_queued_deposits, 10);
+    }
+
+    #[test]
+    fn unfreeze_returns_the_copies() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("mt"), 1, None);
+
+        let data = unfreeze_mt_data(1);
+        let sig = group_sig(b"ValidateUnfreezeMt", &data);
+        set_context(&account("relayer"), 0);
+        bridge.validate_unfreeze_mt(data, sig);
+
+        assert!(bridge.is_action_consumed(4, U128(1)));
+        assert_eq!(called_function(&account("mt")), "mt_transfer");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized Action")]
+    fn unfreeze_signature_is_not_a_transfer_signature() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("mt"), 1, None);
+
+        let data = unfreeze_mt_data(1);
+        let sig = group_sig(b"ValidateTransferMt", &data);
+        set_context(&account("relayer"), 0);
+        bridge.validate_unfreeze_mt(data, sig);
+    }
+
+    #[test]
+    fn withdraw_burns_the_copies() {
+        let mut bridge = setup();
+
+        set_context(&account("alice"), 10);
+        bridge.withdraw_mt(
+            account("mt"),
+            "1".to_string(),
+            U128(5),
+            4,
+            "0xreceiver".to_string(),
+            fee_sig(b"MtTransferTx", &mt_transfer_tx(5)),
+        );
+
+        assert_eq!(called_function(&account("mt")), "mt_burn");
+    }
+}
@@ -0,0 +1,626 @@
+use crate::*;
+
+/// Length of the rolling window of the rate limits (1 hour).
+pub const RATE_LIMIT_WINDOW: u64 = 60 * 60 * 1_000_000_000;
+/// Default delay of the actions queued above the rate limits (1 day).
+pub const DEFAULT_QUEUE_DELAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Rolling-window rate limits of the incoming actions
+/// per source chain and per collection.
+/// Every window keeps the timestamps of the actions executed
+/// in the last `RATE_LIMIT_WINDOW`, so it never holds more
+/// entries than its limit.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct RateLimiter {
+    collection_limits: LookupMap<AccountId, u32>,
+    chain_limits: LookupMap<u8, u32>,
+    collection_windows: LookupMap<AccountId, Vec<u64>>,
+    chain_windows: LookupMap<u8, Vec<u64>>,
+    queue_delay: u64,
+}
+
+/// An incoming action held back by the rate limits.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum QueuedBridgeAction {
+    TransferNft {
+        data: Box<TransferNftData>,
+        deposit: U128,
+        sponsor: Option<AccountId>,
+        sender: AccountId,
+    },
+    UnfreezeNft {
+        data: UnfreezeNftData,
+    },
+    ClaimTransfer {
+        leaf: Box<TransferNftData>,
+        claimant: AccountId,
+        deposit: U128,
+    },
+    ClaimVoucher {
+        record: Box<VoucherRecord>,
+        deposit: U128,
+    },
+    TransferMt {
+        data: Box<TransferMtData>,
+        deposit: U128,
+        sender: AccountId,
+    },
+    UnfreezeMt {
+        data: UnfreezeMtData,
+    },
+}
+
+impl QueuedBridgeAction {
+    /// Total of the deposits held by the bridge for the action.
+    fn deposits(&self) -> u128 {
+        match self {
+            Self::TransferNft { deposit, .. }
+            | Self::ClaimTransfer { deposit, .. }
+            | Self::TransferMt { deposit, .. } => deposit.0,
+            Self::ClaimVoucher { record, deposit } => record.deposit.0 + deposit.0,
+            Self::UnfreezeNft { .. } | Self::UnfreezeMt { .. } => 0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueuedAction {
+    pub action: QueuedBridgeAction,
+    pub eta: U64,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionRateLimitData {
+    pub action_id: U128,
+    pub collection: AccountId,
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChainRateLimitData {
+    pub action_id: U128,
+    pub chain_nonce: u8,
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueueDelayData {
+    pub action_id: U128,
+    pub delay: U64,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CancelQueuedData {
+    pub action_id: U128,
+    pub queue_id: U64,
+}
+
+impl RateLimiter {
+    pub fn new(prefix: &[u8]) -> Self {
+        Self {
+            collection_limits: LookupMap::new([prefix, b"c"].concat()),
+            chain_limits: LookupMap::new([prefix, b"n"].concat()),
+            collection_windows: LookupMap::new([prefix, b"w"].concat()),
+            chain_windows: LookupMap::new([prefix, b"x"].concat()),
+            queue_delay: DEFAULT_QUEUE_DELAY,
+        }
+    }
+
+    /// Records an action of the chain on the collection if neither of
+    /// their limits is reached. Returns false if the action is rate limited.
+    pub fn try_acquire(&mut self, chain_nonce: u8, collection: &AccountId) -> bool {
+        let now = env::block_timestamp();
+        let mut chain_window = self.chain_windows.get(&chain_nonce).unwrap_or_default();
+        let mut collection_window = self.collection_windows.get(collection).unwrap_or_default();
+        let chain_limit = self.chain_limits.get(&chain_nonce);
+        let collection_limit = self.collection_limits.get(collection);
+
+        if !Self::acquire(&mut chain_window, chain_limit, now)
+            || !Self::acquire(&mut collection_window, collection_limit, now)
+        {
+            return false;
+        }
+
+        if chain_limit.is_some() {
+            self.chain_windows.insert(&chain_nonce, &chain_window);
+        }
+        if collection_limit.is_some() {
+            self.collection_windows
+                .insert(collection, &collection_window);
+        }
+        true
+    }
+
+    pub fn set_collection_limit(&mut self, collection: &AccountId, limit: Option<u32>) {
+        match limit {
+            Some(limit) => self.collection_limits.insert(collection, &limit),
+            None => {
+                self.collection_windows.remove(collection);
+                self.collection_limits.remove(collection)
+            }
+        };
+    }
+
+    pub fn set_chain_limit(&mut self, chain_nonce: u8, limit: Option<u32>) {
+        match limit {
+            Some(limit) => self.chain_limits.insert(&chain_nonce, &limit),
+            None => {
+                self.chain_windows.remove(&chain_nonce);
+                self.chain_limits.remove(&chain_nonce)
+            }
+        };
+    }
+
+    pub fn collection_limit(&self, collection: &AccountId) -> Option<u32> {
+        self.collection_limits.get(collection)
+    }
+
+    pub fn chain_limit(&self, chain_nonce: u8) -> Option<u32> {
+        self.chain_limits.get(&chain_nonce)
+    }
+
+    pub fn set_queue_delay(&mut self, delay: u64) {
+        self.queue_delay = delay;
+    }
+
+    pub fn queue_delay(&self) -> u64 {
+        self.queue_delay
+    }
+
+    /// Drops the timestamps that left the window and adds `now`
+    /// if the window is below `limit`. A limit of None never limits.
+    fn acquire(window: &mut Vec<u64>, limit: Option<u32>, now: u64) -> bool {
+        let Some(limit) = limit else {
+            return true;
+        };
+        window.retain(|t| t.saturating_add(RATE_LIMIT_WINDOW) > now);
+        if window.len() >= limit as usize {
+            return false;
+        }
+        window.push(now);
+        true
+    }
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Sets the max number of incoming actions per hour on `collection`.
+    /// A `limit` of None removes the limit of the collection.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_collection_rate_limit(
+        &mut self,
+        data: CollectionRateLimitData,
+        sig_data: Vec<u8>,
+    ) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetCollectionRateLimit",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetCollectionRateLimit {
+                collection: data.collection,
+                limit: data.limit,
+            },
+        );
+    }
+
+    /// Sets the max number of incoming actions per hour from `chain_nonce`.
+    /// A `limit` of None removes the limit of the chain.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_chain_rate_limit(&mut self, data: ChainRateLimitData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetChainRateLimit",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetChainRateLimit {
+                chain_nonce: data.chain_nonce,
+                limit: data.limit,
+            },
+        );
+    }
+
+    /// Sets the delay in nanoseconds of the actions queued above the rate limits.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_queue_delay(&mut self, data: QueueDelayData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetQueueDelay",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetQueueDelay { delay: data.delay },
+        );
+    }
+
+    /// Executes an action queued above the rate limits once its delay passed.
+    /// Can be called by anyone.
+    /// FAILS: If contract is paused, the action doesn't exist or its delay
+    /// didn't pass.
+    pub fn execute_queued_action(&mut self, queue_id: U64) -> Promise {
+        require!(!self.paused, "paused");
+
+        let queued = self
+            .queued_actions
+            .get(&queue_id.0)
+            .unwrap_or_else(|| env::panic_str("Unknown queued action"));
+        require!(
+            queued.eta.0 <= env::block_timestamp(),
+            "Queued action not ready"
+        );
+        self.queued_actions.remove(&queue_id.0);
+        self.total_queued_deposits -= queued.action.deposits();
+
+        match queued.action {
+            QueuedBridgeAction::TransferNft {
+                data,
+                deposit,
+                sponsor,
+                sender,
+            } => {
+                require!(
+                    env::prepaid_gas() >= GAS_FOR_VALIDATE_TRANSFER,
+                    "Not enough gas"
+                );
+                self.internal_transfer_nft(*data, deposit.0, sponsor, sender)
+            }
+            QueuedBridgeAction::UnfreezeNft { data } => {
+                require!(
+                    env::prepaid_gas() >= GAS_FOR_VALIDATE_UNFREEZE,
+                    "Not enough gas"
+                );
+                self.internal_unfreeze_nft(data)
+            }
+            QueuedBridgeAction::ClaimTransfer {
+                leaf,
+                claimant,
+                deposit,
+            } => {
+                require!(
+                    env::prepaid_gas() >= GAS_FOR_CLAIM_TRANSFER,
+                    "Not enough gas"
+                );
+                self.internal_claim_transfer(*leaf, claimant, deposit.0)
+            }
+            QueuedBridgeAction::ClaimVoucher { record, deposit } => {
+                require!(
+                    env::prepaid_gas() >= GAS_FOR_CLAIM_VOUCHER,
+                    "Not enough gas"
+                );
+                self.internal_claim_voucher(*record, deposit.0)
+            }
+            QueuedBridgeAction::TransferMt {
+                data,
+                deposit,
+                sender,
+            } => {
+                require!(env::prepaid_gas() >= GAS_FOR_VALIDATE_MT, "Not enough gas");
+                self.internal_transfer_mt(*data, deposit.0, sender)
+            }
+            QueuedBridgeAction::UnfreezeMt { data } => {
+                require!(env::prepaid_gas() >= GAS_FOR_VALIDATE_MT, "Not enough gas");
+                self.internal_unfreeze_mt(data)
+            }
+        }
+    }
+
+    /// Drops an action queued above the rate limits. Its action id stays
+    /// consumed and the deposits it holds are returned to their payers.
+    /// FAILS: If the action doesn't exist.
+    /// REQUIRED: Signature verification.
+    pub fn validate_cancel_queued_action(&mut self, data: CancelQueuedData, sig_data: Vec<u8>) {
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"CancelQueuedAction",
+        );
+
+        self.internal_cancel_queued_action(data.queue_id.0);
+    }
+
+    /// Drops an action queued above the rate limits. Must be called by a
+    /// guardian. Its action id stays consumed and the deposits it holds
+    /// are returned to their payers.
+    /// FAILS: If the caller is not a guardian or the action doesn't exist.
+    pub fn cancel_queued_action(&mut self, queue_id: U64) {
+        require!(
+            self.guardians.contains(&env::predecessor_account_id()),
+            "Unauthorized"
+        );
+
+        self.internal_cancel_queued_action(queue_id.0);
+    }
+
+    /// Gets the action queued with the provided id.
+    pub fn get_queued_action(&self, queue_id: U64) -> Option<QueuedAction> {
+        self.queued_actions.get(&queue_id.0)
+    }
+
+    /// Gets the max number of incoming actions per hour on `collection`.
+    pub fn get_collection_rate_limit(&self, collection: AccountId) -> Option<u32> {
+        self.rate_limits.collection_limit(&collection)
+    }
+
+    /// Gets the max number of incoming actions per hour from `chain_nonce`.
+    pub fn get_chain_rate_limit(&self, chain_nonce: u8) -> Option<u32> {
+        self.rate_limits.chain_limit(chain_nonce)
+    }
+
+    /// Gets the delay in nanoseconds of the actions queued above the rate limits.
+    pub fn get_queue_delay(&self) -> U64 {
+        U64(self.rate_limits.queue_delay())
+    }
+}
+
+impl XpBridge {
+    /// Queues an action above the rate limits until the queue delay passes.
+    pub(crate) fn internal_queue_action(
+        &mut self,
+        chain_nonce: u8,
+        action_id: u128,
+        contract: AccountId,
+        action: QueuedBridgeAction,
+    ) {
+        self.total_queued_deposits += action.deposits();
+
+        self.queued_cnt += 1;
+        let eta = env::block_timestamp() + self.rate_limits.queue_delay();
+        self.queued_actions.insert(
+            &self.queued_cnt,
+            &QueuedAction {
+                action,
+                eta: U64(eta),
+            },
+        );
+
+        ActionQueuedEvent {
+            queue_id: self.queued_cnt,
+            chain_nonce,
+            action_id,
+            contract,
+            eta,
+        }
+        .emit();
+    }
+
+    fn internal_cancel_queued_action(&mut self, queue_id: u64) {
+        let queued = self
+            .queued_actions
+            .remove(&queue_id)
+            .unwrap_or_else(|| env::panic_str("Unknown queued action"));
+        self.total_queued_deposits -= queued.action.deposits();
+
+        match queued.action {
+            QueuedBridgeAction::TransferNft {
+                deposit,
+                sponsor,
+                sender,
+                ..
+            } => self.internal_refund_mint_deposit(sponsor, sender, deposit.0),
+            QueuedBridgeAction::ClaimTransfer {
+                claimant, deposit, ..
+            }
+            | QueuedBridgeAction::TransferMt {
+                sender: claimant,
+                deposit,
+                ..
+            } => {
+                if deposit.0 > 0 {
+                    self.internal_refund(claimant, deposit.0);
+                }
+            }
+            QueuedBridgeAction::ClaimVoucher { record, deposit } => {
+                if deposit.0 > 0 {
+                    self.internal_refund(record.voucher.transfer.owner_id, deposit.0);
+                }
+                if record.deposit.0 > 0 {
+                    self.internal_refund(record.depositor, record.deposit.0);
+                }
+            }
+            QueuedBridgeAction::UnfreezeNft { .. } | QueuedBridgeAction::UnfreezeMt { .. } => {}
+        }
+
+        QueuedActionCancelledEvent { queue_id }.emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    /// Limits chain 4 to one incoming action per window.
+    fn setup_limit() -> XpBridge {
+        let mut bridge = setup();
+        let data = ChainRateLimitData {
+            action_id: U128(1),
+            chain_nonce: 4,
+            limit: Some(1),
+        };
+        let sig = group_sig(b"SetChainRateLimit", &data);
+        bridge.validate_set_chain_rate_limit(data, sig);
+        assert_eq!(bridge.get_chain_rate_limit(4), Some(1));
+        bridge
+    }
+
+    fn transfer(bridge: &mut XpBridge, action_id: u128) {
+        let data = transfer_data(action_id, &action_id.to_string());
+        let sig = group_sig(b"ValidateTransferNft", &data);
+        bridge.validate_transfer_nft(data, sig);
+    }
+
+    /// Queues a claim of bob holding a deposit of 10.
+    fn queue_claim(bridge: &mut XpBridge) {
+        bridge.internal_queue_action(
+            4,
+            1,
+            account("wrapped"),
+            QueuedBridgeAction::ClaimTransfer {
+                leaf: Box::new(transfer_data(1, "1")),
+                claimant: account("bob"),
+                deposit: U128(10),
+            },
+        );
+    }
+
+    #[test]
+    fn actions_above_the_limit_are_queued() {
+        let mut bridge = setup_limit();
+
+        set_context(&account("relayer"), 10);
+        transfer(&mut bridge, 1);
+        transfer(&mut bridge, 2);
+
+        assert_eq!(
+            bridge.get_queued_action(U64(1)).unwrap().eta.0,
+            DEFAULT_QUEUE_DELAY
+        );
+        assert_eq!(bridge.total_queued_deposits, 10);
+        // Both action ids are consumed, the queued one can't be submitted again.
+        assert!(bridge.is_action_consumed(4, U128(2)));
+    }
+
+    #[test]
+    fn limit_changes_wait_for_the_timelock() {
+        let mut bridge = setup_limit();
+        bridge.timelock_delay = 100;
+        let data = ChainRateLimitData {
+            action_id: U128(2),
+            chain_nonce: 4,
+            limit: None,
+        };
+        let sig = group_sig(b"SetChainRateLimit", &data);
+        bridge.validate_set_chain_rate_limit(data, sig);
+
+        // The old limit applies until the change is executed.
+        set_context(&account("relayer"), 10);
+        transfer(&mut bridge, 1);
+        transfer(&mut bridge, 2);
+        assert!(bridge.get_queued_action(U64(1)).is_some());
+        assert_eq!(bridge.get_chain_rate_limit(4), Some(1));
+
+        testing_env!(context(&account("anyone"), 10).block_timestamp(100).build());
+        bridge.execute_timelock(U64(1));
+        assert_eq!(bridge.get_chain_rate_limit(4), None);
+
+        transfer(&mut bridge, 3);
+        assert!(bridge.get_queued_action(U64(2)).is_none());
+    }
+
+    #[test]
+    fn limits_apply_to_a_rolling_window() {
+        let mut bridge = setup_limit();
+        let collection = account("wrapped");
+
+        assert!(bridge.rate_limits.try_acquire(4, &collection));
+        assert!(!bridge.rate_limits.try_acquire(4, &collection));
+        assert!(bridge.rate_limits.try_acquire(5, &collection));
+
+        testing_env!(context(&account("relayer"), 0)
+            .block_timestamp(RATE_LIMIT_WINDOW)
+            .build());
+        assert!(bridge.rate_limits.try_acquire(4, &collection));
+    }
+
+    #[test]
+    fn queued_actions_execute_after_the_delay() {
+        let mut bridge = setup_limit();
+        set_context(&account("relayer"), 10);
+        transfer(&mut bridge, 1);
+        transfer(&mut bridge, 2);
+
+        testing_env!(context(&account("anyone"), 0)
+            .block_timestamp(DEFAULT_QUEUE_DELAY)
+            .build());
+        bridge.execute_queued_action(U64(1));
+
+        assert!(bridge.get_queued_action(U64(1)).is_none());
+        assert_eq!(bridge.total_queued_deposits, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Queued action not ready")]
+    fn queued_actions_wait_for_the_delay() {
+        let mut bridge = setup_limit();
+        set_context(&account("relayer"), 10);
+        transfer(&mut bridge, 1);
+        transfer(&mut bridge, 2);
+
+        testing_env!(context(&account("anyone"), 0)
+            .block_timestamp(DEFAULT_QUEUE_DELAY - 1)
+            .build());
+        bridge.execute_queued_action(U64(1));
+    }
+
+    #[test]
+    fn signed_cancel_returns_the_deposit() {
+        let mut bridge = setup();
+        queue_claim(&mut bridge);
+        assert_eq!(bridge.total_queued_deposits, 10);
+
+        let data = CancelQueuedData {
+            action_id: U128(1),
+            queue_id: U64(1),
+        };
+        let sig = group_sig(b"CancelQueuedAction", &data);
+        bridge.validate_cancel_queued_action(data, sig);
+
+        assert!(bridge.get_queued_action(U64(1)).is_none());
+        assert_eq!(bridge.total_queued_deposits, 0);
+        assert_eq!(transferred_to(&account("bob")), 10);
+    }
+
+    #[test]
+    fn guardians_can_cancel_queued_actions() {
+        let mut bridge = setup();
+        bridge.guardians.insert(&account("guardian"));
+        queue_claim(&mut bridge);
+
+        set_context(&account("guardian"), 0);
+        bridge.cancel_queued_action(U64(1));
+
+        assert!(bridge.get_queued_action(U64(1)).is_none());
+        assert_eq!(transferred_to(&account("bob")), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_guardians_can_cancel_without_a_signature() {
+        let mut bridge = setup();
+        queue_claim(&mut bridge);
+
+        set_context(&account("mallory"), 0);
+        bridge.cancel_queued_action(U64(1));
+    }
+}
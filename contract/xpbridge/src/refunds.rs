@@ -0,0 +1,219 @@
+use crate::*;
+
+const GAS_FOR_REFUND_CALLBACK: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_FT_REFUND: Gas = Gas(5_000_000_000_000);
+
+#[near_bindgen]
+impl XpBridge {
+    /// Transfers the refunds recorded for the caller after a failed refund.
+    /// FAILS: If the caller has no pending refunds.
+    pub fn claim_refund(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amt = self.pending_refunds.remove(&account_id).unwrap_or(0);
+        require!(amt > 0, "No pending refund");
+        self.total_pending_refunds -= amt;
+
+        self.internal_refund(account_id, amt)
+    }
+
+    /// Transfers the refunds of the NEP-141 token recorded for the caller
+    /// after a failed refund.
+    /// FAILS: If the caller has no pending refunds of the token.
+    pub fn claim_ft_refund(&mut self, token: AccountId) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amt = self
+            .pending_ft_refunds
+            .remove(&(account_id.clone(), token.clone()))
+            .unwrap_or(0);
+        require!(amt > 0, "No pending refund");
+
+        self.internal_refund_ft(account_id, token, amt)
+    }
+
+    /// This is the callback function when the transfer of a refund is
+    /// completed. Records the refund as claimable by the account if the
+    /// transfer failed.
+    #[private]
+    pub fn refund_callback(
+        &mut self,
+        account_id: AccountId,
+        amt: u128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => RefundIssuedEvent {
+                account_id,
+                amt,
+                token: None,
+            }
+            .emit(),
+            Err(_) => {
+                let pending = self.pending_refunds.get(&account_id).unwrap_or(0);
+                self.pending_refunds.insert(&account_id, &(pending + amt));
+                self.total_pending_refunds += amt;
+
+                RefundPendingEvent {
+                    account_id,
+                    amt,
+                    token: None,
+                }
+                .emit();
+            }
+        }
+    }
+
+    /// This is the callback function when the transfer of a refund in a
+    /// NEP-141 token is completed. Records the refund as claimable by the
+    /// account with `claim_ft_refund` if the transfer failed.
+    #[private]
+    pub fn ft_refund_callback(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amt: u128,
+        #[callback_result] call_result: Result<(), PromiseError>,
+    ) {
+        match call_result {
+            Ok(_) => RefundIssuedEvent {
+                account_id,
+                amt,
+                token: Some(token),
+            }
+            .emit(),
+            Err(_) => {
+                let key = (account_id.clone(), token.clone());
+                let pending = self.pending_ft_refunds.get(&key).unwrap_or(0);
+                self.pending_ft_refunds.insert(&key, &(pending + amt));
+
+                RefundPendingEvent {
+                    account_id,
+                    amt,
+                    token: Some(token),
+                }
+                .emit();
+            }
+        }
+    }
+
+    /// Gets the refunds recorded for `account_id` that can be claimed
+    /// with `claim_refund`.
+    pub fn get_pending_refund(&self, account_id: AccountId) -> U128 {
+        U128(self.pending_refunds.get(&account_id).unwrap_or(0))
+    }
+
+    /// Gets the refunds of the NEP-141 token recorded for `account_id`
+    /// that can be claimed with `claim_ft_refund`.
+    pub fn get_pending_ft_refund(&self, account_id: AccountId, token: AccountId) -> U128 {
+        U128(
+            self.pending_ft_refunds
+                .get(&(account_id, token))
+                .unwrap_or(0),
+        )
+    }
+}
+
+impl XpBridge {
+    /// Refunds `amt` to `account_id`. If the transfer fails the
+    /// refund is recorded for the account by `refund_callback`.
+    pub(crate) fn internal_refund(&mut self, account_id: AccountId, amt: u128) -> Promise {
+        Promise::new(account_id.clone()).transfer(amt).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_REFUND_CALLBACK)
+                .refund_callback(account_id, amt),
+        )
+    }
+
+    /// Refunds `amt` of the NEP-141 token to `account_id`. If the transfer
+    /// fails the refund is recorded for the account by `ft_refund_callback`.
+    pub(crate) fn internal_refund_ft(
+        &mut self,
+        account_id: AccountId,
+        token: AccountId,
+        amt: u128,
+    ) -> Promise {
+        fungible_token::ext(token.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(GAS_FOR_FT_REFUND)
+            .ft_transfer(account_id.clone(), U128(amt), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_REFUND_CALLBACK)
+                    .ft_refund_callback(account_id, token, amt),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn failed_refunds_become_claimable() {
+        let mut bridge = setup();
+
+        set_context(&bridge_id(), 0);
+        bridge.refund_callback(account("alice"), 10, Err(PromiseError::Failed));
+        bridge.refund_callback(account("alice"), 5, Err(PromiseError::Failed));
+        assert_eq!(bridge.get_pending_refund(account("alice")).0, 15);
+        assert_eq!(bridge.total_pending_refunds, 15);
+
+        set_context(&account("alice"), 0);
+        bridge.claim_refund();
+        assert_eq!(transferred_to(&account("alice")), 15);
+        assert_eq!(bridge.get_pending_refund(account("alice")).0, 0);
+        assert_eq!(bridge.total_pending_refunds, 0);
+    }
+
+    #[test]
+    fn successful_refunds_are_not_recorded() {
+        let mut bridge = setup();
+
+        set_context(&bridge_id(), 0);
+        bridge.refund_callback(account("alice"), 10, Ok(()));
+        assert_eq!(bridge.get_pending_refund(account("alice")).0, 0);
+    }
+
+    #[test]
+    fn failed_ft_refunds_are_kept_per_token() {
+        let mut bridge = setup();
+
+        set_context(&bridge_id(), 0);
+        bridge.ft_refund_callback(
+            account("alice"),
+            account("usdc"),
+            10,
+            Err(PromiseError::Failed),
+        );
+        assert_eq!(
+            bridge
+                .get_pending_ft_refund(account("alice"), account("usdc"))
+                .0,
+            10
+        );
+        assert_eq!(
+            bridge
+                .get_pending_ft_refund(account("alice"), account("dai"))
+                .0,
+            0
+        );
+
+        set_context(&account("alice"), 0);
+        bridge.claim_ft_refund(account("usdc"));
+        assert_eq!(
+            bridge
+                .get_pending_ft_refund(account("alice"), account("usdc"))
+                .0,
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "No pending refund")]
+    fn nothing_to_claim_without_failed_refunds() {
+        let mut bridge = setup();
+
+        set_context(&account("alice"), 0);
+        bridge.claim_refund();
+    }
+}
@@ -0,0 +1,518 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::metadata::NFTContractMetadata;
+use near_sdk::{serde_json, PromiseResult};
+
+/// Gas of a freeze which registers collections on top of
+/// its metadata lookups and `register_collection_callback`.
+const GAS_FOR_REGISTER_COLLECTION: Gas = Gas(21_000_000_000_000);
+const GAS_FOR_COLLECTION_METADATA: Gas = Gas(5_000_000_000_000);
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PermissionlessModeData {
+    pub action_id: U128,
+    pub enabled: bool,
+    pub registration_fee: U128,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BlockCollectionData {
+    pub action_id: U128,
+    pub collection: AccountId,
+    pub blocked: bool,
+}
+
+/// A collection registered by a freeze in permissionless mode. It is
+/// only recorded once one of its NFTs is frozen, otherwise its fee is
+/// returned to the sender.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingRegistration {
+    pub contract: AccountId,
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub fee: U128,
+}
+
+/// A freeze waiting for the metadata of the collections it registers.
+/// `amt` is the fee of the freeze, without the registration fees.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PendingFreeze {
+    Nft {
+        request: FreezeRequest,
+        sig_data: Vec<u8>,
+    },
+    Batch {
+        request: FreezeBatchRequest,
+        sig_data: Vec<u8>,
+    },
+    Ft {
+        request: FreezeRequest,
+        fee_token: AccountId,
+    },
+}
+
+impl PendingFreeze {
+    /// Gas of `register_collection_callback` continuing with the freeze.
+    fn callback_gas(&self) -> Gas {
+        match self {
+            Self::Nft { .. } => Gas(TGAS * 64),
+            // Every NFT may come with the registration of its collection.
+            Self::Batch { request, .. } => {
+                Gas(TGAS * 34
+                    + (GAS_PER_FREEZE_BATCH_ITEM.0 + TGAS * 4) * request.tokens.len() as u64)
+            }
+            Self::Ft { .. } => Gas(TGAS * 40),
+        }
+    }
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Enables or disables the permissionless mode. In permissionless mode,
+    /// the first freeze of an NFT of a collection which is not whitelisted
+    /// registers the collection, for `registration_fee` on top of the fee.
+    /// Freezes paid with a NEP-141 token have the registration covered by
+    /// their signed fee instead.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_permissionless_mode(
+        &mut self,
+        data: PermissionlessModeData,
+        sig_data: Vec<u8>,
+    ) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetPermissionlessMode",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetPermissionlessMode {
+                enabled: data.enabled,
+                registration_fee: data.registration_fee,
+            },
+        );
+    }
+
+    /// Blocks or unblocks a collection. NFTs of a blocked collection can't
+    /// be frozen, even if it is whitelisted or registered. NFTs which are
+    /// already frozen can still be unfrozen.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_collection_blocked(
+        &mut self,
+        data: BlockCollectionData,
+        sig_data: Vec<u8>,
+    ) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetCollectionBlocked",
+        );
+
+        if data.blocked {
+            self.blocked_collections.insert(&data.collection);
+        } else {
+            self.blocked_collections.remove(&data.collection);
+        }
+    }
+
+    /// This is the callback function when the metadata lookups of the
+    /// collections registered by a freeze are completed. Continues with the
+    /// freeze, which records the collections once their NFTs are frozen,
+    /// otherwise everything is returned to the sender.
+    #[private]
+    pub fn register_collection_callback(
+        &mut self,
+        contracts: Vec<AccountId>,
+        registration_fee: U128,
+        pending: PendingFreeze,
+    ) -> PromiseOrValue<U128> {
+        let fees = registration_fee.0 * contracts.len() as u128;
+        let mut registrations = Vec::with_capacity(contracts.len());
+        for (i, contract) in contracts.into_iter().enumerate() {
+            if self.blocked_collections.contains(&contract) {
+                env::log_str(&format!(
+                    "register collection callback: collection blocked: {}",
+                    contract
+                ));
+                return self.internal_cancel_pending_freeze(pending, fees);
+            }
+            let metadata = match env::promise_result(i as u64) {
+                PromiseResult::Successful(value) => {
+                    serde_json::from_slice::<NFTContractMetadata>(&value).ok()
+                }
+                _ => None,
+            };
+            let Some(metadata) = metadata else {
+                env::log_str(&format!(
+                    "register collection callback: failed to get metadata: {}",
+                    contract
+                ));
+                return self.internal_cancel_pending_freeze(pending, fees);
+            };
+
+            registrations.push(PendingRegistration {
+                contract,
+                spec: metadata.spec,
+                name: metadata.name,
+                symbol: metadata.symbol,
+                base_uri: metadata.base_uri,
+                reference: metadata.reference,
+                fee: registration_fee,
+            });
+        }
+
+        match pending {
+            PendingFreeze::Nft { request, sig_data } => self
+                .internal_freeze_nft(request, sig_data, registrations.pop())
+                .into(),
+            PendingFreeze::Batch { request, sig_data } => self
+                .internal_freeze_nft_batch(request, sig_data, registrations)
+                .into(),
+            PendingFreeze::Ft { request, fee_token } => self
+                .internal_ft_freeze_nft(request, fee_token, registrations.pop())
+                .into(),
+        }
+    }
+
+    /// Checks if the permissionless mode is enabled.
+    pub fn is_permissionless_mode(&self) -> bool {
+        self.permissionless_mode
+    }
+
+    /// Gets the fee charged for registering a collection in permissionless mode.
+    pub fn get_registration_fee(&self) -> U128 {
+        U128(self.registration_fee)
+    }
+
+    /// Checks if the provided collection was registered in permissionless mode.
+    pub fn is_registered_collection(&self, collection: AccountId) -> bool {
+        self.registered_collections.contains(&collection)
+    }
+
+    /// Checks if the provided collection is blocked.
+    pub fn is_blocked_collection(&self, collection: AccountId) -> bool {
+        self.blocked_collections.contains(&collection)
+    }
+}
+
+impl XpBridge {
+    /// Takes the registration fees of `count` collections out of the
+    /// attached deposit and returns the rest of it, which is the fee
+    /// of the freeze.
+    /// FAILS: If the permissionless mode is disabled or the attached deposit
+    /// doesn't cover the registration fees.
+    pub(crate) fn internal_take_registration_fees(&self, count: usize) -> u128 {
+        require!(self.permissionless_mode, "Collection not whitelisted");
+        let fees = self.registration_fee * count as u128;
+        let attached = env::attached_deposit();
+        require!(attached >= fees, "Not enough registration fee");
+        attached - fees
+    }
+
+    /// Reads the metadata of the collections which are not known yet and
+    /// then calls the callback function 'register_collection_callback',
+    /// which continues with the `pending` freeze.
+    /// FAILS: If there is not enough gas for the lookups and the freeze.
+    pub(crate) fn internal_register_collections(
+        &mut self,
+        contracts: Vec<AccountId>,
+        pending: PendingFreeze,
+    ) -> Promise {
+        let callback_gas = pending.callback_gas();
+        require!(
+            env::prepaid_gas()
+                >= GAS_FOR_REGISTER_COLLECTION
+                    + callback_gas
+                    + Gas(GAS_FOR_COLLECTION_METADATA.0 * contracts.len() as u64),
+            "Not enough gas"
+        );
+
+        contracts
+            .iter()
+            .map(|contract| {
+                common_nft::ext(contract.clone())
+                    .with_static_gas(GAS_FOR_COLLECTION_METADATA)
+                    .nft_metadata()
+            })
+            .reduce(|acc, p| acc.and(p))
+            .unwrap()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(callback_gas)
+                    .register_collection_callback(
+                        contracts,
+                        U128(match pending {
+                            PendingFreeze::Ft { .. } => 0,
+                            _ => self.registration_fee,
+                        }),
+                        pending,
+                    ),
+            )
+    }
+
+    /// Records the collections registered by a freeze which froze one of
+    /// their NFTs and collects their fees. Returns the fees of the others,
+    /// which are owed back to the sender.
+    pub(crate) fn internal_settle_registrations(
+        &mut self,
+        registrations: impl IntoIterator<Item = PendingRegistration>,
+        frozen: impl Fn(&AccountId) -> bool,
+    ) -> u128 {
+        let mut refund = 0;
+        for registration in registrations {
+            if !frozen(&registration.contract)
+                || !self.registered_collections.insert(&registration.contract)
+            {
+                // Nothing was frozen or registered by another freeze in the meantime.
+                refund += registration.fee.0;
+                continue;
+            }
+
+            self.tx_fees += registration.fee.0;
+            CollectionRegisteredEvent {
+                contract: registration.contract,
+                spec: registration.spec,
+                name: registration.name,
+                symbol: registration.symbol,
+                base_uri: registration.base_uri,
+                reference: registration.reference,
+                registration_fee: registration.fee.0,
+            }
+            .emit();
+        }
+        refund
+    }
+
+    /// Returns everything paid for a freeze whose collections couldn't be
+    /// registered. `registration_fees` is the part of the fees taken from
+    /// the attached deposit which is not part of `pending` yet.
+    fn internal_cancel_pending_freeze(
+        &mut self,
+        pending: PendingFreeze,
+        registration_fees: u128,
+    ) -> PromiseOrValue<U128> {
+        match pending {
+            PendingFreeze::Nft { request, .. } => {
+                self.internal_refund(request.sender, request.amt.0 + registration_fees);
+                PromiseOrValue::Value(U128(0))
+            }
+            PendingFreeze::Batch { request, .. } => {
+                self.internal_refund(request.sender, request.amt.0 + registration_fees);
+                PromiseOrValue::Value(U128(0))
+            }
+            // The fee token is returned by `ft_resolve_transfer`.
+            PendingFreeze::Ft { request, .. } => PromiseOrValue::Value(request.amt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::get_created_receipts;
+
+    /// Enables the permissionless mode with a registration fee of 100.
+    fn setup_permissionless() -> XpBridge {
+        let mut bridge = setup();
+        let data = PermissionlessModeData {
+            action_id: U128(1),
+            enabled: true,
+            registration_fee: U128(100),
+        };
+        let sig = group_sig(b"SetPermissionlessMode", &data);
+        bridge.validate_set_permissionless_mode(data, sig);
+        assert!(bridge.is_permissionless_mode());
+        bridge
+    }
+
+    fn freeze(bridge: &mut XpBridge, deposit: u128) {
+        set_context(&account("alice"), deposit);
+        bridge.freeze_nft(
+            account("nft"),
+            "1".to_string(),
+            4,
+            "0xreceiver".to_string(),
+            "0xwrapped".to_string(),
+            vec![],
+        );
+    }
+
+    fn pending_freeze() -> PendingFreeze {
+        PendingFreeze::Nft {
+            request: freeze_request("alice"),
+            sig_data: vec![],
+        }
+    }
+
+    fn registration() -> PendingRegistration {
+        PendingRegistration {
+            contract: account("nft"),
+            spec: "nft-1.0.0".to_string(),
+            name: "Collection".to_string(),
+            symbol: "COL".to_string(),
+            base_uri: None,
+            reference: None,
+            fee: U128(100),
+        }
+    }
+
+    fn metadata_result() -> PromiseResult {
+        let metadata = NFTContractMetadata {
+            spec: "nft-1.0.0".to_string(),
+            name: "Collection".to_string(),
+            symbol: "COL".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        };
+        PromiseResult::Successful(serde_json::to_vec(&metadata).unwrap())
+    }
+
+    fn called(function: &str) -> bool {
+        get_created_receipts().iter().any(|r| {
+            r.actions.iter().any(|a| {
+                matches!(a, VmAction::FunctionCall { function_name, .. } if function_name == function)
+            })
+        })
+    }
+
+    #[test]
+    fn unknown_collections_are_looked_up_first() {
+        let mut bridge = setup_permissionless();
+
+        freeze(&mut bridge, 110);
+
+        assert!(called("nft_metadata"));
+        assert!(!called("verify_paid_amount_by_sig"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough registration fee")]
+    fn registration_fee_must_be_attached() {
+        let mut bridge = setup_permissionless();
+
+        freeze(&mut bridge, 99);
+    }
+
+    #[test]
+    fn registration_continues_with_the_freeze() {
+        let mut bridge = setup_permissionless();
+
+        set_promise_results(vec![metadata_result()]);
+        bridge.register_collection_callback(vec![account("nft")], U128(100), pending_freeze());
+
+        assert!(called("verify_paid_amount_by_sig"));
+        // The collection is only recorded once the NFT is frozen.
+        assert!(!bridge.is_registered_collection(account("nft")));
+    }
+
+    #[test]
+    fn frozen_nft_records_the_collection() {
+        let mut bridge = setup_permissionless();
+
+        set_context(&bridge_id(), 0);
+        bridge.freeze_callback(
+            freeze_request("alice"),
+            Some(registration()),
+            Ok(()),
+            Err(PromiseError::Failed),
+        );
+
+        assert!(bridge.is_registered_collection(account("nft")));
+        assert!(bridge.is_bridgeable(&account("nft")));
+        assert_eq!(bridge.tx_fees, 110);
+    }
+
+    #[test]
+    fn failed_freeze_returns_the_registration_fee() {
+        let mut bridge = setup_permissionless();
+
+        set_context(&bridge_id(), 0);
+        bridge.check_enough_fees_callback_for_transfer(
+            freeze_request("alice"),
+            Some(registration()),
+            Err(PromiseError::Failed),
+        );
+
+        assert!(!bridge.is_registered_collection(account("nft")));
+        assert_eq!(bridge.tx_fees, 0);
+        assert_eq!(transferred_to(&account("alice")), 110);
+    }
+
+    #[test]
+    fn blocked_collections_are_not_registered() {
+        let mut bridge = setup_permissionless();
+        let data = BlockCollectionData {
+            action_id: U128(2),
+            collection: account("nft"),
+            blocked: true,
+        };
+        let sig = group_sig(b"SetCollectionBlocked", &data);
+        bridge.validate_set_collection_blocked(data, sig);
+
+        set_promise_results(vec![metadata_result()]);
+        bridge.register_collection_callback(vec![account("nft")], U128(100), pending_freeze());
+
+        assert!(!called("verify_paid_amount_by_sig"));
+        assert_eq!(transferred_to(&account("alice")), 110);
+    }
+
+    #[test]
+    #[should_panic(expected = "Collection blocked")]
+    fn blocked_collections_cannot_be_frozen() {
+        let mut bridge = setup_permissionless();
+        bridge.internal_whitelist(&account("nft"), 2, None);
+        bridge.blocked_collections.insert(&account("nft"));
+
+        freeze(&mut bridge, 10);
+    }
+
+    #[test]
+    fn batch_returns_the_fee_of_collections_without_frozen_nfts() {
+        let mut bridge = setup_permissionless();
+        let tokens = ["nft", "art"]
+            .iter()
+            .map(|name| FreezeBatchItem {
+                token_contract: account(name),
+                token_id: "1".to_string(),
+                mint_with: "0xwrapped".to_string(),
+            })
+            .collect();
+        let art = PendingRegistration {
+            contract: account("art"),
+            ..registration()
+        };
+
+        set_promise_results(vec![
+            PromiseResult::Successful(vec![]),
+            PromiseResult::Failed,
+            PromiseResult::Failed,
+            PromiseResult::Failed,
+        ]);
+        bridge.freeze_batch_callback(freeze_batch_request(tokens), vec![registration(), art]);
+
+        assert!(bridge.is_registered_collection(account("nft")));
+        assert!(!bridge.is_registered_collection(account("art")));
+        // The fee share of the failed NFT and the fee of its collection.
+        assert_eq!(transferred_to(&account("alice")), 5 + 100);
+    }
+}
@@ -0,0 +1,493 @@
+use crate::*;
+
+/// Number of action ids tracked by a single bucket of the replay set.
+pub const REPLAY_BUCKET_SIZE: u128 = 256;
+/// Default time after the last use of a bucket before it can be pruned (30 days).
+pub const DEFAULT_REPLAY_EXPIRY: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+/// Min time after the last use of a bucket before it can be pruned (1 day).
+/// Pruning is permissionless, so the expiry must leave time for the callbacks
+/// of an action to release its id and for action ids submitted out of order.
+pub const MIN_REPLAY_EXPIRY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ReplayBucket {
+    bits: [u64; 4],
+    updated_at: u64,
+}
+
+impl ReplayBucket {
+    fn is_set(&self, offset: usize) -> bool {
+        self.bits[offset / 64] & (1 << (offset % 64)) != 0
+    }
+
+    fn set(&mut self, offset: usize) {
+        self.bits[offset / 64] |= 1 << (offset % 64);
+    }
+
+    fn clear(&mut self, offset: usize) {
+        self.bits[offset / 64] &= !(1 << (offset % 64));
+    }
+}
+
+/// Replay window of the action ids of a single source chain.
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct ReplayWindow {
+    floor: u128,
+    bucket_count: u64,
+    storage_usage: u64,
+}
+
+/// Replay protection for signed actions.
+/// Action ids are namespaced by the nonce of the chain they come from.
+/// Consumed action ids are kept as bits in buckets of `REPLAY_BUCKET_SIZE`
+/// consecutive ids. The floor of a chain starts at the bucket of its first
+/// consumed action id. Buckets which were not used for `expiry` nanoseconds
+/// can be pruned from the bottom, which moves the floor of the chain past
+/// them. Every action id below the floor is considered consumed, so
+/// validators are expected to use increasing action ids for every chain.
+/// The action ids consumed before they were namespaced by chain are kept
+/// in `legacy` and are consumed on every chain until they are moved to the
+/// buckets of the NEAR chain nonce with `prune_legacy`.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ReplayGuard {
+    legacy: UnorderedSet<u128>,
+    buckets: LookupMap<(u8, u128), ReplayBucket>,
+    windows: LookupMap<u8, ReplayWindow>,
+    storage_usage: u64,
+    expiry: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReplayStorageView {
+    pub chain_nonce: u8,
+    pub floor: U128,
+    pub buckets: u64,
+    pub storage_usage: U64,
+    pub expiry: U64,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReplayExpiryData {
+    pub action_id: U128,
+    pub expiry: U64,
+}
+
+impl ReplayGuard {
+    pub fn new(prefix: &[u8]) -> Self {
+        Self::from_legacy(prefix, UnorderedSet::new(prefix))
+    }
+
+    /// Creates the guard of a contract which kept its consumed action ids
+    /// in `legacy`, which was created with the same `prefix`.
+    pub fn from_legacy(prefix: &[u8], legacy: UnorderedSet<u128>) -> Self {
+        Self {
+            legacy,
+            buckets: LookupMap::new([prefix, b"b"].concat()),
+            windows: LookupMap::new([prefix, b"w"].concat()),
+            storage_usage: 0,
+            expiry: DEFAULT_REPLAY_EXPIRY,
+        }
+    }
+
+    pub fn is_consumed(&self, chain_nonce: u8, action_id: u128) -> bool {
+        if self.legacy.contains(&action_id) {
+            return true;
+        }
+        let floor = self.windows.get(&chain_nonce).unwrap_or_default().floor;
+        if action_id < floor {
+            return true;
+        }
+        self.buckets
+            .get(&(chain_nonce, action_id / REPLAY_BUCKET_SIZE))
+            .map(|b| b.is_set((action_id % REPLAY_BUCKET_SIZE) as usize))
+            .unwrap_or(false)
+    }
+
+    /// Marks the action id of the chain as consumed.
+    /// FAILS: If the action id was already consumed.
+    pub fn consume(&mut self, chain_nonce: u8, action_id: u128) {
+        require!(
+            !self.is_consumed(chain_nonce, action_id),
+            "Duplicated Action"
+        );
+
+        self.insert(chain_nonce, action_id);
+    }
+
+    /// Sets the bit of the action id in its bucket, starting the window of
+    /// the chain at that bucket if it is the first action id of the chain.
+    fn insert(&mut self, chain_nonce: u8, action_id: u128) {
+        let index = action_id / REPLAY_BUCKET_SIZE;
+        let mut window = self
+            .windows
+            .get(&chain_nonce)
+            .unwrap_or_else(|| ReplayWindow {
+                floor: index * REPLAY_BUCKET_SIZE,
+                ..Default::default()
+            });
+        let mut bucket = self.buckets.get(&(chain_nonce, index)).unwrap_or_else(|| {
+            window.bucket_count += 1;
+            ReplayBucket {
+                bits: [0; 4],
+                updated_at: 0,
+            }
+        });
+        bucket.set((action_id % REPLAY_BUCKET_SIZE) as usize);
+        bucket.updated_at = env::block_timestamp();
+        self.write_bucket(&mut window, chain_nonce, index, Some(bucket));
+        self.windows.insert(&chain_nonce, &window);
+    }
+
+    /// Releases a consumed action id so it can be submitted again.
+    /// Used when the promise of an action failed. The bucket is kept even
+    /// if it becomes empty, and its use is renewed, so that pruning doesn't
+    /// move the floor past the released action id before it expires.
+    pub fn release(&mut self, chain_nonce: u8, action_id: u128) {
+        let index = action_id / REPLAY_BUCKET_SIZE;
+        let Some(mut bucket) = self.buckets.get(&(chain_nonce, index)) else {
+            return;
+        };
+        let mut window = self.windows.get(&chain_nonce).unwrap_or_default();
+        bucket.clear((action_id % REPLAY_BUCKET_SIZE) as usize);
+        bucket.updated_at = env::block_timestamp();
+        self.write_bucket(&mut window, chain_nonce, index, Some(bucket));
+        self.windows.insert(&chain_nonce, &window);
+    }
+
+    /// Removes the buckets of the chain from the bottom of the replay set
+    /// which were not used for `expiry` nanoseconds, moving the floor past
+    /// them. Buckets which don't exist are skipped, but the floor only moves
+    /// past them with the expired bucket above them, and never past a bucket
+    /// which is not expired. At most `limit` bucket indexes are scanned.
+    /// Returns the number of buckets removed.
+    pub fn prune(&mut self, chain_nonce: u8, limit: u32) -> u32 {
+        let Some(mut window) = self.windows.get(&chain_nonce) else {
+            return 0;
+        };
+        let now = env::block_timestamp();
+        let first = window.floor / REPLAY_BUCKET_SIZE;
+        let mut pruned = 0;
+        for index in first..first + limit as u128 {
+            if window.bucket_count == 0 {
+                break;
+            }
+            if let Some(bucket) = self.buckets.get(&(chain_nonce, index)) {
+                if bucket.updated_at.saturating_add(self.expiry) > now {
+                    break;
+                }
+                window.bucket_count -= 1;
+                self.write_bucket(&mut window, chain_nonce, index, None);
+                window.floor = (index + 1) * REPLAY_BUCKET_SIZE;
+                pruned += 1;
+            }
+        }
+        self.windows.insert(&chain_nonce, &window);
+        pruned
+    }
+
+    /// Moves up to `limit` legacy action ids to the buckets of `chain_nonce`,
+    /// where they expire like the other action ids of the chain.
+    /// Returns the number of action ids moved.
+    pub fn prune_legacy(&mut self, chain_nonce: u8, limit: u32) -> u32 {
+        let action_ids: Vec<u128> = self.legacy.iter().take(limit as usize).collect();
+        for action_id in action_ids.iter() {
+            self.legacy.remove(action_id);
+            if !self.is_consumed(chain_nonce, *action_id) {
+                self.insert(chain_nonce, *action_id);
+            }
+        }
+        action_ids.len() as u32
+    }
+
+    pub fn legacy_len(&self) -> u64 {
+        self.legacy.len()
+    }
+
+    pub fn set_expiry(&mut self, expiry: u64) {
+        self.expiry = expiry;
+    }
+
+    pub fn storage_usage(&self) -> u64 {
+        self.storage_usage
+    }
+
+    pub fn view(&self, chain_nonce: u8) -> ReplayStorageView {
+        let window = self.windows.get(&chain_nonce).unwrap_or_default();
+        ReplayStorageView {
+            chain_nonce,
+            floor: U128(window.floor),
+            buckets: window.bucket_count,
+            storage_usage: U64(window.storage_usage),
+            expiry: U64(self.expiry),
+        }
+    }
+
+    /// Writes or removes a bucket while keeping track of
+    /// the storage used by the replay set.
+    fn write_bucket(
+        &mut self,
+        window: &mut ReplayWindow,
+        chain_nonce: u8,
+        index: u128,
+        bucket: Option<ReplayBucket>,
+    ) {
+        let initial_storage = env::storage_usage();
+        match bucket {
+            Some(bucket) => {
+                self.buckets.insert(&(chain_nonce, index), &bucket);
+            }
+            None => {
+                self.buckets.remove(&(chain_nonce, index));
+            }
+        }
+        let final_storage = env::storage_usage();
+        window.storage_usage =
+            (window.storage_usage + final_storage).saturating_sub(initial_storage);
+        self.storage_usage = (self.storage_usage + final_storage).saturating_sub(initial_storage);
+    }
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Sets the time in nanoseconds after the last use of a bucket of the
+    /// replay set before it can be pruned with `prune_consumed_actions`.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused or the expiry is below `MIN_REPLAY_EXPIRY`.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_replay_expiry(&mut self, data: ReplayExpiryData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+        require!(
+            data.expiry.0 >= MIN_REPLAY_EXPIRY,
+            "Replay expiry too short"
+        );
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetReplayExpiry",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetReplayExpiry {
+                expiry: data.expiry,
+            },
+        );
+    }
+
+    /// Prunes up to `limit` expired buckets of the chain from the replay set,
+    /// freeing the storage staked for them. Can be called by anyone.
+    /// Returns the number of buckets pruned.
+    pub fn prune_consumed_actions(&mut self, chain_nonce: u8, limit: u32) -> u32 {
+        self.consumed_actions.prune(chain_nonce, limit)
+    }
+
+    /// Moves up to `limit` of the action ids consumed before the replay set
+    /// was namespaced by chain to the buckets of the NEAR chain nonce, so they
+    /// can be pruned like the others. Can be called by anyone. Only the actions
+    /// signed for this contract itself are kept, the transfers signed before
+    /// can't be replayed as their data changed since.
+    /// Returns the number of action ids moved.
+    pub fn prune_legacy_actions(&mut self, limit: u32) -> u32 {
+        self.consumed_actions.prune_legacy(CHAIN_NONCE, limit)
+    }
+
+    /// Gets the number of action ids consumed before the replay set was
+    /// namespaced by chain which were not moved yet.
+    pub fn get_legacy_action_count(&self) -> U64 {
+        U64(self.consumed_actions.legacy_len())
+    }
+
+    /// Checks if the provided action id of the chain was already consumed.
+    /// Actions signed for this contract itself use the NEAR chain nonce.
+    pub fn is_action_consumed(&self, chain_nonce: u8, action_id: U128) -> bool {
+        self.consumed_actions.is_consumed(chain_nonce, action_id.0)
+    }
+
+    /// Gets the floor, size and storage usage of the replay set of the chain.
+    pub fn get_replay_storage(&self, chain_nonce: u8) -> ReplayStorageView {
+        self.consumed_actions.view(chain_nonce)
+    }
+
+    /// Gets the storage used by the replay set of all the chains in bytes.
+    pub fn get_replay_storage_usage(&self) -> U64 {
+        U64(self.consumed_actions.storage_usage())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    fn at(timestamp: u64) {
+        testing_env!(context(&account("relayer"), 0)
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    #[test]
+    fn released_action_ids_can_be_consumed_again() {
+        let mut bridge = setup();
+
+        bridge.consumed_actions.consume(4, 7);
+        assert!(bridge.is_action_consumed(4, U128(7)));
+        assert!(!bridge.is_action_consumed(5, U128(7)));
+
+        bridge.consumed_actions.release(4, 7);
+        assert!(!bridge.is_action_consumed(4, U128(7)));
+        bridge.consumed_actions.consume(4, 7);
+        assert!(bridge.is_action_consumed(4, U128(7)));
+    }
+
+    #[test]
+    fn pruning_moves_the_floor_past_expired_buckets() {
+        let mut bridge = setup();
+        let data = ReplayExpiryData {
+            action_id: U128(1),
+            expiry: U64(MIN_REPLAY_EXPIRY),
+        };
+        let sig = group_sig(b"SetReplayExpiry", &data);
+        bridge.validate_set_replay_expiry(data, sig);
+
+        at(0);
+        bridge.consumed_actions.consume(4, 1);
+        bridge.consumed_actions.consume(4, 300);
+        bridge.consumed_actions.consume(5, 1);
+        at(MIN_REPLAY_EXPIRY / 2);
+        bridge.consumed_actions.consume(4, 600);
+        let usage = bridge.get_replay_storage_usage().0;
+        assert_eq!(bridge.get_replay_storage(4).buckets, 3);
+
+        at(MIN_REPLAY_EXPIRY);
+        assert_eq!(bridge.prune_consumed_actions(4, 10), 2);
+
+        let view = bridge.get_replay_storage(4);
+        assert_eq!(view.floor.0, 2 * REPLAY_BUCKET_SIZE);
+        assert_eq!(view.buckets, 1);
+        assert!(bridge.get_replay_storage_usage().0 < usage);
+        // Everything below the floor stays consumed.
+        assert!(bridge.is_action_consumed(4, U128(2)));
+        assert!(bridge.is_action_consumed(4, U128(600)));
+        assert!(!bridge.is_action_consumed(4, U128(601)));
+        // The other chains are not pruned.
+        assert_eq!(bridge.get_replay_storage(5).buckets, 1);
+        assert!(!bridge.is_action_consumed(5, U128(2)));
+    }
+
+    #[test]
+    fn floor_starts_at_the_first_bucket() {
+        let mut bridge = setup();
+
+        bridge.consumed_actions.consume(4, 1_000);
+        assert_eq!(bridge.get_replay_storage(4).floor.0, 3 * REPLAY_BUCKET_SIZE);
+        assert!(bridge.is_action_consumed(4, U128(1)));
+        assert!(!bridge.is_action_consumed(4, U128(1_001)));
+    }
+
+    #[test]
+    fn pruning_skips_missing_buckets() {
+        let mut bridge = setup();
+        bridge.consumed_actions.set_expiry(100);
+
+        at(0);
+        bridge.consumed_actions.consume(4, 1);
+        bridge.consumed_actions.consume(4, 1_000);
+        at(50);
+        bridge.consumed_actions.consume(4, 2_000);
+
+        // The bucket of 2000 is not expired, the floor stops below it.
+        at(120);
+        assert_eq!(bridge.prune_consumed_actions(4, 100), 2);
+        assert_eq!(bridge.get_replay_storage(4).floor.0, 4 * REPLAY_BUCKET_SIZE);
+        assert!(!bridge.is_action_consumed(4, U128(1_999)));
+
+        at(150);
+        assert_eq!(bridge.prune_consumed_actions(4, 100), 1);
+        let view = bridge.get_replay_storage(4);
+        assert_eq!(view.floor.0, 8 * REPLAY_BUCKET_SIZE);
+        assert_eq!(view.buckets, 0);
+    }
+
+    #[test]
+    fn pruning_scans_at_most_the_limit() {
+        let mut bridge = setup();
+        bridge.consumed_actions.set_expiry(0);
+
+        at(0);
+        bridge.consumed_actions.consume(4, 1);
+        bridge.consumed_actions.consume(4, 1_000);
+        at(1);
+        assert_eq!(bridge.prune_consumed_actions(4, 2), 1);
+        assert_eq!(bridge.get_replay_storage(4).floor.0, REPLAY_BUCKET_SIZE);
+        assert_eq!(bridge.prune_consumed_actions(4, 2), 0);
+        assert_eq!(bridge.prune_consumed_actions(4, 3), 1);
+        assert_eq!(bridge.get_replay_storage(4).floor.0, 4 * REPLAY_BUCKET_SIZE);
+    }
+
+    #[test]
+    fn legacy_action_ids_move_to_the_near_buckets() {
+        set_context(&bridge_id(), 0);
+        let mut legacy = UnorderedSet::new(b"c");
+        legacy.insert(&5);
+        legacy.insert(&7);
+        let mut bridge = setup();
+        bridge.consumed_actions = ReplayGuard::from_legacy(b"c", legacy);
+        assert!(bridge.is_action_consumed(4, U128(5)));
+
+        assert_eq!(bridge.prune_legacy_actions(1), 1);
+        assert_eq!(bridge.get_legacy_action_count().0, 1);
+        assert_eq!(bridge.prune_legacy_actions(10), 1);
+        assert_eq!(bridge.get_legacy_action_count().0, 0);
+
+        assert!(bridge.is_action_consumed(CHAIN_NONCE, U128(5)));
+        assert!(bridge.is_action_consumed(CHAIN_NONCE, U128(7)));
+        assert!(!bridge.is_action_consumed(CHAIN_NONCE, U128(8)));
+        assert!(!bridge.is_action_consumed(4, U128(5)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Replay expiry too short")]
+    fn replay_expiry_is_bounded() {
+        let mut bridge = setup();
+        let data = ReplayExpiryData {
+            action_id: U128(1),
+            expiry: U64(MIN_REPLAY_EXPIRY - 1),
+        };
+        let sig = group_sig(b"SetReplayExpiry", &data);
+        bridge.validate_set_replay_expiry(data, sig);
+    }
+
+    #[test]
+    fn release_renews_the_bucket() {
+        let mut bridge = setup();
+        bridge.consumed_actions.set_expiry(100);
+
+        at(0);
+        bridge.consumed_actions.consume(4, 1);
+        bridge.consumed_actions.consume(4, 2);
+        at(80);
+        bridge.consumed_actions.release(4, 2);
+
+        at(120);
+        assert_eq!(bridge.prune_consumed_actions(4, 10), 0);
+        assert!(!bridge.is_action_consumed(4, U128(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicated Action")]
+    fn action_ids_below_the_floor_cannot_be_consumed() {
+        let mut bridge = setup();
+        bridge.consumed_actions.set_expiry(0);
+
+        at(0);
+        bridge.consumed_actions.consume(4, 1);
+        at(1);
+        bridge.prune_consumed_actions(4, 1);
+        bridge.consumed_actions.consume(4, 2);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::*;
+use near_sdk::PromiseResult;
+use std::collections::HashMap;
+
+/// Royalties are expressed in basis points of the sale price. The payout
+/// of a source collection is requested for this balance to read them.
+pub const ROYALTY_BASIS_POINTS: u128 = 10_000;
+/// Maximum number of royalty accounts read from a source collection.
+pub const MAX_ROYALTY_ACCOUNTS: u32 = 10;
+
+pub const GAS_FOR_NFT_PAYOUT: Gas = Gas(3_000_000_000_000);
+
+/// NEP-199 payout: the amount owed to every account for a sale.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+/// Requests the NEP-199 payout of a token for `ROYALTY_BASIS_POINTS`.
+pub(crate) fn nft_payout(token_contract: AccountId, token_id: TokenId) -> Promise {
+    common_nft::ext(token_contract)
+        .with_static_gas(GAS_FOR_NFT_PAYOUT)
+        .nft_payout(
+            token_id,
+            U128(ROYALTY_BASIS_POINTS),
+            Some(MAX_ROYALTY_ACCOUNTS + 1),
+        )
+}
+
+/// Gets the royalty split in basis points from the payout of a frozen
+/// token. The payout is requested once the token is owned by the bridge,
+/// so only the share of the bridge is dropped as the share of the owner.
+/// A sender which is also a royalty account keeps its share.
+/// Collections which don't implement NEP-199 have no royalties.
+pub(crate) fn royalty_from_payout(payout: Option<Payout>) -> Option<HashMap<AccountId, u32>> {
+    let royalty: HashMap<AccountId, u32> = payout?
+        .payout
+        .into_iter()
+        .filter(|(account_id, _)| *account_id != env::current_account_id())
+        .filter(|(_, amount)| amount.0 > 0 && amount.0 <= ROYALTY_BASIS_POINTS)
+        .map(|(account_id, amount)| (account_id, amount.0 as u32))
+        .collect();
+    (!royalty.is_empty()).then_some(royalty)
+}
+
+/// Parses the payout of a token from the result of `nft_payout`.
+pub(crate) fn payout_from_result(result: PromiseResult) -> Option<Payout> {
+    match result {
+        PromiseResult::Successful(value) => near_sdk::serde_json::from_slice(&value).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn payout(entries: &[(AccountId, u128)]) -> Option<Payout> {
+        Some(Payout {
+            payout: entries
+                .iter()
+                .map(|(account_id, amount)| (account_id.clone(), U128(*amount)))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn royalty_drops_the_owner_share() {
+        set_context(&bridge_id(), 0);
+        let royalty = royalty_from_payout(payout(&[
+            (account("artist"), 500),
+            (account("alice"), 300),
+            (bridge_id(), 9_200),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            royalty,
+            HashMap::from([(account("artist"), 500), (account("alice"), 300)])
+        );
+    }
+
+    #[test]
+    fn royalty_ignores_invalid_shares() {
+        set_context(&bridge_id(), 0);
+        let royalty = royalty_from_payout(payout(&[
+            (account("artist"), 0),
+            (account("studio"), ROYALTY_BASIS_POINTS + 1),
+        ]));
+
+        assert!(royalty.is_none());
+    }
+
+    #[test]
+    fn collections_without_payout_have_no_royalty() {
+        set_context(&bridge_id(), 0);
+        assert!(royalty_from_payout(None).is_none());
+        assert!(payout_from_result(PromiseResult::Failed).is_none());
+        assert!(payout_from_result(PromiseResult::Successful(b"{}".to_vec())).is_none());
+    }
+
+    #[test]
+    fn frozen_nfts_carry_their_royalty() {
+        let mut bridge = setup();
+        bridge.internal_whitelist(&account("nft"), 1, None);
+
+        set_context(&bridge_id(), 0);
+        bridge.freeze_callback(
+            freeze_request("alice"),
+            None,
+            Ok(()),
+            Ok(payout(&[(account("artist"), 500), (bridge_id(), 9_500)]).unwrap()),
+        );
+
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs[0].contains(r#""royalty":{"artist.near":500}"#));
+    }
+}
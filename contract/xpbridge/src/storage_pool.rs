@@ -0,0 +1,337 @@
+use crate::*;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::assert_one_yocto;
+
+/// Default deposit attached to a mint paid from the storage pool (0.01 NEAR).
+pub const DEFAULT_MINT_STORAGE_COST: u128 = 10_000_000_000_000_000_000_000;
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageSponsorData {
+    pub action_id: U128,
+    pub collection: AccountId,
+    pub sponsor: Option<AccountId>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MintStorageCostData {
+    pub action_id: U128,
+    pub amount: U128,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Sets the account whose storage pool balance pays for the mints
+    /// on `collection` when no deposit is attached by the validator.
+    /// A `sponsor` of None removes the sponsor of the collection.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_storage_sponsor(&mut self, data: StorageSponsorData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetStorageSponsor",
+        );
+
+        match data.sponsor {
+            Some(sponsor) => self.storage_sponsors.insert(&data.collection, &sponsor),
+            None => self.storage_sponsors.remove(&data.collection),
+        };
+    }
+
+    /// Sets the deposit attached to a mint paid from the storage pool.
+    /// What is left after paying for the storage of the token is
+    /// refunded to the sponsor by the collection.
+    /// Scheduled with the timelock if a delay is set.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_mint_storage_cost(&mut self, data: MintStorageCostData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetMintStorageCost",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetMintStorageCost {
+                amount: data.amount,
+            },
+        );
+    }
+
+    /// Moves the fees accrued by the caller through the fee split
+    /// to the caller's storage pool balance.
+    /// FAILS: If the caller has no accrued fees.
+    pub fn fund_storage_pool_from_fees(&mut self) -> StorageBalance {
+        let account_id = env::predecessor_account_id();
+        let amt = self.accrued_fees.remove(&account_id).unwrap_or(0);
+        require!(amt > 0, "Nothing to claim");
+        self.total_accrued_fees -= amt;
+
+        self.internal_storage_pool_deposit(&account_id, amt);
+        self.internal_storage_balance(&account_id)
+    }
+
+    /// Gets the sponsor paying for the mints on `collection`.
+    pub fn get_storage_sponsor(&self, collection: AccountId) -> Option<AccountId> {
+        self.storage_sponsors.get(&collection)
+    }
+
+    /// Gets the deposit attached to a mint paid from the storage pool.
+    pub fn get_mint_storage_cost(&self) -> U128 {
+        U128(self.mint_storage_cost)
+    }
+
+    /// Gets the sum of the storage pool balances of all the sponsors.
+    pub fn get_storage_pool_total(&self) -> U128 {
+        U128(self.total_storage_pool)
+    }
+}
+
+/// Storage pool of the sponsors paying for the mints of incoming transfers.
+/// Any account can fund its balance, the balance is only spent on the
+/// mints of the collections it sponsors.
+#[near_bindgen]
+impl StorageManagement for XpBridge {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut amount = env::attached_deposit();
+        // There is no minimum balance, so a registration only deposit is refunded.
+        if registration_only.unwrap_or(false) {
+            if amount > 0 {
+                Promise::new(env::predecessor_account_id()).transfer(amount);
+            }
+            amount = 0;
+        }
+        self.internal_storage_pool_deposit(&account_id, amount);
+        self.internal_storage_balance(&account_id)
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = self
+            .storage_pool
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("Account not registered"));
+        let amount = amount.map(|a| a.0).unwrap_or(balance);
+        require!(amount <= balance, "Not enough balance");
+
+        self.storage_pool.insert(&account_id, &(balance - amount));
+        self.total_storage_pool -= amount;
+        if amount > 0 {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        self.internal_storage_balance(&account_id)
+    }
+
+    /// Withdraws the whole balance of the caller. `force` has no effect
+    /// since the pool doesn't keep anything else for the account.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        let _ = force;
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        match self.storage_pool.remove(&account_id) {
+            Some(balance) => {
+                self.total_storage_pool -= balance;
+                if balance > 0 {
+                    Promise::new(account_id).transfer(balance);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(0),
+            max: None,
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.storage_pool
+            .get(&account_id)
+            .map(|_| self.internal_storage_balance(&account_id))
+    }
+}
+
+impl XpBridge {
+    /// Gets the deposit to attach to a mint on `collection`.
+    /// Uses the deposit attached by the validator if any, otherwise
+    /// charges the storage pool of the sponsor of the collection.
+    /// Returns the deposit and the sponsor that was charged.
+    pub(crate) fn internal_mint_deposit(
+        &mut self,
+        collection: &AccountId,
+        attached: u128,
+    ) -> (u128, Option<AccountId>) {
+        if attached > 0 {
+            return (attached, None);
+        }
+
+        let sponsor = self
+            .storage_sponsors
+            .get(collection)
+            .unwrap_or_else(|| env::panic_str("No storage sponsor for collection"));
+        let balance = self.storage_pool.get(&sponsor).unwrap_or(0);
+        require!(
+            balance >= self.mint_storage_cost,
+            "Not enough storage pool balance"
+        );
+        self.storage_pool
+            .insert(&sponsor, &(balance - self.mint_storage_cost));
+        self.total_storage_pool -= self.mint_storage_cost;
+        (self.mint_storage_cost, Some(sponsor))
+    }
+
+    /// Returns the deposit of a failed mint to the storage pool of the
+    /// sponsor, or to `sender` if it attached the deposit itself.
+    pub(crate) fn internal_refund_mint_deposit(
+        &mut self,
+        sponsor: Option<AccountId>,
+        sender: AccountId,
+        amt: u128,
+    ) {
+        match sponsor {
+            Some(sponsor) => self.internal_storage_pool_deposit(&sponsor, amt),
+            None if amt > 0 => {
+                self.internal_refund(sender, amt);
+            }
+            None => {}
+        }
+    }
+
+    fn internal_storage_pool_deposit(&mut self, account_id: &AccountId, amt: u128) {
+        let balance = self.storage_pool.get(account_id).unwrap_or(0);
+        self.storage_pool.insert(account_id, &(balance + amt));
+        self.total_storage_pool += amt;
+    }
+
+    fn internal_storage_balance(&self, account_id: &AccountId) -> StorageBalance {
+        let balance = U128(self.storage_pool.get(account_id).unwrap_or(0));
+        StorageBalance {
+            total: balance,
+            available: balance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::ONE_NEAR;
+
+    /// Funds the pool of the sponsor of "wrapped.near" with 1 NEAR.
+    fn setup_sponsor() -> XpBridge {
+        let mut bridge = setup();
+        let data = StorageSponsorData {
+            action_id: U128(1),
+            collection: account("wrapped"),
+            sponsor: Some(account("sponsor")),
+        };
+        let sig = group_sig(b"SetStorageSponsor", &data);
+        bridge.validate_set_storage_sponsor(data, sig);
+
+        set_context(&account("sponsor"), ONE_NEAR);
+        bridge.storage_deposit(None, None);
+        bridge
+    }
+
+    fn pool_balance(bridge: &XpBridge, account_id: &AccountId) -> u128 {
+        bridge
+            .storage_balance_of(account_id.clone())
+            .map(|b| b.total.0)
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn unfunded_mints_are_paid_by_the_sponsor() {
+        let mut bridge = setup_sponsor();
+        let data = transfer_data(1, "1");
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        set_context(&account("relayer"), 0);
+        bridge.validate_transfer_nft(data, sig);
+
+        let cost = bridge.get_mint_storage_cost().0;
+        assert_eq!(pool_balance(&bridge, &account("sponsor")), ONE_NEAR - cost);
+        assert_eq!(bridge.get_storage_pool_total().0, ONE_NEAR - cost);
+
+        // A failed mint puts the deposit back into the pool.
+        set_context(&bridge_id(), 0);
+        bridge.validate_transfer_callback(
+            transfer_data(1, "1").validated_event(),
+            Some(account("sponsor")),
+            account("relayer"),
+            cost,
+            Err(PromiseError::Failed),
+        );
+        assert_eq!(pool_balance(&bridge, &account("sponsor")), ONE_NEAR);
+        assert_eq!(transferred_to(&account("relayer")), 0);
+    }
+
+    #[test]
+    fn failed_mints_return_the_attached_deposit() {
+        let mut bridge = setup_sponsor();
+
+        set_context(&bridge_id(), 0);
+        bridge.validate_transfer_callback(
+            transfer_data(1, "1").validated_event(),
+            None,
+            account("relayer"),
+            10,
+            Err(PromiseError::Failed),
+        );
+        assert_eq!(transferred_to(&account("relayer")), 10);
+        assert_eq!(pool_balance(&bridge, &account("sponsor")), ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough storage pool balance")]
+    fn unfunded_mints_need_a_funded_pool() {
+        let mut bridge = setup_sponsor();
+        let data = MintStorageCostData {
+            action_id: U128(2),
+            amount: U128(2 * ONE_NEAR),
+        };
+        let sig = group_sig(b"SetMintStorageCost", &data);
+        bridge.validate_set_mint_storage_cost(data, sig);
+        let data = transfer_data(1, "1");
+        let sig = group_sig(b"ValidateTransferNft", &data);
+
+        set_context(&account("relayer"), 0);
+        bridge.validate_transfer_nft(data, sig);
+    }
+
+    #[test]
+    fn sponsors_can_withdraw_their_balance() {
+        let mut bridge = setup_sponsor();
+
+        set_context(&account("sponsor"), 1);
+        bridge.storage_withdraw(Some(U128(ONE_NEAR / 4)));
+
+        assert_eq!(transferred_to(&account("sponsor")), ONE_NEAR / 4);
+        assert_eq!(pool_balance(&bridge, &account("sponsor")), ONE_NEAR * 3 / 4);
+        assert_eq!(bridge.get_storage_pool_total().0, ONE_NEAR * 3 / 4);
+    }
+}
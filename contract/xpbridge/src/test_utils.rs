@@ -0,0 +1,177 @@
+use crate::*;
+use ed25519_dalek::{Keypair, PublicKey as DalekPublicKey, SecretKey, Signer};
+use near_sdk::mock::VmAction;
+use near_sdk::test_utils::{get_created_receipts, VMContextBuilder};
+use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig};
+
+pub const MAX_GAS: Gas = Gas(300_000_000_000_000);
+
+pub fn bridge_id() -> AccountId {
+    "bridge.near".parse().unwrap()
+}
+
+pub fn account(name: &str) -> AccountId {
+    format!("{}.near", name).parse().unwrap()
+}
+
+fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = DalekPublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+pub fn group_keypair() -> Keypair {
+    keypair(1)
+}
+
+pub fn fee_keypair() -> Keypair {
+    keypair(2)
+}
+
+/// The group key the validators rotate to.
+pub fn next_group_keypair() -> Keypair {
+    keypair(3)
+}
+
+/// Signs the sha512 hash of `context` and the borsh encoding of `data`
+/// like the validators do.
+pub fn sign<T: BorshSerialize>(keypair: &Keypair, context: &[u8], data: &T) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update(context);
+    hasher.update(data.try_to_vec().unwrap());
+    keypair.sign(&hasher.finalize()).to_bytes().to_vec()
+}
+
+pub fn group_sig<T: BorshSerialize>(context: &[u8], data: &T) -> Vec<u8> {
+    sign(&group_keypair(), context, data)
+}
+
+pub fn fee_sig<T: BorshSerialize>(context: &[u8], data: &T) -> Vec<u8> {
+    sign(&fee_keypair(), context, data)
+}
+
+/// Sets up a call to the bridge from `predecessor`, who also signs it.
+pub fn set_context(predecessor: &AccountId, deposit: u128) {
+    testing_env!(context(predecessor, deposit).build());
+}
+
+/// Sets up a callback of the bridge which sees `results` as the
+/// results of the promises it was chained to.
+pub fn set_promise_results(results: Vec<PromiseResult>) {
+    testing_env!(
+        context(&bridge_id(), 0).build(),
+        VMConfig::test(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        results,
+    );
+}
+
+pub fn context(predecessor: &AccountId, deposit: u128) -> VMContextBuilder {
+    let mut builder = VMContextBuilder::new();
+    builder
+        .current_account_id(bridge_id())
+        .predecessor_account_id(predecessor.clone())
+        .signer_account_id(predecessor.clone())
+        .attached_deposit(deposit)
+        .prepaid_gas(MAX_GAS);
+    builder
+}
+
+/// Initializes a bridge with the test group and fee keys.
+/// The timelock delay is removed, so the scheduled actions run
+/// immediately unless a test sets a delay.
+pub fn setup() -> XpBridge {
+    set_context(&bridge_id(), 0);
+    let mut bridge = XpBridge::initialize(
+        group_keypair().public.to_bytes(),
+        fee_keypair().public.to_bytes(),
+    );
+    bridge.timelock_delay = 0;
+    bridge
+}
+
+/// A freeze of the NFT "1" of the "nft.near" collection by `sender`
+/// to chain 4, with a fee of 10.
+pub fn freeze_request(sender: &str) -> FreezeRequest {
+    FreezeRequest {
+        token_contract: account("nft"),
+        token_id: "1".to_string(),
+        chain_nonce: 4,
+        to: "0xreceiver".to_string(),
+        mint_with: "0xwrapped".to_string(),
+        amt: U128(10),
+        sender: account(sender),
+    }
+}
+
+/// A freeze of `tokens` by alice to chain 4, with a fee of 10.
+pub fn freeze_batch_request(tokens: Vec<FreezeBatchItem>) -> FreezeBatchRequest {
+    FreezeBatchRequest {
+        tokens,
+        chain_nonce: 4,
+        to: "0xreceiver".to_string(),
+        amt: U128(10),
+        sender: account("alice"),
+    }
+}
+
+/// Sums the NEAR transferred to `account_id` by the receipts of the last call.
+pub fn transferred_to(account_id: &AccountId) -> u128 {
+    get_created_receipts()
+        .iter()
+        .filter(|r| &r.receiver_id == account_id)
+        .flat_map(|r| r.actions.iter())
+        .map(|a| match a {
+            VmAction::Transfer { deposit } => *deposit,
+            _ => 0,
+        })
+        .sum()
+}
+
+pub fn token_metadata() -> TokenMetadata {
+    TokenMetadata {
+        title: Some("Wrapped".to_string()),
+        description: None,
+        media: None,
+        media_hash: None,
+        copies: None,
+        issued_at: None,
+        expires_at: None,
+        starts_at: None,
+        updated_at: None,
+        extra: None,
+        reference: None,
+        reference_hash: None,
+    }
+}
+
+/// An incoming transfer from chain 4 which mints `token_id` on the
+/// "wrapped.near" collection for bob.
+pub fn transfer_data(action_id: u128, token_id: &str) -> TransferNftData {
+    TransferNftData {
+        action_id: U128(action_id),
+        chain_nonce: 4,
+        tx_hash: format!("0xtx{}", action_id),
+        origin_contract: "0xorigin".to_string(),
+        origin_token_id: token_id.to_string(),
+        mint_with: account("wrapped"),
+        token_id: token_id.to_string(),
+        owner_id: account("bob"),
+        token_metadata: token_metadata(),
+        royalty: None,
+    }
+}
+
+/// An incoming unfreeze from chain 4 which returns `token_id` of the
+/// "nft.near" collection to bob.
+pub fn unfreeze_data(action_id: u128, token_id: &str) -> UnfreezeNftData {
+    UnfreezeNftData {
+        action_id: U128(action_id),
+        chain_nonce: 4,
+        tx_hash: format!("0xtx{}", action_id),
+        token_contract: account("nft"),
+        token_id: token_id.to_string(),
+        receiver_id: account("bob"),
+    }
+}
@@ -0,0 +1,447 @@
+//! Timelock of the sensitive admin actions.
+//!
+//! These actions are scheduled for `timelock_delay` nanoseconds before
+//! anyone can execute them with `execute_timelock`, so that the guardians
+//! can cancel them if the group key is compromised:
+//! - the group key and the fee key, which hand over the bridge;
+//! - the timelock delay and the guardians, which guard the timelock itself;
+//! - blacklisting a collection, which locks its frozen NFTs in the bridge;
+//! - withdrawing the NEAR and NEP-141 fees and the fee split, which move
+//!   the fees out of the bridge;
+//! - the rate limits and the queue delay, which bound what a leaked key
+//!   can mint or unfreeze;
+//! - the key grace period, which keeps a replaced group key valid;
+//! - the replay expiry, which decides when consumed action ids are pruned;
+//! - the permissionless mode and its registration fee, which open the
+//!   bridge to any collection;
+//! - the mint storage cost, which is paid from the storage pool.
+//!
+//! The other admin actions take effect immediately. They either contain an
+//! incident (pausing, blocking or cancelling), act on a single transfer or
+//! collection, or can be undone by the next signed action.
+
+use crate::*;
+
+/// Default delay of the timelock (2 days).
+pub const DEFAULT_TIMELOCK_DELAY: u64 = 2 * 24 * 60 * 60 * 1_000_000_000;
+
+/// A sensitive admin action scheduled by the timelock.
+#[derive(Clone, PartialEq, Debug, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TimelockAction {
+    UpdateGroupKey {
+        group_key: [u8; 32],
+    },
+    UpdateFeePublicKey {
+        fee_pk: [u8; 32],
+    },
+    Blacklist {
+        token_contract: AccountId,
+    },
+    WithdrawFees {
+        account_id: AccountId,
+    },
+    SetDelay {
+        delay: U64,
+    },
+    SetGuardian {
+        account_id: AccountId,
+        enabled: bool,
+    },
+    SetCollectionRateLimit {
+        collection: AccountId,
+        limit: Option<u32>,
+    },
+    SetChainRateLimit {
+        chain_nonce: u8,
+        limit: Option<u32>,
+    },
+    SetQueueDelay {
+        delay: U64,
+    },
+    SetKeyGracePeriod {
+        grace_period: U64,
+    },
+    SetReplayExpiry {
+        expiry: U64,
+    },
+    SetFeeSplit {
+        beneficiaries: Vec<FeeBeneficiary>,
+    },
+    SetPermissionlessMode {
+        enabled: bool,
+        registration_fee: U128,
+    },
+    SetMintStorageCost {
+        amount: U128,
+    },
+    WithdrawFtFees {
+        token: AccountId,
+        account_id: AccountId,
+    },
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockEntry {
+    pub action_id: U128,
+    pub action: TimelockAction,
+    pub eta: U64,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockDelayData {
+    pub action_id: U128,
+    pub delay: U64,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GuardianData {
+    pub action_id: U128,
+    pub account_id: AccountId,
+    pub enabled: bool,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CancelTimelockData {
+    pub action_id: U128,
+    pub timelock_id: U64,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Schedules a change of the delay of the timelock in nanoseconds.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_timelock_delay(&mut self, data: TimelockDelayData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetTimelockDelay",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetDelay { delay: data.delay },
+        );
+    }
+
+    /// Schedules adding or removing a guardian, which can cancel
+    /// the scheduled actions with `cancel_timelock`.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_guardian(&mut self, data: GuardianData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetGuardian",
+        );
+
+        self.internal_schedule(
+            data.action_id.0,
+            TimelockAction::SetGuardian {
+                account_id: data.account_id,
+                enabled: data.enabled,
+            },
+        );
+    }
+
+    /// Cancels a scheduled action.
+    /// FAILS: If the action doesn't exist.
+    /// REQUIRED: Signature verification.
+    pub fn validate_cancel_timelock(&mut self, data: CancelTimelockData, sig_data: Vec<u8>) {
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"CancelTimelock",
+        );
+
+        self.internal_cancel_timelock(data.timelock_id.0);
+    }
+
+    /// Cancels a scheduled action. Must be called by a guardian.
+    /// FAILS: If the caller is not a guardian or the action doesn't exist.
+    pub fn cancel_timelock(&mut self, timelock_id: U64) {
+        require!(
+            self.guardians.contains(&env::predecessor_account_id()),
+            "Unauthorized"
+        );
+
+        self.internal_cancel_timelock(timelock_id.0);
+    }
+
+    /// Executes a scheduled action once its delay passed.
+    /// Can be called by anyone.
+    /// FAILS: If contract is paused, the action doesn't exist or its
+    /// delay didn't pass.
+    pub fn execute_timelock(&mut self, timelock_id: U64) -> PromiseOrValue<()> {
+        require!(!self.paused, "paused");
+
+        let entry = self
+            .timelocks
+            .remove(&timelock_id.0)
+            .unwrap_or_else(|| env::panic_str("Unknown timelock"));
+        require!(entry.eta.0 <= env::block_timestamp(), "Timelock not ready");
+
+        TimelockExecutedEvent {
+            timelock_id: timelock_id.0,
+        }
+        .emit();
+
+        self.internal_execute(entry.action_id.0, entry.action)
+    }
+
+    /// Gets the scheduled action with the provided id.
+    pub fn get_timelock(&self, timelock_id: U64) -> Option<TimelockEntry> {
+        self.timelocks.get(&timelock_id.0)
+    }
+
+    /// Gets the delay of the timelock in nanoseconds.
+    pub fn get_timelock_delay(&self) -> U64 {
+        U64(self.timelock_delay)
+    }
+
+    /// Checks if the provided account is a guardian.
+    pub fn is_guardian(&self, account_id: AccountId) -> bool {
+        self.guardians.contains(&account_id)
+    }
+}
+
+impl XpBridge {
+    /// Schedules a sensitive action for execution after the timelock delay.
+    /// Without a delay, the action is executed immediately.
+    pub(crate) fn internal_schedule(
+        &mut self,
+        action_id: u128,
+        action: TimelockAction,
+    ) -> PromiseOrValue<()> {
+        if self.timelock_delay == 0 {
+            return self.internal_execute(action_id, action);
+        }
+
+        self.timelock_cnt += 1;
+        let eta = env::block_timestamp() + self.timelock_delay;
+        self.timelocks.insert(
+            &self.timelock_cnt,
+            &TimelockEntry {
+                action_id: U128(action_id),
+                action: action.clone(),
+                eta: U64(eta),
+            },
+        );
+
+        TimelockScheduledEvent {
+            timelock_id: self.timelock_cnt,
+            action_id,
+            action,
+            eta,
+        }
+        .emit();
+        PromiseOrValue::Value(())
+    }
+
+    fn internal_execute(&mut self, action_id: u128, action: TimelockAction) -> PromiseOrValue<()> {
+        match action {
+            TimelockAction::UpdateGroupKey { group_key } => {
+                self.internal_rotate_group_key(group_key)
+            }
+            TimelockAction::UpdateFeePublicKey { fee_pk } => self.fee_pk = fee_pk,
+            TimelockAction::Blacklist { token_contract } => {
+                self.internal_blacklist(&token_contract);
+            }
+            TimelockAction::WithdrawFees { account_id } => {
+                require!(
+                    env::prepaid_gas() >= GAS_FOR_VALIDATE_WITHDRAW,
+                    "Not enough gas"
+                );
+                return self.internal_withdraw_fees(action_id, account_id);
+            }
+            TimelockAction::SetDelay { delay } => self.timelock_delay = delay.0,
+            TimelockAction::SetGuardian {
+                account_id,
+                enabled,
+            } => {
+                if enabled {
+                    self.guardians.insert(&account_id);
+                } else {
+                    self.guardians.remove(&account_id);
+                }
+            }
+            TimelockAction::SetCollectionRateLimit { collection, limit } => {
+                self.rate_limits.set_collection_limit(&collection, limit)
+            }
+            TimelockAction::SetChainRateLimit { chain_nonce, limit } => {
+                self.rate_limits.set_chain_limit(chain_nonce, limit)
+            }
+            TimelockAction::SetQueueDelay { delay } => self.rate_limits.set_queue_delay(delay.0),
+            TimelockAction::SetKeyGracePeriod { grace_period } => {
+                self.key_grace_period = grace_period.0
+            }
+            TimelockAction::SetReplayExpiry { expiry } => {
+                self.consumed_actions.set_expiry(expiry.0)
+            }
+            TimelockAction::SetFeeSplit { beneficiaries } => self.fee_split = beneficiaries,
+            TimelockAction::SetPermissionlessMode {
+                enabled,
+                registration_fee,
+            } => {
+                self.permissionless_mode = enabled;
+                self.registration_fee = registration_fee.0;
+            }
+            TimelockAction::SetMintStorageCost { amount } => self.mint_storage_cost = amount.0,
+            TimelockAction::WithdrawFtFees { token, account_id } => {
+                require!(
+                    env::prepaid_gas() >= GAS_FOR_VALIDATE_WITHDRAW,
+                    "Not enough gas"
+                );
+                return self.internal_withdraw_ft_fees(action_id, token, account_id);
+            }
+        }
+        PromiseOrValue::Value(())
+    }
+
+    fn internal_cancel_timelock(&mut self, timelock_id: u64) {
+        require!(
+            self.timelocks.remove(&timelock_id).is_some(),
+            "Unknown timelock"
+        );
+
+        TimelockCancelledEvent { timelock_id }.emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    /// Sets a timelock delay of 100 and schedules making carol a guardian.
+    fn setup_scheduled() -> XpBridge {
+        let mut bridge = setup();
+        let data = TimelockDelayData {
+            action_id: U128(1),
+            delay: U64(100),
+        };
+        let sig = group_sig(b"SetTimelockDelay", &data);
+        bridge.validate_set_timelock_delay(data, sig);
+        assert_eq!(bridge.get_timelock_delay().0, 100);
+
+        let data = GuardianData {
+            action_id: U128(2),
+            account_id: account("carol"),
+            enabled: true,
+        };
+        let sig = group_sig(b"SetGuardian", &data);
+        bridge.validate_set_guardian(data, sig);
+        bridge
+    }
+
+    fn at(predecessor: &str, timestamp: u64) {
+        testing_env!(context(&account(predecessor), 0)
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    #[test]
+    fn new_bridges_schedule_sensitive_actions() {
+        set_context(&bridge_id(), 0);
+        let mut bridge = XpBridge::initialize(
+            group_keypair().public.to_bytes(),
+            fee_keypair().public.to_bytes(),
+        );
+        assert_eq!(bridge.get_timelock_delay().0, DEFAULT_TIMELOCK_DELAY);
+
+        let data = MintStorageCostData {
+            action_id: U128(1),
+            amount: U128(1),
+        };
+        let sig = group_sig(b"SetMintStorageCost", &data);
+        bridge.validate_set_mint_storage_cost(data, sig);
+        assert_eq!(bridge.mint_storage_cost, DEFAULT_MINT_STORAGE_COST);
+
+        at("anyone", DEFAULT_TIMELOCK_DELAY);
+        bridge.execute_timelock(U64(1));
+        assert_eq!(bridge.mint_storage_cost, 1);
+    }
+
+    #[test]
+    fn actions_run_immediately_without_a_delay() {
+        let mut bridge = setup();
+        let data = GuardianData {
+            action_id: U128(1),
+            account_id: account("carol"),
+            enabled: true,
+        };
+        let sig = group_sig(b"SetGuardian", &data);
+        bridge.validate_set_guardian(data, sig);
+
+        assert!(bridge.is_guardian(account("carol")));
+        assert!(bridge.get_timelock(U64(1)).is_none());
+    }
+
+    #[test]
+    fn scheduled_actions_run_after_the_delay() {
+        let mut bridge = setup_scheduled();
+        assert!(!bridge.is_guardian(account("carol")));
+        assert_eq!(bridge.get_timelock(U64(1)).unwrap().eta.0, 100);
+
+        at("anyone", 100);
+        bridge.execute_timelock(U64(1));
+
+        assert!(bridge.is_guardian(account("carol")));
+        assert!(bridge.get_timelock(U64(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Timelock not ready")]
+    fn scheduled_actions_wait_for_the_delay() {
+        let mut bridge = setup_scheduled();
+
+        at("anyone", 99);
+        bridge.execute_timelock(U64(1));
+    }
+
+    #[test]
+    fn guardians_can_cancel_scheduled_actions() {
+        let mut bridge = setup_scheduled();
+        bridge.guardians.insert(&account("guardian"));
+
+        at("guardian", 0);
+        bridge.cancel_timelock(U64(1));
+
+        assert!(bridge.get_timelock(U64(1)).is_none());
+    }
+
+    #[test]
+    fn scheduled_actions_can_be_cancelled_with_a_signature() {
+        let mut bridge = setup_scheduled();
+        let data = CancelTimelockData {
+            action_id: U128(3),
+            timelock_id: U64(1),
+        };
+        let sig = group_sig(b"CancelTimelock", &data);
+        bridge.validate_cancel_timelock(data, sig);
+
+        assert!(bridge.get_timelock(U64(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_guardians_can_cancel_without_a_signature() {
+        let mut bridge = setup_scheduled();
+
+        at("mallory", 0);
+        bridge.cancel_timelock(U64(1));
+    }
+}
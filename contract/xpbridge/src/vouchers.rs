@@ -0,0 +1,383 @@
+use crate::*;
+
+pub(crate) const GAS_FOR_CLAIM_VOUCHER: Gas = Gas(35_000_000_000_000);
+
+/// A validated incoming transfer which is minted when the
+/// recipient claims it, instead of by the validators.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferVoucherData {
+    pub transfer: TransferNftData,
+    pub expires_at: U64,
+}
+
+/// A stored voucher with the deposit paying for its storage, which is
+/// returned to the account that submitted it when the voucher is claimed
+/// or expires.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VoucherRecord {
+    pub voucher: TransferVoucherData,
+    pub depositor: AccountId,
+    pub deposit: U128,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Validates the transfer of NFT from the bridge to the destination chain
+    /// like `validate_transfer_nft`, but stores it as a voucher instead of
+    /// minting it. The recipient mints the NFT with `claim`, paying for the
+    /// storage, until `expires_at` (in nanoseconds). The attached deposit
+    /// pays for the storage of the voucher, the rest of it is returned to
+    /// the caller. The deposit is returned when the voucher is claimed or
+    /// expires.
+    /// FAILS: If contract is paused, the voucher is already expired,
+    /// `mint_with` doesn't match the collection registered for its origin,
+    /// a metadata hash is not a sha256 hash or the attached deposit doesn't
+    /// cover the storage.
+    /// REQUIRED: Signature verification.
+    #[payable]
+    pub fn validate_transfer_voucher(&mut self, data: TransferVoucherData, sig_data: Vec<u8>) {
+        require!(!self.paused, "paused");
+        require!(
+            data.expires_at.0 > env::block_timestamp(),
+            "Voucher expired"
+        );
+
+        self.require_chain_sig(
+            data.transfer.chain_nonce,
+            data.transfer.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"ValidateTransferVoucher",
+        );
+        self.require_collection_origin(&data.transfer);
+        require_valid_token_metadata(&data.transfer.token_metadata);
+
+        let key = (data.transfer.chain_nonce, data.transfer.action_id.0);
+        let mut record = VoucherRecord {
+            voucher: data,
+            depositor: env::predecessor_account_id(),
+            deposit: U128(0),
+        };
+        let initial_storage = env::storage_usage();
+        self.vouchers.insert(&key, &record);
+        let cost =
+            env::storage_byte_cost() * env::storage_usage().saturating_sub(initial_storage) as u128;
+        let attached = env::attached_deposit();
+        require!(attached >= cost, "Not enough deposit for storage");
+        record.deposit = U128(cost);
+        self.vouchers.insert(&key, &record);
+        if attached > cost {
+            Promise::new(env::predecessor_account_id()).transfer(attached - cost);
+        }
+
+        let data = record.voucher;
+        let transfer = &data.transfer;
+
+        VoucherIssuedEvent {
+            chain_nonce: transfer.chain_nonce,
+            tx_hash: transfer.tx_hash.clone(),
+            action_id: transfer.action_id.0,
+            contract: transfer.mint_with.clone(),
+            token_id: transfer.token_id.clone(),
+            owner_id: transfer.owner_id.clone(),
+            expires_at: data.expires_at.0,
+        }
+        .emit();
+    }
+
+    /// Mints the NFT of a voucher to its recipient. Must be called by the
+    /// recipient, who pays for the storage of the token with the attached
+    /// deposit and gets back what is left of it. The storage deposit of the
+    /// voucher is returned to its submitter once the NFT is minted.
+    /// Above the rate limits of the chain or the collection, the claim
+    /// is queued and can be executed with `execute_queued_action` later.
+    /// FAILS: If contract is paused or the voucher doesn't exist or is expired.
+    #[payable]
+    pub fn claim(&mut self, chain_nonce: u8, action_id: U128) -> PromiseOrValue<()> {
+        require!(
+            env::prepaid_gas() >= GAS_FOR_CLAIM_VOUCHER,
+            "Not enough gas"
+        );
+        require!(!self.paused, "paused");
+        require!(env::attached_deposit() > 0, "Storage deposit required");
+
+        let record = self
+            .vouchers
+            .remove(&(chain_nonce, action_id.0))
+            .unwrap_or_else(|| env::panic_str("Unknown voucher"));
+        require!(
+            record.voucher.expires_at.0 > env::block_timestamp(),
+            "Voucher expired"
+        );
+        let transfer = &record.voucher.transfer;
+        require!(
+            transfer.owner_id == env::predecessor_account_id(),
+            "Unauthorized"
+        );
+
+        if !self
+            .rate_limits
+            .try_acquire(transfer.chain_nonce, &transfer.mint_with)
+        {
+            self.internal_queue_action(
+                transfer.chain_nonce,
+                transfer.action_id.0,
+                transfer.mint_with.clone(),
+                QueuedBridgeAction::ClaimVoucher {
+                    record: Box::new(record),
+                    deposit: U128(env::attached_deposit()),
+                },
+            );
+            return PromiseOrValue::Value(());
+        }
+
+        self.internal_claim_voucher(record, env::attached_deposit())
+            .into()
+    }
+
+    /// This is the callback function when the promise in the claim
+    /// function is completed. Returns the storage deposit of the voucher to
+    /// its submitter, or restores the voucher and returns the deposit to the
+    /// recipient if the mint failed.
+    #[private]
+    pub fn claim_voucher_callback(
+        &mut self,
+        voucher: TransferVoucherData,
+        depositor: AccountId,
+        deposit: u128,
+        amt: u128,
+        #[callback_result] call_result: Result<Token, PromiseError>,
+    ) {
+        let transfer = voucher.transfer.clone();
+        match call_result {
+            Ok(token) => {
+                if deposit > 0 {
+                    self.internal_refund(depositor, deposit);
+                }
+                TransferValidatedEvent {
+                    chain_nonce: transfer.chain_nonce,
+                    tx_hash: transfer.tx_hash,
+                    action_id: transfer.action_id.0,
+                    contract: transfer.mint_with,
+                    token_id: token.token_id,
+                    owner_id: token.owner_id,
+                    quantity: None,
+                }
+                .emit()
+            }
+            Err(e) => {
+                self.vouchers.insert(
+                    &(transfer.chain_nonce, transfer.action_id.0),
+                    &VoucherRecord {
+                        voucher,
+                        depositor,
+                        deposit: U128(deposit),
+                    },
+                );
+                self.internal_refund(transfer.owner_id.clone(), amt);
+                env::log_str(&format!(
+                    "claim voucher callback: failed to mint nft: actionid: {} : {:?}",
+                    transfer.action_id.0, e
+                ))
+            }
+        }
+    }
+
+    /// Removes a voucher which was not claimed before it expired, returns
+    /// its storage deposit to its submitter and emits a VoucherExpiredEvent
+    /// so the NFT is returned on the source chain. Can be called by anyone.
+    /// FAILS: If the voucher doesn't exist or is not expired yet.
+    pub fn expire_voucher(&mut self, chain_nonce: u8, action_id: U128) {
+        let record = self
+            .vouchers
+            .get(&(chain_nonce, action_id.0))
+            .unwrap_or_else(|| env::panic_str("Unknown voucher"));
+        require!(
+            record.voucher.expires_at.0 <= env::block_timestamp(),
+            "Voucher not expired"
+        );
+        self.vouchers.remove(&(chain_nonce, action_id.0));
+        if record.deposit.0 > 0 {
+            self.internal_refund(record.depositor, record.deposit.0);
+        }
+
+        let transfer = record.voucher.transfer;
+        VoucherExpiredEvent {
+            chain_nonce: transfer.chain_nonce,
+            tx_hash: transfer.tx_hash,
+            action_id: transfer.action_id.0,
+            contract: transfer.mint_with,
+            token_id: transfer.token_id,
+            owner_id: transfer.owner_id,
+        }
+        .emit();
+    }
+
+    /// Gets the voucher of the transfer with the provided chain nonce and action id.
+    pub fn get_voucher(&self, chain_nonce: u8, action_id: U128) -> Option<TransferVoucherData> {
+        self.vouchers
+            .get(&(chain_nonce, action_id.0))
+            .map(|record| record.voucher)
+    }
+}
+
+impl XpBridge {
+    /// Mints the NFT of a claimed voucher to its recipient and then calls
+    /// 'claim_voucher_callback'.
+    pub(crate) fn internal_claim_voucher(
+        &mut self,
+        record: VoucherRecord,
+        deposit: u128,
+    ) -> Promise {
+        let transfer = record.voucher.transfer.clone();
+        let origin = transfer.origin();
+        xpnft::ext(transfer.mint_with)
+            .with_attached_deposit(deposit)
+            .with_static_gas(Gas(TGAS * 10))
+            .nft_mint(
+                transfer.token_id,
+                transfer.owner_id.clone(),
+                transfer.token_metadata,
+                transfer.royalty,
+                Some(origin),
+                Some(transfer.owner_id),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas(TGAS * 15))
+                    .claim_voucher_callback(
+                        record.voucher,
+                        record.depositor,
+                        record.deposit.0,
+                        deposit,
+                    ),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    fn voucher() -> TransferVoucherData {
+        TransferVoucherData {
+            transfer: transfer_data(1, "1"),
+            expires_at: U64(100),
+        }
+    }
+
+    /// Stores the voucher of the transfer 1, submitted by the relayer.
+    fn setup_voucher() -> XpBridge {
+        let mut bridge = setup();
+        let sig = group_sig(b"ValidateTransferVoucher", &voucher());
+
+        set_context(&account("relayer"), ONE_NEAR);
+        bridge.validate_transfer_voucher(voucher(), sig);
+        bridge
+    }
+
+    fn at(predecessor: &str, timestamp: u64, deposit: u128) {
+        testing_env!(context(&account(predecessor), deposit)
+            .block_timestamp(timestamp)
+            .build());
+    }
+
+    #[test]
+    fn voucher_storage_is_paid_by_the_submitter() {
+        let bridge = setup_voucher();
+
+        assert!(bridge.get_voucher(4, U128(1)) == Some(voucher()));
+        let deposit = bridge.vouchers.get(&(4, 1)).unwrap().deposit.0;
+        assert!(deposit > 0);
+        assert_eq!(transferred_to(&account("relayer")), ONE_NEAR - deposit);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough deposit for storage")]
+    fn voucher_storage_must_be_covered() {
+        let mut bridge = setup();
+        let sig = group_sig(b"ValidateTransferVoucher", &voucher());
+
+        set_context(&account("relayer"), 0);
+        bridge.validate_transfer_voucher(voucher(), sig);
+    }
+
+    #[test]
+    fn claim_returns_the_voucher_deposit() {
+        let mut bridge = setup_voucher();
+        let deposit = bridge.vouchers.get(&(4, 1)).unwrap().deposit.0;
+
+        at("bob", 50, 10);
+        bridge.claim(4, U128(1));
+        assert!(bridge.get_voucher(4, U128(1)).is_none());
+
+        set_context(&bridge_id(), 0);
+        bridge.claim_voucher_callback(
+            voucher(),
+            account("relayer"),
+            deposit,
+            10,
+            Ok(Token {
+                token_id: "1".to_string(),
+                owner_id: account("bob"),
+                metadata: None,
+                approved_account_ids: None,
+            }),
+        );
+        assert_eq!(transferred_to(&account("relayer")), deposit);
+    }
+
+    #[test]
+    fn failed_claim_restores_the_voucher() {
+        let mut bridge = setup_voucher();
+        let deposit = bridge.vouchers.get(&(4, 1)).unwrap().deposit.0;
+
+        at("bob", 50, 10);
+        bridge.claim(4, U128(1));
+
+        set_context(&bridge_id(), 0);
+        bridge.claim_voucher_callback(
+            voucher(),
+            account("relayer"),
+            deposit,
+            10,
+            Err(PromiseError::Failed),
+        );
+        assert!(bridge.get_voucher(4, U128(1)).is_some());
+        assert_eq!(transferred_to(&account("bob")), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_recipient_can_claim() {
+        let mut bridge = setup_voucher();
+
+        at("mallory", 50, 10);
+        bridge.claim(4, U128(1));
+    }
+
+    #[test]
+    fn expired_vouchers_return_the_deposit() {
+        let mut bridge = setup_voucher();
+        let deposit = bridge.vouchers.get(&(4, 1)).unwrap().deposit.0;
+
+        at("anyone", 100, 0);
+        bridge.expire_voucher(4, U128(1));
+
+        assert!(bridge.get_voucher(4, U128(1)).is_none());
+        assert_eq!(transferred_to(&account("relayer")), deposit);
+    }
+
+    #[test]
+    #[should_panic(expected = "Voucher not expired")]
+    fn vouchers_cannot_expire_early() {
+        let mut bridge = setup_voucher();
+
+        at("anyone", 99, 0);
+        bridge.expire_voucher(4, U128(1));
+    }
+}
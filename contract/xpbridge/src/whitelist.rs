@@ -0,0 +1,227 @@
+//! Whitelist of the collections which can be bridged, with the metadata
+//! of each entry and a paginated listing.
+//!
+//! There is no rule whitelisting a collection by the hash of its deployed
+//! code. A contract can't read the code hash of another account on NEAR,
+//! so the bridge would have to trust a hash given with the call, and the
+//! code of an account can be redeployed after it was checked. Collections
+//! sharing a code are whitelisted one by one, or allowed with
+//! `validate_set_permissionless_collection`. The wrapped collections
+//! deployed by the factory are never frozen, so they need no entry.
+
+use crate::*;
+
+/// Metadata of a whitelisted collection.
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WhitelistEntry {
+    pub added_at: U64,
+    pub action_id: U128,
+    pub notes: Option<String>,
+}
+
+/// A whitelisted collection. Collections whitelisted before
+/// metadata was recorded have no entry.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WhitelistView {
+    pub token_contract: AccountId,
+    pub entry: Option<WhitelistEntry>,
+}
+
+#[derive(Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PermissionlessCollectionData {
+    pub action_id: U128,
+    pub collection: AccountId,
+    pub enabled: bool,
+}
+
+#[near_bindgen]
+impl XpBridge {
+    /// Allows or disallows bridging `collection` without whitelisting it.
+    /// FAILS: If contract is paused.
+    /// REQUIRED: Signature verification.
+    pub fn validate_set_permissionless_collection(
+        &mut self,
+        data: PermissionlessCollectionData,
+        sig_data: Vec<u8>,
+    ) {
+        require!(!self.paused, "paused");
+
+        self.require_sig(
+            data.action_id.into(),
+            data.try_to_vec().unwrap(),
+            sig_data,
+            b"SetPermissionlessCollection",
+        );
+
+        if data.enabled {
+            self.permissionless_collections.insert(&data.collection);
+        } else {
+            self.permissionless_collections.remove(&data.collection);
+        }
+    }
+
+    /// Checks if the provided collection can be bridged without being whitelisted.
+    pub fn is_permissionless_collection(&self, collection: AccountId) -> bool {
+        self.permissionless_collections.contains(&collection)
+    }
+
+    /// Gets the whitelisted collections with their metadata, starting at
+    /// `from_index` (0 by default) and returning at most `limit` entries.
+    pub fn get_whitelist(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<WhitelistView> {
+        let contracts = self.whitelist.as_vector();
+        let start = from_index.map(|i| i.0).unwrap_or(0);
+        let end = start
+            .saturating_add(limit.map(|l| l.0).unwrap_or(u64::MAX))
+            .min(contracts.len());
+        (start..end)
+            .filter_map(|i| contracts.get(i))
+            .map(|token_contract| WhitelistView {
+                entry: self.whitelist_entries.get(&token_contract),
+                token_contract,
+            })
+            .collect()
+    }
+
+    /// Gets the number of whitelisted collections.
+    pub fn get_whitelist_len(&self) -> u64 {
+        self.whitelist.len()
+    }
+}
+
+impl XpBridge {
+    /// Adds a collection to the whitelist and records its metadata.
+    pub(crate) fn internal_whitelist(
+        &mut self,
+        token_contract: &AccountId,
+        action_id: u128,
+        notes: Option<String>,
+    ) {
+        self.whitelist.insert(token_contract);
+        self.whitelist_entries.insert(
+            token_contract,
+            &WhitelistEntry {
+                added_at: U64(env::block_timestamp()),
+                action_id: U128(action_id),
+                notes,
+            },
+        );
+    }
+
+    /// Checks if the collection is whitelisted, permissionless
+    /// or registered in permissionless mode.
+    pub(crate) fn is_bridgeable(&self, token_contract: &AccountId) -> bool {
+        self.whitelist.contains(token_contract)
+            || self.permissionless_collections.contains(token_contract)
+            || self.registered_collections.contains(token_contract)
+    }
+
+    /// Checks that NFTs of the collection can be unfrozen.
+    /// FAILS: If the collection is not bridgeable.
+    pub(crate) fn require_bridgeable(&self, token_contract: &AccountId) {
+        require!(
+            self.is_bridgeable(token_contract),
+            "Collection not whitelisted"
+        );
+    }
+
+    /// Checks that NFTs of the collection can be frozen.
+    /// FAILS: If the collection is blocked or not bridgeable.
+    pub(crate) fn require_freezable(&self, token_contract: &AccountId) {
+        require!(
+            !self.blocked_collections.contains(token_contract),
+            "Collection blocked"
+        );
+        self.require_bridgeable(token_contract);
+    }
+
+    /// Removes a collection and its metadata from the whitelist.
+    pub(crate) fn internal_blacklist(&mut self, token_contract: &AccountId) {
+        self.whitelist.remove(token_contract);
+        self.whitelist_entries.remove(token_contract);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::testing_env;
+
+    fn whitelist(bridge: &mut XpBridge, action_id: u128, name: &str, notes: Option<&str>) {
+        let data = WhitelistData {
+            action_id: U128(action_id),
+            token_contract: account(name),
+            notes: notes.map(str::to_string),
+        };
+        let sig = group_sig(b"WhitelistNft", &data);
+        bridge.validate_whitelist(data, sig);
+    }
+
+    #[test]
+    fn whitelist_records_the_entry_metadata() {
+        let mut bridge = setup();
+
+        testing_env!(context(&account("relayer"), 0).block_timestamp(42).build());
+        whitelist(&mut bridge, 7, "nft", Some("Audited"));
+
+        let views = bridge.get_whitelist(None, None);
+        assert_eq!(views.len(), 1);
+        assert_eq!(views[0].token_contract, account("nft"));
+        let entry = views[0].entry.clone().unwrap();
+        assert_eq!(entry.added_at.0, 42);
+        assert_eq!(entry.action_id.0, 7);
+        assert_eq!(entry.notes, Some("Audited".to_string()));
+    }
+
+    #[test]
+    fn whitelist_is_paginated() {
+        let mut bridge = setup();
+        for (i, name) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            whitelist(&mut bridge, i as u128 + 1, name, None);
+        }
+        assert_eq!(bridge.get_whitelist_len(), 5);
+
+        let page: Vec<AccountId> = bridge
+            .get_whitelist(Some(U64(1)), Some(U64(2)))
+            .into_iter()
+            .map(|v| v.token_contract)
+            .collect();
+        assert_eq!(page, vec![account("b"), account("c")]);
+        assert_eq!(bridge.get_whitelist(Some(U64(4)), Some(U64(10))).len(), 1);
+        assert!(bridge.get_whitelist(Some(U64(9)), None).is_empty());
+    }
+
+    #[test]
+    fn blacklist_removes_the_entry() {
+        let mut bridge = setup();
+        whitelist(&mut bridge, 1, "nft", None);
+        let data = BlacklistData {
+            action_id: U128(2),
+            token_contract: account("nft"),
+        };
+        let sig = group_sig(b"ValidateBlacklistNft", &data);
+        bridge.validate_blacklist(data, sig);
+
+        assert_eq!(bridge.get_whitelist_len(), 0);
+        assert!(bridge.whitelist_entries.get(&account("nft")).is_none());
+    }
+
+    #[test]
+    fn permissionless_collections_are_bridgeable() {
+        let mut bridge = setup();
+        let data = PermissionlessCollectionData {
+            action_id: U128(1),
+            collection: account("nft"),
+            enabled: true,
+        };
+        let sig = group_sig(b"SetPermissionlessCollection", &data);
+        bridge.validate_set_permissionless_collection(data, sig);
+
+        assert!(bridge.is_permissionless_collection(account("nft")));
+        assert!(bridge.is_bridgeable(&account("nft")));
+        assert_eq!(bridge.get_whitelist_len(), 0);
+    }
+}
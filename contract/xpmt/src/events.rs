@@ -0,0 +1,70 @@
+use crate::*;
+use near_sdk::serde_json::json;
+
+/// NEP-245 `mt_mint` event.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtMint {
+    pub owner_id: AccountId,
+    pub token_ids: Vec<TokenId>,
+    pub amounts: Vec<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+impl MtMint {
+    pub fn emit(self) {
+        emit_event("mt_mint", self);
+    }
+}
+
+/// NEP-245 `mt_burn` event.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtBurn {
+    pub owner_id: AccountId,
+    pub token_ids: Vec<TokenId>,
+    pub amounts: Vec<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorized_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+impl MtBurn {
+    pub fn emit(self) {
+        emit_event("mt_burn", self);
+    }
+}
+
+/// NEP-245 `mt_transfer` event.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MtTransfer {
+    pub old_owner_id: AccountId,
+    pub new_owner_id: AccountId,
+    pub token_ids: Vec<TokenId>,
+    pub amounts: Vec<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorized_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+impl MtTransfer {
+    pub fn emit(self) {
+        emit_event("mt_transfer", self);
+    }
+}
+
+fn emit_event<T: Serialize>(event: &str, data: T) {
+    env::log_str(&format!(
+        "EVENT_JSON:{}",
+        json!({
+            "standard": "nep245",
+            "version": "1.0.0",
+            "event": event,
+            "data": [data],
+        })
+    ));
+}
@@ -0,0 +1,287 @@
+use near_contract_standards::non_fungible_token::metadata::{NFTContractMetadata, TokenMetadata};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LazyOption, LookupMap};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, near_bindgen, require, AccountId, BorshStorageKey, Gas, PanicOnDefault, Promise,
+    PromiseOrValue,
+};
+pub mod events;
+#[cfg(test)]
+mod test_utils;
+pub mod transfers;
+pub use crate::events::*;
+pub use crate::transfers::*;
+
+pub type TokenId = String;
+
+/// NEP-245 token. Copies of a token can be held by many accounts,
+/// so no owner is recorded.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Token {
+    pub token_id: TokenId,
+    pub owner_id: Option<AccountId>,
+}
+
+// Define the contract structure
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    owner_id: AccountId,
+    metadata: LazyOption<NFTContractMetadata>,
+    balances: LookupMap<(TokenId, AccountId), u128>,
+    supply: LookupMap<TokenId, u128>,
+    token_metadata: LookupMap<TokenId, TokenMetadata>,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Metadata,
+    Balances,
+    Supply,
+    TokenMetadata,
+}
+
+// Implement the contract structure
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn initialize(owner_id: AccountId, metadata: NFTContractMetadata) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        metadata.assert_valid();
+        Self {
+            owner_id,
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            balances: LookupMap::new(StorageKey::Balances),
+            supply: LookupMap::new(StorageKey::Supply),
+            token_metadata: LookupMap::new(StorageKey::TokenMetadata),
+        }
+    }
+
+    /// Mints `amount` copies of a token. The metadata is recorded by the
+    /// first mint of the token. The deposit left after paying for the
+    /// storage is refunded to `refund_id`, or to the caller if it is not
+    /// provided.
+    #[payable]
+    pub fn mt_mint(
+        &mut self,
+        token_id: TokenId,
+        token_owner_id: AccountId,
+        amount: U128,
+        token_metadata: Option<TokenMetadata>,
+        refund_id: Option<AccountId>,
+    ) {
+        self.assert_owner();
+        require!(amount.0 > 0, "Amount must be positive");
+
+        let initial_storage = env::storage_usage();
+        if let Some(token_metadata) = token_metadata {
+            if !self.token_metadata.contains_key(&token_id) {
+                self.token_metadata.insert(&token_id, &token_metadata);
+            }
+        }
+        let supply = self.supply.get(&token_id).unwrap_or(0);
+        self.supply.insert(
+            &token_id,
+            &supply
+                .checked_add(amount.0)
+                .unwrap_or_else(|| env::panic_str("Supply overflow")),
+        );
+        self.internal_deposit(&token_id, &token_owner_id, amount.0);
+        refund_storage_deposit(initial_storage, refund_id);
+
+        MtMint {
+            owner_id: token_owner_id,
+            token_ids: vec![token_id],
+            amounts: vec![amount],
+            memo: None,
+        }
+        .emit();
+    }
+
+    /// Burns `amount` copies of a token held by `from`.
+    pub fn mt_burn(&mut self, token_id: TokenId, from: AccountId, amount: U128) {
+        self.assert_owner();
+        require!(amount.0 > 0, "Amount must be positive");
+
+        self.internal_withdraw(&token_id, &from, amount.0);
+        let supply = self.supply.get(&token_id).unwrap_or(0) - amount.0;
+        self.supply.insert(&token_id, &supply);
+
+        MtBurn {
+            owner_id: from,
+            token_ids: vec![token_id],
+            amounts: vec![amount],
+            authorized_id: Some(env::predecessor_account_id()),
+            memo: None,
+        }
+        .emit();
+    }
+
+    /// Gets the number of copies of a token held by the account.
+    pub fn mt_balance_of(&self, account_id: AccountId, token_id: TokenId) -> U128 {
+        U128(self.balances.get(&(token_id, account_id)).unwrap_or(0))
+    }
+
+    /// Gets the number of copies of every token held by the account.
+    pub fn mt_batch_balance_of(&self, account_id: AccountId, token_ids: Vec<TokenId>) -> Vec<U128> {
+        token_ids
+            .into_iter()
+            .map(|token_id| self.mt_balance_of(account_id.clone(), token_id))
+            .collect()
+    }
+
+    /// Gets the number of copies of a token, None if it was never minted.
+    pub fn mt_supply(&self, token_id: TokenId) -> Option<U128> {
+        self.supply.get(&token_id).map(U128)
+    }
+
+    /// Gets the number of copies of every token.
+    pub fn mt_batch_supply(&self, token_ids: Vec<TokenId>) -> Vec<Option<U128>> {
+        token_ids
+            .into_iter()
+            .map(|token_id| self.mt_supply(token_id))
+            .collect()
+    }
+
+    /// Gets the tokens with the provided ids, None for the unknown ones.
+    pub fn mt_token(&self, token_ids: Vec<TokenId>) -> Vec<Option<Token>> {
+        token_ids
+            .into_iter()
+            .map(|token_id| {
+                self.supply.contains_key(&token_id).then_some(Token {
+                    token_id,
+                    owner_id: None,
+                })
+            })
+            .collect()
+    }
+
+    pub fn mt_metadata_contract(&self) -> NFTContractMetadata {
+        self.metadata.get().unwrap()
+    }
+
+    /// Gets the metadata of the tokens with the provided ids.
+    pub fn mt_metadata_token(&self, token_ids: Vec<TokenId>) -> Vec<Option<TokenMetadata>> {
+        token_ids
+            .into_iter()
+            .map(|token_id| self.token_metadata.get(&token_id))
+            .collect()
+    }
+}
+
+impl Contract {
+    fn assert_owner(&self) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Unauthorized");
+    }
+
+    pub(crate) fn internal_deposit(
+        &mut self,
+        token_id: &TokenId,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        let key = (token_id.clone(), account_id.clone());
+        let balance = self.balances.get(&key).unwrap_or(0);
+        self.balances.insert(
+            &key,
+            &balance
+                .checked_add(amount)
+                .unwrap_or_else(|| env::panic_str("Balance overflow")),
+        );
+    }
+
+    pub(crate) fn internal_withdraw(
+        &mut self,
+        token_id: &TokenId,
+        account_id: &AccountId,
+        amount: u128,
+    ) {
+        let key = (token_id.clone(), account_id.clone());
+        let balance = self.balances.get(&key).unwrap_or(0);
+        require!(balance >= amount, "Not enough balance");
+        if balance == amount {
+            self.balances.remove(&key);
+        } else {
+            self.balances.insert(&key, &(balance - amount));
+        }
+    }
+}
+
+/// Charges the storage used since `initial_storage` to the attached deposit
+/// and refunds the rest to `refund_id`, or to the caller if it is not provided.
+fn refund_storage_deposit(initial_storage: u64, refund_id: Option<AccountId>) {
+    let used = env::storage_usage().saturating_sub(initial_storage);
+    let cost = env::storage_byte_cost() * used as u128;
+    let attached = env::attached_deposit();
+    if attached < cost {
+        env::panic_str(&format!("Must attach {} yoctoNEAR to cover storage", cost));
+    }
+    if attached - cost > 1 {
+        Promise::new(refund_id.unwrap_or_else(env::predecessor_account_id))
+            .transfer(attached - cost);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn mint_adds_to_the_balance_and_supply() {
+        let mut contract = setup();
+        mint(&mut contract, "1", &account("alice"), 5);
+        mint(&mut contract, "1", &account("bob"), 2);
+
+        assert_eq!(
+            contract.mt_balance_of(account("alice"), "1".to_string()).0,
+            5
+        );
+        assert_eq!(contract.mt_supply("1".to_string()), Some(U128(7)));
+        assert_eq!(
+            contract.mt_metadata_token(vec!["1".to_string(), "2".to_string()])[0]
+                .as_ref()
+                .unwrap()
+                .title,
+            token_metadata().title
+        );
+        assert!(contract.mt_token(vec!["2".to_string()])[0].is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_owner_can_mint() {
+        let mut contract = setup();
+
+        set_context(&account("alice"), ONE_NEAR);
+        contract.mt_mint("1".to_string(), account("alice"), U128(5), None, None);
+    }
+
+    #[test]
+    fn burn_removes_the_copies() {
+        let mut contract = setup();
+        mint(&mut contract, "1", &account("alice"), 5);
+
+        set_context(&bridge(), 0);
+        contract.mt_burn("1".to_string(), account("alice"), U128(5));
+
+        assert_eq!(
+            contract.mt_balance_of(account("alice"), "1".to_string()).0,
+            0
+        );
+        assert_eq!(contract.mt_supply("1".to_string()), Some(U128(0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough balance")]
+    fn burn_is_limited_to_the_balance() {
+        let mut contract = setup();
+        mint(&mut contract, "1", &account("alice"), 5);
+
+        set_context(&bridge(), 0);
+        contract.mt_burn("1".to_string(), account("alice"), U128(6));
+    }
+}
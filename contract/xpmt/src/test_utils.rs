@@ -0,0 +1,74 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::metadata::NFT_METADATA_SPEC;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::testing_env;
+
+pub use near_sdk::ONE_NEAR;
+
+pub fn collection_id() -> AccountId {
+    "xpmt.near".parse().unwrap()
+}
+
+pub fn bridge() -> AccountId {
+    "bridge.near".parse().unwrap()
+}
+
+pub fn account(name: &str) -> AccountId {
+    format!("{}.near", name).parse().unwrap()
+}
+
+/// Sets up a call to the collection from `predecessor`.
+pub fn set_context(predecessor: &AccountId, deposit: u128) {
+    testing_env!(VMContextBuilder::new()
+        .current_account_id(collection_id())
+        .predecessor_account_id(predecessor.clone())
+        .attached_deposit(deposit)
+        .prepaid_gas(Gas(300_000_000_000_000))
+        .build());
+}
+
+pub fn token_metadata() -> TokenMetadata {
+    TokenMetadata {
+        title: Some("Token".to_string()),
+        description: None,
+        media: None,
+        media_hash: None,
+        copies: None,
+        issued_at: None,
+        expires_at: None,
+        starts_at: None,
+        updated_at: None,
+        extra: None,
+        reference: None,
+        reference_hash: None,
+    }
+}
+
+/// Initializes a collection owned by the bridge.
+pub fn setup() -> Contract {
+    set_context(&bridge(), 0);
+    Contract::initialize(
+        bridge(),
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Wrapped".to_string(),
+            symbol: "WRAP".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        },
+    )
+}
+
+/// Mints `amount` copies of `token_id` to `owner_id` from the bridge.
+pub fn mint(contract: &mut Contract, token_id: &str, owner_id: &AccountId, amount: u128) {
+    set_context(&bridge(), ONE_NEAR);
+    contract.mt_mint(
+        token_id.to_string(),
+        owner_id.clone(),
+        U128(amount),
+        Some(token_metadata()),
+        None,
+    );
+}
@@ -0,0 +1,328 @@
+use crate::*;
+use near_sdk::{ext_contract, PromiseResult};
+
+/// Gas kept for `mt_resolve_transfer` and the transfer itself,
+/// the rest of the prepaid gas goes to `mt_on_transfer`.
+const GAS_FOR_MT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000);
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(10_000_000_000_000);
+
+#[ext_contract(mt_receiver)]
+pub trait MultiTokenReceiver {
+    fn mt_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>>;
+}
+
+/// NEP-245 transfers. Approvals are not supported, only the holder of
+/// the tokens can transfer them. Transfers take exactly 1 yoctoNEAR like
+/// in the standard, so the storage of the balance of a new receiver is
+/// paid by the collection. The balance of an account is removed once it
+/// holds no copies, which frees that storage again.
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn mt_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+    ) {
+        self.mt_batch_transfer(
+            receiver_id,
+            vec![token_id],
+            vec![amount],
+            approval.map(|a| vec![Some(a)]),
+            memo,
+        )
+    }
+
+    #[payable]
+    pub fn mt_batch_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+    ) {
+        let sender_id = env::predecessor_account_id();
+        self.internal_batch_transfer(
+            &sender_id,
+            &receiver_id,
+            &token_ids,
+            &amounts,
+            approvals,
+            memo,
+        );
+    }
+
+    #[payable]
+    pub fn mt_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        self.mt_batch_transfer_call(
+            receiver_id,
+            vec![token_id],
+            vec![amount],
+            approval.map(|a| vec![Some(a)]),
+            memo,
+            msg,
+        )
+    }
+
+    /// Transfers the tokens and calls `mt_on_transfer` on the receiver,
+    /// which returns the amounts it didn't use. These are returned to the
+    /// sender by `mt_resolve_transfer`.
+    #[payable]
+    pub fn mt_batch_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        require!(
+            env::prepaid_gas() > GAS_FOR_MT_TRANSFER_CALL,
+            "Not enough gas"
+        );
+        let sender_id = env::predecessor_account_id();
+        self.internal_batch_transfer(
+            &sender_id,
+            &receiver_id,
+            &token_ids,
+            &amounts,
+            approvals,
+            memo,
+        );
+
+        mt_receiver::ext(receiver_id.clone())
+            .with_static_gas(env::prepaid_gas() - GAS_FOR_MT_TRANSFER_CALL)
+            .mt_on_transfer(
+                sender_id.clone(),
+                vec![sender_id.clone(); token_ids.len()],
+                token_ids.clone(),
+                amounts.clone(),
+                msg,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .mt_resolve_transfer(sender_id, receiver_id, token_ids, amounts),
+            )
+            .into()
+    }
+
+    /// Returns the amounts the receiver didn't use to the sender, as far
+    /// as the receiver still holds them. Returns the amounts that were used.
+    /// Like in the transfer, the storage of the balance of the sender is
+    /// paid by the collection.
+    #[private]
+    pub fn mt_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        token_ids: Vec<TokenId>,
+        amounts: Vec<U128>,
+    ) -> Vec<U128> {
+        let unused: Vec<U128> = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<Vec<U128>>(&value)
+                    .ok()
+                    .filter(|unused| unused.len() == amounts.len())
+                    .unwrap_or_else(|| amounts.clone())
+            }
+            _ => amounts.clone(),
+        };
+
+        let mut refunded_ids = Vec::new();
+        let mut refunded_amounts = Vec::new();
+        let used = token_ids
+            .iter()
+            .zip(amounts.iter().zip(unused))
+            .map(|(token_id, (amount, unused))| {
+                let balance = self.mt_balance_of(receiver_id.clone(), token_id.clone()).0;
+                let refund = unused.0.min(amount.0).min(balance);
+                if refund > 0 {
+                    self.internal_withdraw(token_id, &receiver_id, refund);
+                    self.internal_deposit(token_id, &sender_id, refund);
+                    refunded_ids.push(token_id.clone());
+                    refunded_amounts.push(U128(refund));
+                }
+                U128(amount.0 - refund)
+            })
+            .collect();
+
+        if !refunded_ids.is_empty() {
+            MtTransfer {
+                old_owner_id: receiver_id,
+                new_owner_id: sender_id,
+                token_ids: refunded_ids,
+                amounts: refunded_amounts,
+                authorized_id: None,
+                memo: Some("refund".to_string()),
+            }
+            .emit();
+        }
+        used
+    }
+}
+
+impl Contract {
+    fn internal_batch_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_ids: &[TokenId],
+        amounts: &[U128],
+        approvals: Option<Vec<Option<(AccountId, u64)>>>,
+        memo: Option<String>,
+    ) {
+        require!(
+            env::attached_deposit() == 1,
+            "Requires attached deposit of exactly 1 yoctoNEAR"
+        );
+        require!(approvals.is_none(), "Approvals not supported");
+        require!(sender_id != receiver_id, "Sender and receiver must differ");
+        require!(
+            !token_ids.is_empty() && token_ids.len() == amounts.len(),
+            "Invalid token ids or amounts"
+        );
+
+        for (token_id, amount) in token_ids.iter().zip(amounts) {
+            require!(amount.0 > 0, "Amount must be positive");
+            self.internal_withdraw(token_id, sender_id, amount.0);
+            self.internal_deposit(token_id, receiver_id, amount.0);
+        }
+
+        MtTransfer {
+            old_owner_id: sender_id.clone(),
+            new_owner_id: receiver_id.clone(),
+            token_ids: token_ids.to_vec(),
+            amounts: amounts.to_vec(),
+            authorized_id: None,
+            memo,
+        }
+        .emit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    fn setup_balance() -> Contract {
+        let mut contract = setup();
+        mint(&mut contract, "1", &account("alice"), 5);
+        contract
+    }
+
+    fn balance(contract: &Contract, name: &str) -> u128 {
+        contract.mt_balance_of(account(name), "1".to_string()).0
+    }
+
+    #[test]
+    fn transfer_moves_the_copies() {
+        let mut contract = setup_balance();
+
+        set_context(&account("alice"), 1);
+        contract.mt_transfer(account("bob"), "1".to_string(), U128(2), None, None);
+
+        assert_eq!(balance(&contract, "alice"), 3);
+        assert_eq!(balance(&contract, "bob"), 2);
+    }
+
+    #[test]
+    fn new_receivers_need_only_one_yocto() {
+        let mut contract = setup_balance();
+
+        set_context(&account("alice"), 1);
+        let initial_storage = env::storage_usage();
+        contract.mt_transfer(account("bob"), "1".to_string(), U128(5), None, None);
+
+        assert_eq!(balance(&contract, "bob"), 5);
+        assert!(get_created_receipts().is_empty());
+        // The balance of alice is removed with her last copy.
+        assert!(env::storage_usage() <= initial_storage);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn transfer_requires_one_yocto() {
+        let mut contract = setup_balance();
+
+        set_context(&account("alice"), ONE_NEAR);
+        contract.mt_transfer(account("bob"), "1".to_string(), U128(2), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Approvals not supported")]
+    fn transfer_rejects_approvals() {
+        let mut contract = setup_balance();
+
+        set_context(&account("bridge"), 1);
+        contract.mt_transfer(
+            account("bob"),
+            "1".to_string(),
+            U128(2),
+            Some((account("alice"), 0)),
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough balance")]
+    fn transfer_is_limited_to_the_balance() {
+        let mut contract = setup_balance();
+
+        set_context(&account("alice"), 1);
+        contract.mt_transfer(account("bob"), "1".to_string(), U128(6), None, None);
+    }
+
+    #[test]
+    fn unused_copies_are_returned_to_the_sender() {
+        let mut contract = setup_balance();
+        set_context(&account("alice"), 1);
+        contract.mt_transfer(account("bob"), "1".to_string(), U128(4), None, None);
+
+        testing_env!(
+            near_sdk::test_utils::VMContextBuilder::new()
+                .current_account_id(collection_id())
+                .predecessor_account_id(collection_id())
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&vec![U128(3)]).unwrap()
+            )],
+        );
+        let used = contract.mt_resolve_transfer(
+            account("alice"),
+            account("bob"),
+            vec!["1".to_string()],
+            vec![U128(4)],
+        );
+
+        assert_eq!(used, vec![U128(1)]);
+        assert_eq!(balance(&contract, "alice"), 4);
+        assert_eq!(balance(&contract, "bob"), 1);
+    }
+}
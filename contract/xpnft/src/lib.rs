@@ -4,8 +4,18 @@ use near_contract_standards::non_fungible_token::metadata::{
 };
 use near_contract_standards::non_fungible_token::{NonFungibleToken, Token, TokenId};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LazyOption;
-use near_sdk::{env, near_bindgen, AccountId, BorshStorageKey, Promise, PromiseOrValue, PanicOnDefault};
+use near_sdk::collections::{LazyOption, LookupMap};
+use near_sdk::serde_json::json;
+use near_sdk::{
+    env, near_bindgen, require, AccountId, BorshStorageKey, PanicOnDefault, Promise, PromiseOrValue,
+};
+use std::collections::HashMap;
+pub mod origin;
+pub mod royalty;
+#[cfg(test)]
+mod test_utils;
+pub use crate::origin::*;
+pub use crate::royalty::*;
 
 // Define the contract structure
 #[near_bindgen]
@@ -13,6 +23,16 @@ use near_sdk::{env, near_bindgen, AccountId, BorshStorageKey, Promise, PromiseOr
 pub struct Contract {
     tokens: NonFungibleToken,
     metadata: LazyOption<NFTContractMetadata>,
+    royalties: LookupMap<TokenId, HashMap<AccountId, u32>>,
+    origins: LookupMap<TokenId, TokenOrigin>,
+}
+
+/// State of the contract before royalties and origins were stored,
+/// read by `migrate`.
+#[derive(BorshDeserialize)]
+struct OldContract {
+    tokens: NonFungibleToken,
+    metadata: LazyOption<NFTContractMetadata>,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -22,6 +42,8 @@ enum StorageKey {
     TokenMetadata,
     Enumeration,
     Approval,
+    Royalty,
+    Origin,
 }
 
 // Implement the contract structure
@@ -40,27 +62,63 @@ impl Contract {
                 Some(StorageKey::Approval),
             ),
             metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            royalties: LookupMap::new(StorageKey::Royalty),
+            origins: LookupMap::new(StorageKey::Origin),
         }
     }
 
+    /// Migrates the state of a contract deployed before royalties and
+    /// origins were stored. The tokens and the metadata are kept.
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        require!(
+            env::predecessor_account_id() == env::current_account_id(),
+            "Unauthorized"
+        );
+        let old: OldContract =
+            env::state_read().unwrap_or_else(|| env::panic_str("No state to migrate"));
+        Self {
+            tokens: old.tokens,
+            metadata: old.metadata,
+            royalties: LookupMap::new(StorageKey::Royalty),
+            origins: LookupMap::new(StorageKey::Origin),
+        }
+    }
+
+    /// Mints a new token with an optional royalty split in basis points
+    /// and the provenance of the token it wraps. The deposit left after
+    /// paying for the storage is refunded to `refund_id`, or to the caller
+    /// if it is not provided.
     #[payable]
     pub fn nft_mint(
         &mut self,
         token_id: TokenId,
         token_owner_id: AccountId,
         token_metadata: TokenMetadata,
+        royalty: Option<HashMap<AccountId, u32>>,
+        origin: Option<TokenOrigin>,
+        refund_id: Option<AccountId>,
     ) -> Token {
         assert_eq!(
             env::predecessor_account_id(),
             self.tokens.owner_id,
             "Unauthorized"
         );
+        let initial_storage = env::storage_usage();
+        if let Some(royalty) = royalty.filter(|r| !r.is_empty()) {
+            assert_valid_royalty(&royalty);
+            self.royalties.insert(&token_id, &royalty);
+        }
+        if let Some(origin) = origin {
+            self.origins.insert(&token_id, &origin);
+        }
         let token = self.tokens.internal_mint_with_refund(
             token_id,
             token_owner_id,
             Some(token_metadata),
-            Some(env::predecessor_account_id()),
+            None,
         );
+        refund_storage_deposit(initial_storage, refund_id);
 
         NftMint {
             owner_id: &token.owner_id,
@@ -72,6 +130,43 @@ impl Contract {
         token
     }
 
+    /// Replaces the metadata of a token and emits an `nft_metadata_update`
+    /// event. The deposit left after paying for additional storage is
+    /// refunded to `refund_id`, or to the caller if it is not provided.
+    #[payable]
+    pub fn nft_update_metadata(
+        &mut self,
+        token_id: TokenId,
+        token_metadata: TokenMetadata,
+        refund_id: Option<AccountId>,
+    ) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.tokens.owner_id,
+            "Unauthorized"
+        );
+        require!(
+            self.tokens.owner_by_id.contains_key(&token_id),
+            "unknown token id"
+        );
+
+        let initial_storage = env::storage_usage();
+        if let Some(token_metadata_by_id) = &mut self.tokens.token_metadata_by_id {
+            token_metadata_by_id.insert(&token_id, &token_metadata);
+        }
+        refund_storage_deposit(initial_storage, refund_id);
+
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            json!({
+                "standard": "nep171",
+                "version": "1.1.0",
+                "event": "nft_metadata_update",
+                "data": [{ "token_ids": [token_id] }],
+            })
+        ));
+    }
+
     #[payable]
     pub fn nft_burn(&mut self, token_id: TokenId, from: AccountId) -> Promise {
         assert_eq!(
@@ -122,6 +217,8 @@ impl Contract {
         if let Some(token_metadata_by_id) = &mut self.tokens.token_metadata_by_id {
             token_metadata_by_id.remove(&token_id);
         }
+        self.royalties.remove(&token_id);
+        self.origins.remove(&token_id);
 
         NftBurn {
             owner_id: &from,
@@ -138,6 +235,21 @@ impl Contract {
     }
 }
 
+/// Charges the storage used since `initial_storage` to the attached deposit
+/// and refunds the rest to `refund_id`, or to the caller if it is not provided.
+fn refund_storage_deposit(initial_storage: u64, refund_id: Option<AccountId>) {
+    let used = env::storage_usage().saturating_sub(initial_storage);
+    let cost = env::storage_byte_cost() * used as u128;
+    let attached = env::attached_deposit();
+    if attached < cost {
+        env::panic_str(&format!("Must attach {} yoctoNEAR to cover storage", cost));
+    }
+    if attached - cost > 1 {
+        Promise::new(refund_id.unwrap_or_else(env::predecessor_account_id))
+            .transfer(attached - cost);
+    }
+}
+
 near_contract_standards::impl_non_fungible_token_core!(Contract, tokens);
 near_contract_standards::impl_non_fungible_token_approval!(Contract, tokens);
 near_contract_standards::impl_non_fungible_token_enumeration!(Contract, tokens);
@@ -148,3 +260,87 @@ impl NonFungibleTokenMetadataProvider for Contract {
         self.metadata.get().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    #[derive(BorshSerialize)]
+    struct BaselineContract {
+        tokens: NonFungibleToken,
+        metadata: LazyOption<NFTContractMetadata>,
+    }
+
+    #[test]
+    fn migrate_keeps_the_tokens_and_the_metadata() {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id(collection_id())
+            .predecessor_account_id(collection_id())
+            .attached_deposit(ONE_NEAR)
+            .build());
+        let mut old = BaselineContract {
+            tokens: NonFungibleToken::new(
+                StorageKey::NonFungibleToken,
+                bridge(),
+                Some(StorageKey::TokenMetadata),
+                Some(StorageKey::Enumeration),
+                Some(StorageKey::Approval),
+            ),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&collection_metadata())),
+        };
+        let owner = account("alice");
+        old.tokens
+            .internal_mint("1".to_string(), owner.clone(), Some(token_metadata()));
+        env::state_write(&old);
+
+        let mut contract = Contract::migrate();
+
+        assert_eq!(contract.nft_token("1".to_string()).unwrap().owner_id, owner);
+        assert_eq!(contract.nft_metadata().symbol, "WRAP");
+        assert!(contract.nft_royalty("1".to_string()).is_empty());
+        assert!(contract.nft_origin("1".to_string()).is_none());
+
+        let royalty = HashMap::from([(owner, 500)]);
+        mint(&mut contract, "2", Some(royalty.clone()), None);
+        assert_eq!(contract.nft_royalty("2".to_string()), royalty);
+    }
+
+    #[test]
+    fn bridge_can_update_the_metadata() {
+        let mut contract = setup();
+        mint(&mut contract, "1", None, None);
+
+        set_context(&bridge(), ONE_NEAR);
+        let renamed = TokenMetadata {
+            title: Some("Renamed".to_string()),
+            ..token_metadata()
+        };
+        contract.nft_update_metadata("1".to_string(), renamed, None);
+
+        let token = contract.nft_token("1".to_string()).unwrap();
+        assert_eq!(token.metadata.unwrap().title.as_deref(), Some("Renamed"));
+        assert!(near_sdk::test_utils::get_logs()[0].contains("nft_metadata_update"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn only_the_bridge_can_update_the_metadata() {
+        let mut contract = setup();
+        mint(&mut contract, "1", None, None);
+
+        set_context(&account("alice"), ONE_NEAR);
+        contract.nft_update_metadata("1".to_string(), token_metadata(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown token id")]
+    fn unknown_tokens_cannot_be_updated() {
+        let mut contract = setup();
+
+        set_context(&bridge(), ONE_NEAR);
+        contract.nft_update_metadata("1".to_string(), token_metadata(), None);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::*;
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+
+/// Where a wrapped token comes from: the token on the origin chain
+/// and the bridge action it was minted by.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenOrigin {
+    pub chain_nonce: u8,
+    pub contract: String,
+    pub token_id: String,
+    pub action_id: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Gets the provenance of a token. Tokens minted
+    /// without provenance have none.
+    pub fn nft_origin(&self, token_id: TokenId) -> Option<TokenOrigin> {
+        self.origins.get(&token_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn origin() -> TokenOrigin {
+        TokenOrigin {
+            chain_nonce: 4,
+            contract: "0xorigin".to_string(),
+            token_id: "77".to_string(),
+            action_id: U128(9),
+        }
+    }
+
+    #[test]
+    fn minted_tokens_keep_their_origin() {
+        let mut contract = setup();
+        mint(&mut contract, "1", None, Some(origin()));
+        mint(&mut contract, "2", None, None);
+
+        let stored = contract.nft_origin("1".to_string()).unwrap();
+        assert_eq!(stored.chain_nonce, 4);
+        assert_eq!(stored.contract, "0xorigin");
+        assert_eq!(stored.token_id, "77");
+        assert_eq!(stored.action_id.0, 9);
+        assert!(contract.nft_origin("2".to_string()).is_none());
+    }
+
+    #[test]
+    fn burnt_tokens_drop_their_origin() {
+        let mut contract = setup();
+        mint(&mut contract, "1", None, Some(origin()));
+
+        set_context(&bridge(), 0);
+        contract.nft_burn("1".to_string(), account("alice"));
+
+        assert!(contract.nft_origin("1".to_string()).is_none());
+    }
+}
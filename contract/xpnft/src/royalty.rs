@@ -0,0 +1,193 @@
+use crate::*;
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenCore;
+use near_sdk::assert_one_yocto;
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Royalties are expressed in basis points of the sale price.
+pub const ROYALTY_BASIS_POINTS: u32 = 10_000;
+/// Maximum number of royalty accounts of a token.
+pub const MAX_ROYALTY_ACCOUNTS: usize = 10;
+
+/// NEP-199 payout: the amount owed to every account for a sale.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Gets the royalty split of a token in basis points.
+    pub fn nft_royalty(&self, token_id: TokenId) -> HashMap<AccountId, u32> {
+        self.royalties.get(&token_id).unwrap_or_default()
+    }
+
+    /// Computes how `balance` is split between the royalty accounts
+    /// and the owner of the token.
+    pub fn nft_payout(
+        &self,
+        token_id: TokenId,
+        balance: U128,
+        max_len_payout: Option<u32>,
+    ) -> Payout {
+        let owner_id = self
+            .tokens
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("unknown token id"));
+        self.internal_payout(&token_id, owner_id, balance.0, max_len_payout)
+    }
+
+    /// Transfers the token and returns the payout of the sale to the
+    /// previous owner, as `nft_payout`.
+    #[payable]
+    pub fn nft_transfer_payout(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        balance: U128,
+        max_len_payout: Option<u32>,
+    ) -> Payout {
+        assert_one_yocto();
+        let owner_id = self
+            .tokens
+            .owner_by_id
+            .get(&token_id)
+            .unwrap_or_else(|| env::panic_str("unknown token id"));
+        let payout = self.internal_payout(&token_id, owner_id, balance.0, max_len_payout);
+        self.tokens
+            .nft_transfer(receiver_id, token_id, approval_id, memo);
+        payout
+    }
+}
+
+impl Contract {
+    fn internal_payout(
+        &self,
+        token_id: &TokenId,
+        owner_id: AccountId,
+        balance: u128,
+        max_len_payout: Option<u32>,
+    ) -> Payout {
+        let royalty = self.royalties.get(token_id).unwrap_or_default();
+        require!(
+            max_len_payout.map_or(true, |max| royalty.len() < max as usize),
+            "Too many royalty accounts for max_len_payout"
+        );
+
+        let mut payout = HashMap::new();
+        let mut paid = 0;
+        for (account_id, bps) in royalty {
+            let amount = balance * bps as u128 / ROYALTY_BASIS_POINTS as u128;
+            paid += amount;
+            payout.insert(account_id, U128(amount));
+        }
+        let owner_share = payout.get(&owner_id).map(|a| a.0).unwrap_or(0);
+        payout.insert(owner_id, U128(balance - paid + owner_share));
+
+        Payout { payout }
+    }
+}
+
+/// Checks that a royalty split can be paid out of a sale.
+pub fn assert_valid_royalty(royalty: &HashMap<AccountId, u32>) {
+    require!(
+        royalty.len() <= MAX_ROYALTY_ACCOUNTS,
+        "Too many royalty accounts"
+    );
+    require!(
+        royalty.values().map(|bps| *bps as u64).sum::<u64>() <= ROYALTY_BASIS_POINTS as u64,
+        "Royalties exceed 100%"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn royalty() -> HashMap<AccountId, u32> {
+        HashMap::from([(account("artist"), 500), (account("studio"), 250)])
+    }
+
+    #[test]
+    fn payout_splits_the_balance() {
+        let mut contract = setup();
+        mint(&mut contract, "1", Some(royalty()), None);
+
+        let payout = contract
+            .nft_payout("1".to_string(), U128(10_000), Some(3))
+            .payout;
+        assert_eq!(payout.len(), 3);
+        assert_eq!(payout[&account("artist")].0, 500);
+        assert_eq!(payout[&account("studio")].0, 250);
+        assert_eq!(payout[&account("alice")].0, 9_250);
+    }
+
+    #[test]
+    fn owner_royalty_is_added_to_the_owner_share() {
+        let mut contract = setup();
+        mint(
+            &mut contract,
+            "1",
+            Some(HashMap::from([(account("alice"), 1_000)])),
+            None,
+        );
+
+        let payout = contract.nft_payout("1".to_string(), U128(100), None).payout;
+        assert_eq!(payout.len(), 1);
+        assert_eq!(payout[&account("alice")].0, 100);
+    }
+
+    #[test]
+    fn transfer_payout_moves_the_token() {
+        let mut contract = setup();
+        mint(&mut contract, "1", Some(royalty()), None);
+
+        set_context(&account("alice"), 1);
+        let payout = contract
+            .nft_transfer_payout(
+                account("bob"),
+                "1".to_string(),
+                None,
+                None,
+                U128(10_000),
+                None,
+            )
+            .payout;
+
+        assert_eq!(payout[&account("alice")].0, 9_250);
+        assert_eq!(
+            contract.nft_token("1".to_string()).unwrap().owner_id,
+            account("bob")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many royalty accounts for max_len_payout")]
+    fn payout_respects_max_len_payout() {
+        let mut contract = setup();
+        mint(&mut contract, "1", Some(royalty()), None);
+
+        contract.nft_payout("1".to_string(), U128(10_000), Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Royalties exceed 100%")]
+    fn royalties_cannot_exceed_the_price() {
+        let mut contract = setup();
+        mint(
+            &mut contract,
+            "1",
+            Some(HashMap::from([
+                (account("artist"), 6_000),
+                (account("studio"), 5_000),
+            ])),
+            None,
+        );
+    }
+}
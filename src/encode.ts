@@ -6,6 +6,8 @@ export class WhitelistData {
     actionId: BN;
     @field({ type: "String" })
     tokenContract: string;
+    @field({ type: option("String") })
+    notes?: string;
 
     constructor(data: WhitelistData) {
         Object.assign(this, data);
@@ -75,9 +77,30 @@ export class TokenMetadataData {
     }
 }
 
+// Entries must be sorted by account id, as borsh serializes the
+// royalty HashMap of the contract with sorted keys.
+export class RoyaltyShareData {
+    @field({ type: "String" })
+    accountId: string;
+    @field({ type: "u32" })
+    bps: number;
+
+    constructor(data: RoyaltyShareData) {
+        Object.assign(this, data);
+    }
+}
+
 export class TransferNftData {
     @field({ type: "u128" })
     actionId: BN;
+    @field({ type: "u8" })
+    chainNonce: number;
+    @field({ type: "String" })
+    txHash: string;
+    @field({ type: "String" })
+    originContract: string;
+    @field({ type: "String" })
+    originTokenId: string;
     @field({ type: "String" })
     mintWith: string;
     @field({ type: "String" })
@@ -86,8 +109,29 @@ export class TransferNftData {
     tokenOwnerId: string;
     @field({ type: TokenMetadataData })
     tokenMetadata: TokenMetadataData;
+    @field({ type: option(vec(RoyaltyShareData)) })
+    royalty: RoyaltyShareData[] | undefined;
 
     constructor(data: TransferNftData) {
         Object.assign(this, data);
     }
 }
+
+export class UnfreezeNftData {
+    @field({ type: "u128" })
+    actionId: BN;
+    @field({ type: "u8" })
+    chainNonce: number;
+    @field({ type: "String" })
+    txHash: string;
+    @field({ type: "String" })
+    tokenContract: string;
+    @field({ type: "String" })
+    tokenId: string;
+    @field({ type: "String" })
+    receiverId: string;
+
+    constructor(data: UnfreezeNftData) {
+        Object.assign(this, data);
+    }
+}
@@ -3,6 +3,7 @@ import { Account, Contract } from "near-api-js";
 import {
     PauseData,
     TransferNftData,
+    UnfreezeNftData,
     UnpauseData,
     WhitelistData,
 } from "../encode";
@@ -46,10 +47,15 @@ interface TransferNftParam {
     args: {
         data: {
             action_id: string;
+            chain_nonce: number;
+            tx_hash: string;
+            origin_contract: string;
+            origin_token_id: string;
             mint_with: string;
             token_id: string;
             owner_id: string;
             token_metadata: TokenMetadata;
+            royalty: Record<string, number> | undefined;
         };
         sig_data: string;
     };
@@ -79,7 +85,18 @@ interface FreezeNftParam {
 }
 
 interface UnfreezeNftParam {
-    args: {};
+    args: {
+        data: {
+            action_id: string;
+            chain_nonce: number;
+            tx_hash: string;
+            token_contract: string;
+            token_id: string;
+            receiver_id: string;
+        };
+        sig_data: string;
+    };
+    gas: number;
 }
 
 interface BridgeContract extends Contract {
@@ -188,6 +205,10 @@ export class BridgeHelper {
             args: {
                 data: {
                     action_id: data.actionId.toString(),
+                    chain_nonce: data.chainNonce,
+                    tx_hash: data.txHash,
+                    origin_contract: data.originContract,
+                    origin_token_id: data.originTokenId,
                     mint_with: data.mintWith,
                     token_id: data.tokenId,
                     owner_id: data.tokenOwnerId,
@@ -195,7 +216,9 @@ export class BridgeHelper {
                         title: data.tokenMetadata.title,
                         description: data.tokenMetadata.description,
                         media: data.tokenMetadata.media,
-                        media_hash: data.tokenMetadata.mediaHash,
+                        media_hash: data.tokenMetadata.mediaHash
+                            ? Buffer.from(data.tokenMetadata.mediaHash).toString("base64")
+                            : undefined,
                         copies: data.tokenMetadata.copies,
                         issued_at: data.tokenMetadata.issuedAt,
                         expires_at: data.tokenMetadata.expiresAt,
@@ -203,8 +226,13 @@ export class BridgeHelper {
                         updated_at: data.tokenMetadata.updatedAt,
                         extra: data.tokenMetadata.extra,
                         reference: data.tokenMetadata.reference,
-                        reference_hash: data.tokenMetadata.referenceHash,
+                        reference_hash: data.tokenMetadata.referenceHash
+                            ? Buffer.from(data.tokenMetadata.referenceHash).toString("base64")
+                            : undefined,
                     },
+                    royalty: data.royalty
+                        ? Object.fromEntries(data.royalty.map((r) => [r.accountId, r.bps]))
+                        : undefined,
                 },
                 sig_data: Buffer.from(signature).toString("base64"),
             },
@@ -250,4 +278,21 @@ export class BridgeHelper {
             },
         });
     }
+
+    async unfreezeNft(data: UnfreezeNftData, signature: Uint8Array) {
+        return await this.contract.validate_unfreeze_nft({
+            args: {
+                data: {
+                    action_id: data.actionId.toString(),
+                    chain_nonce: data.chainNonce,
+                    tx_hash: data.txHash,
+                    token_contract: data.tokenContract,
+                    token_id: data.tokenId,
+                    receiver_id: data.receiverId,
+                },
+                sig_data: Buffer.from(signature).toString("base64"),
+            },
+            gas: 300_000_000_000_000,
+        });
+    }
 }
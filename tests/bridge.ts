@@ -188,6 +188,10 @@ describe("bridge", async () => {
         const actionId = new BN(3);
         const data = new TransferNftData({
             actionId,
+            chainNonce: 7,
+            txHash: "0x0",
+            originContract: "0x0",
+            originTokenId: "0",
             mintWith: xpnftAcc.accountId,
             tokenId: "0",
             tokenOwnerId: nftOwnerAcc.accountId,
@@ -205,6 +209,7 @@ describe("bridge", async () => {
                 reference: null,
                 referenceHash: null,
             }),
+            royalty: undefined,
         });
         const message = serialize(data);
         const msgHash = createHash("SHA256").update(message).digest();